@@ -0,0 +1,128 @@
+//! Measures the per-frame cost of refreshing an index when nothing changed, with the indexed
+//! component spread over different numbers of archetypes, and what indexing only some of
+//! those archetypes saves.
+
+use bevy::ecs::system::SystemState;
+use bevy::prelude::*;
+use bevy_mod_index::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const ENTITIES: u32 = 100_000;
+
+#[derive(Component)]
+struct Cell(u32);
+
+#[derive(Component)]
+struct Marker<const N: usize>;
+
+struct CellIndex;
+
+impl IndexInfo for CellIndex {
+    type Component = Cell;
+    type Value = u32;
+    type Storage = HashmapStorage<Self>;
+    type ValueParam = ();
+
+    fn value(c: &Cell, _: &()) -> u32 {
+        c.0 % 1000
+    }
+}
+
+// Both leave out the entities with `Marker<0>`, but only the filter skips their archetypes.
+struct SkippedCellIndex;
+
+impl IndexInfo for SkippedCellIndex {
+    type Component = Cell;
+    type Value = u32;
+    type Storage = SkipDisabledStorage<Self, Marker<0>>;
+    type ValueParam = ();
+
+    fn value(c: &Cell, _: &()) -> u32 {
+        c.0 % 1000
+    }
+}
+
+struct FilteredCellIndex;
+
+impl IndexInfo for FilteredCellIndex {
+    type Component = Cell;
+    type Value = u32;
+    type Storage = FilteredStorage<Self, Without<Marker<0>>>;
+    type ValueParam = ();
+
+    fn value(c: &Cell, _: &()) -> u32 {
+        c.0 % 1000
+    }
+}
+
+// Gives the entity one of 64 combinations of markers, each of which is its own archetype.
+fn add_markers(entity: &mut EntityWorldMut, bits: u32) {
+    if bits & 1 != 0 {
+        entity.insert(Marker::<0>);
+    }
+    if bits & 2 != 0 {
+        entity.insert(Marker::<1>);
+    }
+    if bits & 4 != 0 {
+        entity.insert(Marker::<2>);
+    }
+    if bits & 8 != 0 {
+        entity.insert(Marker::<3>);
+    }
+    if bits & 16 != 0 {
+        entity.insert(Marker::<4>);
+    }
+    if bits & 32 != 0 {
+        entity.insert(Marker::<5>);
+    }
+}
+
+fn unchanged_refresh(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unchanged_refresh");
+    for archetypes in [1, 8, 64] {
+        let mut app = App::new();
+        app.add_plugins(IndexPlugin::<CellIndex>::new());
+        for i in 0..ENTITIES {
+            add_markers(&mut app.world.spawn(Cell(i)), i % archetypes);
+        }
+        app.update();
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(archetypes),
+            &archetypes,
+            |b, _| {
+                b.iter(|| {
+                    app.update();
+                    black_box(app.world.resource::<HashmapStorage<CellIndex>>());
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn filtered_refresh(c: &mut Criterion) {
+    let mut group = c.benchmark_group("filtered_refresh");
+    let mut app = App::new();
+    app.add_plugins((
+        IndexPlugin::<SkippedCellIndex>::new(),
+        IndexPlugin::<FilteredCellIndex>::new(),
+    ));
+    for i in 0..ENTITIES {
+        add_markers(&mut app.world.spawn(Cell(i)), i % 64);
+    }
+    app.update();
+
+    let mut skipped = SystemState::<Index<SkippedCellIndex>>::new(&mut app.world);
+    group.bench_function("skip_disabled", |b| {
+        b.iter(|| black_box(skipped.get_mut(&mut app.world).lookup(&0).len()))
+    });
+    let mut filtered = SystemState::<Index<FilteredCellIndex>>::new(&mut app.world);
+    group.bench_function("filtered", |b| {
+        b.iter(|| black_box(filtered.get_mut(&mut app.world).lookup(&0).len()))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, unchanged_refresh, filtered_refresh);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+//! Compares building an index over many entities that share a few large values, with and
+//! without interning the values.
+
+use bevy::ecs::system::SystemState;
+use bevy::prelude::*;
+use bevy_mod_index::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const ENTITIES: usize = 1_000_000;
+const DISTINCT: usize = 100;
+const VALUE_LEN: usize = 256;
+
+#[derive(Component)]
+struct Label(String);
+
+struct Copied;
+
+impl IndexInfo for Copied {
+    type Component = Label;
+    type Value = String;
+    type Storage = HashmapStorage<Self>;
+    type ValueParam = ();
+
+    fn value(c: &Label, _: &()) -> String {
+        c.0.clone()
+    }
+}
+
+struct Interned;
+
+impl IndexInfo for Interned {
+    type Component = Label;
+    type Value = String;
+    type Storage = InternedStorage<Self>;
+    type ValueParam = ();
+
+    fn value(c: &Label, _: &()) -> String {
+        c.0.clone()
+    }
+}
+
+/// Refresh `I` from scratch, so every entity is indexed again.
+fn build<I: IndexInfo<Value = String>>(world: &mut World) -> usize {
+    world.insert_resource(I::Storage::default());
+    let mut state = SystemState::<Index<I>>::new(world);
+    let mut idx = state.get_mut(world);
+    idx.lookup(black_box(&label(0))).len()
+}
+
+fn label(i: usize) -> String {
+    format!("{:0>width$}", i % DISTINCT, width = VALUE_LEN)
+}
+
+fn interned(c: &mut Criterion) {
+    let mut world = World::new();
+    world.spawn_batch((0..ENTITIES).map(|i| Label(label(i))));
+
+    // Timings include dropping the previous run's storage, which is also where copying
+    // values per entity costs the most.
+    let mut group = c.benchmark_group("build");
+    group.bench_with_input(BenchmarkId::from_parameter("copied"), &(), |b, _| {
+        b.iter(|| build::<Copied>(&mut world))
+    });
+    group.bench_with_input(BenchmarkId::from_parameter("interned"), &(), |b, _| {
+        b.iter(|| build::<Interned>(&mut world))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, interned);
+criterion_main!(benches);
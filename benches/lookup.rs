@@ -0,0 +1,171 @@
+//! Measures the cost of `Index::lookup` and its variants alone, using storages that are
+//! built up front so that no time is spent refreshing.
+
+use bevy::ecs::system::SystemState;
+use bevy::prelude::*;
+use bevy_mod_index::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const ENTITIES: u32 = 100_000;
+
+// Never spawned: the storage is filled directly, so the world has nothing to refresh.
+#[derive(Component)]
+struct Bucketed;
+
+struct BucketIndex;
+
+impl IndexInfo for BucketIndex {
+    type Component = Bucketed;
+    type Value = u32;
+    type Storage = HashmapStorage<Self>;
+    type ValueParam = ();
+
+    fn value(_: &Bucketed, _: &()) -> u32 {
+        unreachable!("no Bucketed components are spawned")
+    }
+}
+
+struct CachedBucketIndex;
+
+impl IndexInfo for CachedBucketIndex {
+    type Component = Bucketed;
+    type Value = u32;
+    type Storage = CachedStorage<Self>;
+    type ValueParam = ();
+
+    fn value(_: &Bucketed, _: &()) -> u32 {
+        unreachable!("no Bucketed components are spawned")
+    }
+}
+
+struct SharedBucketIndex;
+
+impl IndexInfo for SharedBucketIndex {
+    type Component = Bucketed;
+    type Value = u32;
+    type Storage = SharedStorage<Self>;
+    type ValueParam = ();
+
+    fn value(_: &Bucketed, _: &()) -> u32 {
+        unreachable!("no Bucketed components are spawned")
+    }
+}
+
+struct EntitySetBucketIndex;
+
+impl IndexInfo for EntitySetBucketIndex {
+    type Component = Bucketed;
+    type Value = u32;
+    type Storage = EntitySetStorage<Self>;
+    type ValueParam = ();
+
+    fn value(_: &Bucketed, _: &()) -> u32 {
+        unreachable!("no Bucketed components are spawned")
+    }
+}
+
+fn lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup");
+    for buckets in [1, 10, 1000] {
+        let mut world = World::new();
+        world.insert_resource(HashmapStorage::<BucketIndex>::from_entries(
+            (0..ENTITIES).map(|i| (i % buckets, Entity::from_raw(i))),
+        ));
+        let mut state = SystemState::<Index<BucketIndex>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+
+        group.bench_with_input(BenchmarkId::from_parameter(buckets), &buckets, |b, _| {
+            b.iter(|| idx.lookup(black_box(&0)).len())
+        });
+    }
+    group.finish();
+}
+
+// Sharing a bucket behind an `Arc` against copying it for every lookup.
+fn lookup_shared(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup_shared");
+    for buckets in [1, 10, 1000] {
+        let entries = || (0..ENTITIES).map(|i| (i % buckets, Entity::from_raw(i)));
+        let mut world = World::new();
+        world.insert_resource(HashmapStorage::<BucketIndex>::from_entries(entries()));
+        world.insert_resource(SharedStorage::<SharedBucketIndex>::from_entries(entries()));
+        let mut state =
+            SystemState::<(Index<BucketIndex>, Index<SharedBucketIndex>)>::new(&mut world);
+        let (mut idx, mut shared) = state.get_mut(&mut world);
+
+        group.bench_with_input(BenchmarkId::new("clone", buckets), &buckets, |b, _| {
+            b.iter(|| idx.lookup(black_box(&0)).len())
+        });
+        group.bench_with_input(BenchmarkId::new("shared", buckets), &buckets, |b, _| {
+            b.iter(|| shared.lookup_shared(black_box(&0)).len())
+        });
+    }
+    group.finish();
+}
+
+// Probing every entity of a bucket, in a copied `HashSet` against bevy's `EntityHashSet`
+// read in place.
+fn lookup_entityset(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup_entityset");
+    let entries = || (0..ENTITIES).map(|i| (i % 10, Entity::from_raw(i)));
+    let mut world = World::new();
+    world.insert_resource(HashmapStorage::<BucketIndex>::from_entries(entries()));
+    world.insert_resource(EntitySetStorage::<EntitySetBucketIndex>::from_entries(
+        entries(),
+    ));
+    let mut state =
+        SystemState::<(Index<BucketIndex>, Index<EntitySetBucketIndex>)>::new(&mut world);
+    let (mut idx, mut entityset) = state.get_mut(&mut world);
+
+    group.bench_function("hashset", |b| {
+        b.iter(|| {
+            let set = idx.lookup(black_box(&0));
+            set.iter().filter(|entity| set.contains(*entity)).count()
+        })
+    });
+    group.bench_function("entityset", |b| {
+        b.iter(|| {
+            let set = entityset.lookup_entityset(black_box(&0));
+            set.iter().filter(|entity| set.contains(*entity)).count()
+        })
+    });
+    group.finish();
+}
+
+// A few hot values looked up over and over, as systems that query the same values every
+// frame do. Nothing changes between lookups, so cached lookups never copy the entities.
+fn lookup_hot_set(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup_hot_set");
+    let entries = || (0..ENTITIES).map(|i| (i % 100, Entity::from_raw(i)));
+    let mut world = World::new();
+    world.insert_resource(HashmapStorage::<BucketIndex>::from_entries(entries()));
+    world.insert_resource(CachedStorage::<CachedBucketIndex>::from_entries(entries()));
+    let mut state = SystemState::<(Index<BucketIndex>, Index<CachedBucketIndex>)>::new(&mut world);
+    let (mut idx, mut cached) = state.get_mut(&mut world);
+    let hot = [0, 1, 2, 3];
+
+    group.bench_function("lookup", |b| {
+        b.iter(|| {
+            hot.iter()
+                .map(|val| idx.lookup(black_box(val)).len())
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("lookup_cached", |b| {
+        b.iter(|| {
+            hot.iter()
+                .map(|val| cached.lookup_cached(black_box(val)).len())
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    lookup,
+    lookup_shared,
+    lookup_entityset,
+    lookup_hot_set
+);
+criterion_main!(benches);
@@ -0,0 +1,96 @@
+//! Compares refreshing an index entity by entity with rebuilding it from scratch, for
+//! different fractions of the components changing between refreshes, and the cost of
+//! indexing a large batch of new entities with and without reserving space first.
+
+use bevy::ecs::system::SystemState;
+use bevy::prelude::*;
+use bevy_mod_index::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const ENTITIES: u32 = 100_000;
+
+#[derive(Component)]
+struct Cell(u32);
+
+struct CellIndex;
+
+impl IndexInfo for CellIndex {
+    type Component = Cell;
+    type Value = u32;
+    type Storage = HashmapStorage<Self>;
+    type ValueParam = ();
+
+    fn value(c: &Cell, _: &()) -> u32 {
+        c.0 % 1000
+    }
+}
+
+// Only refreshed manually, so that each run can choose how.
+struct EagerCellIndex;
+
+impl IndexInfo for EagerCellIndex {
+    type Component = Cell;
+    type Value = u32;
+    type Storage = HashmapStorage<Self>;
+    type ValueParam = ();
+    const REFRESH: RefreshPolicy = RefreshPolicy::Eager;
+
+    fn value(c: &Cell, _: &()) -> u32 {
+        c.0 % 1000
+    }
+}
+
+fn refresh(c: &mut Criterion) {
+    let mut group = c.benchmark_group("refresh");
+    for (strategy, threshold) in [("incremental", None), ("rebuild", Some(0.0))] {
+        for percent in [1, 10, 25, 50, 60, 75, 90, 100] {
+            let mut app = App::new();
+            app.add_plugins(IndexPlugin::<CellIndex>::new().rebuild_threshold(threshold));
+            app.world.spawn_batch((0..ENTITIES).map(Cell));
+            app.update();
+            let mut cells = app.world.query::<&mut Cell>();
+            let changed = (ENTITIES * percent / 100) as usize;
+
+            group.bench_with_input(BenchmarkId::new(strategy, percent), &percent, |b, _| {
+                b.iter(|| {
+                    for mut cell in cells.iter_mut(&mut app.world).take(changed) {
+                        cell.0 += 1;
+                    }
+                    app.update();
+                    black_box(app.world.resource::<HashmapStorage<CellIndex>>());
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn refresh_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("refresh_batch");
+    let mut app = App::new();
+    app.add_plugins(IndexPlugin::<EagerCellIndex>::new());
+    app.world.spawn_batch((0..ENTITIES).map(Cell));
+    let mut state = SystemState::<Index<EagerCellIndex>>::new(&mut app.world);
+
+    // Each run starts from an empty storage, so every entity is new to the index. Timings
+    // include dropping the previous run's storage.
+    for (name, reserving) in [("refresh", false), ("refresh_reserving", true)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                app.world
+                    .insert_resource(HashmapStorage::<EagerCellIndex>::default());
+                let mut idx = state.get_mut(&mut app.world);
+                if reserving {
+                    idx.refresh_reserving();
+                } else {
+                    idx.refresh();
+                }
+                idx.lookup(black_box(&0)).len()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, refresh, refresh_batch);
+criterion_main!(benches);
@@ -0,0 +1,141 @@
+//! Compares the memory kept and the refresh time of `SparseStorage` and `HashmapStorage`
+//! for a component that only a few of many entities have.
+
+use bevy::ecs::system::SystemState;
+use bevy::prelude::*;
+use bevy_mod_index::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const ENTITIES: u32 = 1_000_000;
+const RARE: u32 = 100;
+
+/// Counts the bytes currently allocated, to measure how much each storage keeps.
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+// SAFETY: Only forwards to the system allocator.
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+#[derive(Component)]
+struct Filler;
+
+#[derive(Component)]
+struct Rare(u32);
+
+struct Hashed;
+
+impl IndexInfo for Hashed {
+    type Component = Rare;
+    type Value = u32;
+    type Storage = HashmapStorage<Self>;
+    type ValueParam = ();
+
+    fn value(c: &Rare, _: &()) -> u32 {
+        c.0 % 4
+    }
+}
+
+struct Sparse;
+
+impl IndexInfo for Sparse {
+    type Component = Rare;
+    type Value = u32;
+    type Storage = SparseStorage<Self>;
+    type ValueParam = ();
+
+    fn value(c: &Rare, _: &()) -> u32 {
+        c.0 % 4
+    }
+}
+
+fn spawn_world() -> World {
+    let mut world = World::new();
+    world.spawn_batch((0..ENTITIES - RARE).map(|_| Filler));
+    world.spawn_batch((0..RARE).map(Rare));
+    world
+}
+
+/// The bytes freed by dropping `I`'s storage.
+fn storage_bytes<I: IndexInfo>(world: &mut World) -> usize {
+    let storage = world.remove_resource::<I::Storage>();
+    let before = LIVE.load(Ordering::Relaxed);
+    drop(storage);
+    before - LIVE.load(Ordering::Relaxed)
+}
+
+/// The bytes that `I`'s storage keeps after indexing every rare entity, and after most of
+/// them are removed again.
+fn retained<I: IndexInfo<Value = u32>>() -> (usize, usize) {
+    let mut world = spawn_world();
+    let mut state = SystemState::<Index<I>>::new(&mut world);
+    black_box(state.get_mut(&mut world).lookup(&0).len());
+    let full = storage_bytes::<I>(&mut world);
+
+    let mut world = spawn_world();
+    let mut state = SystemState::<Index<I>>::new(&mut world);
+    black_box(state.get_mut(&mut world).lookup(&0).len());
+    let rare: Vec<Entity> = world
+        .query_filtered::<Entity, With<Rare>>()
+        .iter(&world)
+        .skip(10)
+        .collect();
+    for entity in rare {
+        world.entity_mut(entity).remove::<Rare>();
+    }
+    world.increment_change_tick();
+    black_box(state.get_mut(&mut world).lookup(&0).len());
+    (full, storage_bytes::<I>(&mut world))
+}
+
+fn memory(_: &mut Criterion) {
+    for (name, (full, pruned)) in [
+        ("hashmap", retained::<Hashed>()),
+        ("sparse", retained::<Sparse>()),
+    ] {
+        println!("sparse_memory/{name}: {full} bytes for {RARE} entities, {pruned} bytes for 10");
+    }
+}
+
+fn refresh<I: IndexInfo<Value = u32>>(c: &mut Criterion, name: &str) {
+    let mut world = spawn_world();
+    let mut state = SystemState::<Index<I>>::new(&mut world);
+    state.get_mut(&mut world);
+    let mut rare = world.query::<&mut Rare>();
+
+    let mut group = c.benchmark_group("sparse_refresh");
+    group.bench_with_input(BenchmarkId::from_parameter(name), &(), |b, _| {
+        b.iter(|| {
+            // Move every rare entity to the next value.
+            for mut component in rare.iter_mut(&mut world) {
+                component.0 += 1;
+            }
+            world.increment_change_tick();
+            state.get_mut(&mut world).lookup(black_box(&0)).len()
+        })
+    });
+    group.finish();
+}
+
+fn throughput(c: &mut Criterion) {
+    refresh::<Hashed>(c, "hashmap");
+    refresh::<Sparse>(c, "sparse");
+}
+
+criterion_group!(benches, memory, throughput);
+criterion_main!(benches);
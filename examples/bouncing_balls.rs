@@ -29,8 +29,9 @@ impl IndexInfo for RegionIndex {
     type Component = Transform;
     type Value = Region;
     type Storage = HashmapStorage<Self>;
+    type ValueParam = ();
 
-    fn value(t: &Transform) -> Region {
+    fn value(t: &Transform, _: &()) -> Region {
         get_region(&t.translation.xy())
     }
 }
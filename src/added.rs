@@ -0,0 +1,232 @@
+use crate::index::IndexInfo;
+use crate::storage::{
+    changed_since_refresh, HashmapStorageRefreshData, IndexStorage, RemovalReader,
+};
+use crate::unique_multimap::UniqueMultiMap;
+use bevy::ecs::component::Tick;
+use bevy::ecs::system::StaticSystemParam;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use std::marker::PhantomData;
+
+/// [`IndexInfo`] for looking up entities by when their `C` component was added.
+///
+/// Each entity is indexed under the [`Tick`] of its component the first time the index
+/// sees it, divided by `TICKS_PER_BUCKET`, and is never moved to another bucket after
+/// that, even when the component changes. Removing and re-adding the component indexes
+/// the entity again under its new tick. Use [`bucket`][Self::bucket] to get the value to
+/// look up for a given tick.
+///
+/// Bevy advances the change tick once for every system that runs, not once per frame, so
+/// choose `TICKS_PER_BUCKET` based on roughly how many systems run per frame if you want
+/// buckets to line up with frames. With the default of 1, each bucket holds the entities
+/// added by a single system run.
+///
+/// Bevy does not expose the tick that a component was added at through queries, so the
+/// tick of the component's latest change is used when the index first sees it. These are
+/// the same unless the component is changed after being added but before the index's next
+/// refresh, which can be avoided by refreshing with an
+/// [`IndexPlugin`][crate::plugin::IndexPlugin].
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy::ecs::system::SystemChangeTick;
+/// # use bevy_mod_index::prelude::*;
+/// # #[derive(Component)]
+/// # struct Enemy;
+/// fn newest_enemies(mut idx: Index<AddedAtIndex<Enemy>>, ticks: SystemChangeTick) {
+///     let last_run = AddedAtIndex::<Enemy>::bucket(ticks.last_run());
+///     let enemies = idx.lookup(&last_run);
+/// }
+/// ```
+pub struct AddedAtIndex<C: Component, const TICKS_PER_BUCKET: u32 = 1>(PhantomData<fn() -> C>);
+
+impl<C: Component, const TICKS_PER_BUCKET: u32> AddedAtIndex<C, TICKS_PER_BUCKET> {
+    /// Get the bucket that components added at `tick` are indexed under.
+    pub fn bucket(tick: Tick) -> u32 {
+        tick.get() / TICKS_PER_BUCKET
+    }
+}
+
+impl<C: Component, const TICKS_PER_BUCKET: u32> IndexInfo for AddedAtIndex<C, TICKS_PER_BUCKET> {
+    type Component = C;
+    type Value = u32;
+    type Storage = AddedAtStorage<C, TICKS_PER_BUCKET>;
+    type ValueParam = ();
+
+    /// Not used, since the value depends on the component's change ticks rather than its data.
+    fn value(_: &C, _: &()) -> u32 {
+        unreachable!("AddedAtStorage reads change ticks instead of component values")
+    }
+}
+
+/// [`IndexStorage`] for [`AddedAtIndex`], which indexes each entity under the tick that it
+/// was first seen at.
+///
+/// | Feature | `AddedAtStorage` |
+/// |---|---|
+/// | Automatic refresh timing | When used; once per-system run |
+/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] |
+/// | Sees updates from earlier in the frame? | Yes |
+#[derive(Resource)]
+pub struct AddedAtStorage<C: Component, const TICKS_PER_BUCKET: u32> {
+    map: UniqueMultiMap<u32, Entity>,
+    last_refresh_tick: Option<Tick>,
+    removals: RemovalReader<C>,
+    dirty: bool,
+}
+
+impl<C: Component, const TICKS_PER_BUCKET: u32> Default for AddedAtStorage<C, TICKS_PER_BUCKET> {
+    fn default() -> Self {
+        Self {
+            map: Default::default(),
+            last_refresh_tick: None,
+            removals: Default::default(),
+            dirty: false,
+        }
+    }
+}
+
+impl<C: Component, const TICKS_PER_BUCKET: u32> IndexStorage<AddedAtIndex<C, TICKS_PER_BUCKET>>
+    for AddedAtStorage<C, TICKS_PER_BUCKET>
+{
+    type RefreshData<'w, 's> = HashmapStorageRefreshData<'w, 's, AddedAtIndex<C, TICKS_PER_BUCKET>>;
+
+    fn lookup<'w, 's>(
+        &mut self,
+        val: &u32,
+        _data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
+    ) -> HashSet<Entity> {
+        self.map.get(val)
+    }
+
+    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
+        let this_run = data.ticks.this_run();
+        if std::mem::take(&mut self.dirty) {
+            // Drop entities that don't have the component any more, and index the ones that
+            // were missed. Entities that are still indexed keep their bucket, since the tick
+            // they were first seen at can't be recovered from the world.
+            self.last_refresh_tick = None;
+            let stale: Vec<Entity> = self
+                .map
+                .iter_rev()
+                .map(|(entity, _)| *entity)
+                .filter(|entity| !data.components.contains(*entity))
+                .collect();
+            for entity in stale {
+                self.map.remove(&entity);
+            }
+        }
+        let removed: Vec<Entity> = data.removals.read().collect();
+        for entity in removed {
+            // A component that was added back counts as newly added.
+            match data.components.get(entity) {
+                Ok((_, component)) => {
+                    let bucket =
+                        AddedAtIndex::<C, TICKS_PER_BUCKET>::bucket(component.last_changed());
+                    self.map.insert(&bucket, &entity);
+                }
+                Err(_) => {
+                    self.map.remove(&entity);
+                }
+            };
+        }
+        for (entity, component) in &data.components {
+            // Newly added components are always changed, so only those need checking.
+            if changed_since_refresh::<AddedAtIndex<C, TICKS_PER_BUCKET>>(
+                &component,
+                self.last_refresh_tick,
+                this_run,
+            ) && self.map.key_of(&entity).is_none()
+            {
+                let bucket = AddedAtIndex::<C, TICKS_PER_BUCKET>::bucket(component.last_changed());
+                self.map.insert(&bucket, &entity);
+            }
+        }
+        self.last_refresh_tick = Some(this_run);
+    }
+
+    fn apply(&mut self, world: &World) {
+        for entity in self.removals.read(world) {
+            self.map.remove(&entity);
+        }
+    }
+
+    fn entity_count(&self) -> Option<usize> {
+        Some(self.map.len())
+    }
+
+    fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use bevy::ecs::component::Tick;
+    use bevy::ecs::system::SystemState;
+    use bevy::prelude::*;
+
+    #[derive(Component)]
+    struct Health(u32);
+
+    fn added_tick(world: &World, entity: Entity) -> Tick {
+        world
+            .entity(entity)
+            .get_change_ticks::<Health>()
+            .unwrap()
+            .added_tick()
+    }
+
+    #[test]
+    fn test_added_at_index() {
+        let mut world = World::new();
+        let a = world.spawn(Health(1)).id();
+        world.increment_change_tick();
+        let b = world.spawn(Health(1)).id();
+        let (tick_a, tick_b) = (added_tick(&world, a), added_tick(&world, b));
+        assert_ne!(tick_a, tick_b);
+
+        let mut state = SystemState::<(
+            Index<AddedAtIndex<Health>>,
+            Index<AddedAtIndex<Health, 1000>>,
+        )>::new(&mut world);
+        let (mut fine, mut coarse) = state.get_mut(&mut world);
+        assert_eq!(
+            fine.lookup(&AddedAtIndex::<Health>::bucket(tick_a)),
+            [a].into()
+        );
+        assert_eq!(
+            fine.lookup(&AddedAtIndex::<Health>::bucket(tick_b)),
+            [b].into()
+        );
+        assert_eq!(
+            coarse.lookup(&AddedAtIndex::<Health, 1000>::bucket(tick_a)),
+            [a, b].into()
+        );
+
+        // Changing the component doesn't move it.
+        world.increment_change_tick();
+        world.get_mut::<Health>(a).unwrap().0 = 2;
+        let (mut fine, _) = state.get_mut(&mut world);
+        assert_eq!(
+            fine.lookup(&AddedAtIndex::<Health>::bucket(tick_a)),
+            [a].into()
+        );
+
+        // Re-adding it does.
+        world.entity_mut(a).remove::<Health>();
+        world.increment_change_tick();
+        world.entity_mut(a).insert(Health(3));
+        let tick_readded = added_tick(&world, a);
+        let (mut fine, _) = state.get_mut(&mut world);
+        assert!(fine
+            .lookup(&AddedAtIndex::<Health>::bucket(tick_a))
+            .is_empty());
+        assert_eq!(
+            fine.lookup(&AddedAtIndex::<Health>::bucket(tick_readded)),
+            [a].into()
+        );
+    }
+}
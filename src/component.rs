@@ -0,0 +1,364 @@
+use crate::index::{indexed_value, Index, IndexInfo, RefreshPolicy};
+use crate::storage::{AggregateStorage, HashmapStorage, Measure};
+use bevy::ecs::system::SystemParamItem;
+use bevy::prelude::*;
+use bevy::utils::{FixedState, HashSet};
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+
+/// [`IndexInfo`] for looking up entities by the value of their `C` component itself.
+///
+/// This saves writing an [`IndexInfo`] implementation when the component is its own value,
+/// so lookups take the component type directly:
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// struct Number(usize);
+///
+/// fn find_zeroes(mut idx: Index<ComponentIndex<Number>>) {
+///     let zeroes = idx.lookup(&Number(0));
+/// }
+/// ```
+///
+/// The component must implement [`Clone`], [`Hash`], and [`Eq`] to be used as a value.
+/// Using a component that doesn't is reported where the index is named:
+///
+/// ```compile_fail
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// #[derive(Component, Clone, PartialEq, Eq)]
+/// struct Unhashable(usize);
+///
+/// fn find_zeroes(mut idx: Index<ComponentIndex<Unhashable>>) {}
+/// ```
+///
+/// Implement [`IndexInfo`] on your own type instead if you need a different storage, or
+/// to index only part of the component.
+pub struct ComponentIndex<C: Component + Clone + Hash + Eq>(PhantomData<fn() -> C>);
+
+impl<C: Component + Clone + Hash + Eq> IndexInfo for ComponentIndex<C> {
+    type Component = C;
+    type Value = C;
+    type Storage = HashmapStorage<Self>;
+    type ValueParam = ();
+
+    fn value(c: &C, _: &()) -> C {
+        c.clone()
+    }
+}
+
+/// [`IndexInfo`] for grouping entities by their `G` component and keeping the sum and count
+/// of their `M` [`Measure`]s per group, with [`AggregateStorage`].
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// struct Team(u32);
+///
+/// #[derive(Component)]
+/// struct Score(u32);
+///
+/// impl Measure for Score {
+///     fn measure(&self) -> f64 {
+///         self.0 as f64
+///     }
+/// }
+///
+/// fn team_scores(mut idx: Index<AggregateIndex<Team, Score>>) {
+///     let total = idx.group_sum(&Team(1));
+///     let average = idx.group_avg(&Team(1));
+/// }
+/// ```
+pub struct AggregateIndex<G, M>(PhantomData<fn() -> (G, M)>);
+
+impl<G: Component + Clone + Hash + Eq, M: Measure> IndexInfo for AggregateIndex<G, M> {
+    type Component = G;
+    type Value = G;
+    type Storage = AggregateStorage<Self, M>;
+    type ValueParam = ();
+
+    fn value(c: &G, _: &()) -> G {
+        c.clone()
+    }
+}
+
+/// [`IndexInfo`] for splitting the entities in the index `I` into `N` shards by the hash of
+/// their value, e.g. to spread work over several systems or frames.
+///
+/// Entities with equal values always share a shard, and shards are only as even as the
+/// values' hashes are: an index with few distinct values, or one value held by most entities,
+/// leaves some shards much larger than others. Values are hashed with the same fixed hasher
+/// as [`Index::hash_value`], so shards are stable within a build of a program.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// struct Chunk(i32, i32);
+///
+/// fn update_first_quarter(mut idx: Index<ShardIndex<ComponentIndex<Chunk>, 4>>) {
+///     for entity in idx.lookup_shard(0) {
+///         // ...
+///     }
+/// }
+/// ```
+pub struct ShardIndex<I: IndexInfo, const N: usize>(PhantomData<fn() -> I>);
+
+impl<I: IndexInfo, const N: usize> ShardIndex<I, N> {
+    /// Get the shard that entities with the value `val` in `I` are in.
+    pub fn shard_of(val: &I::Value) -> usize {
+        (FixedState.hash_one(val) % N as u64) as usize
+    }
+}
+
+impl<I: IndexInfo, const N: usize> IndexInfo for ShardIndex<I, N> {
+    type Component = I::Component;
+    type Value = usize;
+    type Storage = HashmapStorage<Self>;
+    type ValueParam = I::ValueParam;
+
+    /// Not used, since the shard depends on `I`'s value for the entity.
+    fn value(_: &I::Component, _: &SystemParamItem<I::ValueParam>) -> usize {
+        unreachable!("value_for_entity is overridden")
+    }
+
+    fn value_for_entity(
+        entity: Entity,
+        c: &I::Component,
+        param: &SystemParamItem<I::ValueParam>,
+    ) -> Option<usize> {
+        indexed_value::<I>(entity, c, param).map(|val| Self::shard_of(&val))
+    }
+
+    const SEE_OWN_CHANGES: bool = I::SEE_OWN_CHANGES;
+    const REFRESH: RefreshPolicy = I::REFRESH;
+}
+
+impl<'w, 's, I: IndexInfo, const N: usize> Index<'w, 's, ShardIndex<I, N>> {
+    /// Get all of the entities in shard `shard`, which is empty unless it is less than `N`.
+    pub fn lookup_shard(&mut self, shard: usize) -> HashSet<Entity> {
+        self.lookup(&shard)
+    }
+}
+
+/// Define an [`IndexInfo`] that looks up any component implementing a trait by the result
+/// of one of the trait's methods.
+///
+/// `trait_index!(CategoryIndex: Categorize => category -> Category)` defines a
+/// `CategoryIndex<C>` struct, with `C` the component type, indexed by calling
+/// `Categorize::category(&self) -> Category`. This saves writing an [`IndexInfo`] for every
+/// component that implements the trait:
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// # use bevy_mod_index::trait_index;
+/// #[derive(Clone, PartialEq, Eq, Hash)]
+/// pub enum Category {
+///     Weapon,
+///     Armor,
+/// }
+///
+/// pub trait Categorize {
+///     fn category(&self) -> Category;
+/// }
+///
+/// trait_index!(
+///     /// Look up items by their category.
+///     pub CategoryIndex: Categorize => category -> Category
+/// );
+///
+/// #[derive(Component)]
+/// struct Sword;
+///
+/// impl Categorize for Sword {
+///     fn category(&self) -> Category {
+///         Category::Weapon
+///     }
+/// }
+///
+/// fn find_swords(mut idx: Index<CategoryIndex<Sword>>) {
+///     let weapons = idx.lookup(&Category::Weapon);
+/// }
+/// ```
+///
+/// Each component gets its own index, so `CategoryIndex<Sword>` only contains entities with
+/// a `Sword`. The indexes use [`HashmapStorage`], and the method's return type must be the
+/// same for every component.
+#[macro_export]
+macro_rules! trait_index {
+    ($(#[$meta:meta])* $vis:vis $name:ident: $trait:path => $method:ident -> $value:ty) => {
+        $(#[$meta])*
+        $vis struct $name<C>($crate::component::__macro::PhantomData<fn() -> C>);
+
+        impl<C: $trait + $crate::component::__macro::Component> $crate::index::IndexInfo
+            for $name<C>
+        {
+            type Component = C;
+            type Value = $value;
+            type Storage = $crate::storage::HashmapStorage<Self>;
+            type ValueParam = ();
+
+            fn value(c: &C, _: &()) -> $value {
+                <C as $trait>::$method(c)
+            }
+        }
+    };
+}
+
+#[doc(hidden)]
+pub mod __macro {
+    pub use bevy::ecs::component::Component;
+    pub use std::marker::PhantomData;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use bevy::ecs::system::SystemState;
+    use bevy::prelude::*;
+    use bevy::utils::HashSet;
+
+    #[derive(Component, Clone, PartialEq, Eq, Hash, Debug)]
+    struct Number(usize);
+
+    #[test]
+    fn test_component_index() {
+        let mut world = World::new();
+        let zero = world.spawn(Number(0)).id();
+        world.spawn(Number(1));
+        let mut state = SystemState::<Index<ComponentIndex<Number>>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(0)), [zero].into());
+        assert_eq!(idx.lookup(&Number(1)).len(), 1);
+    }
+
+    #[derive(Component)]
+    struct Score(i32);
+
+    impl Measure for Score {
+        fn measure(&self) -> f64 {
+            self.0 as f64
+        }
+    }
+
+    #[test]
+    fn test_aggregate_index() {
+        let mut world = World::new();
+        let a = world.spawn((Number(1), Score(10))).id();
+        world.spawn((Number(1), Score(5)));
+        // Counts for lookups, but not for the aggregate.
+        world.spawn(Number(1));
+        world.spawn((Number(2), Score(7)));
+        let mut state = SystemState::<Index<AggregateIndex<Number, Score>>>::new(&mut world);
+        world.increment_change_tick();
+
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(1)).len(), 3);
+        assert_eq!(idx.group_sum(&Number(1)), 15.0);
+        assert_eq!(idx.group_count(&Number(1)), 2);
+        assert_eq!(idx.group_avg(&Number(1)), Some(7.5));
+        assert_eq!(idx.group_avg(&Number(3)), None);
+
+        // Changing one member's measure moves the sum by exactly the difference.
+        world.get_mut::<Score>(a).unwrap().0 = 13;
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.group_sum(&Number(1)), 18.0);
+        assert_eq!(idx.group_sum(&Number(2)), 7.0);
+
+        // Moving it to another group moves its measure with it.
+        world.get_mut::<Number>(a).unwrap().0 = 2;
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.group_sum(&Number(1)), 5.0);
+        assert_eq!(idx.group_sum(&Number(2)), 20.0);
+        assert_eq!(idx.group_count(&Number(2)), 2);
+
+        world.entity_mut(a).remove::<Score>();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.group_sum(&Number(2)), 7.0);
+    }
+
+    #[test]
+    fn test_shard_index() {
+        let mut world = World::new();
+        let entities: HashSet<Entity> = (0..100).map(|i| world.spawn(Number(i)).id()).collect();
+        let mut state =
+            SystemState::<Index<ShardIndex<ComponentIndex<Number>, 4>>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+
+        let shards: Vec<HashSet<Entity>> = (0..4).map(|i| idx.lookup_shard(i)).collect();
+        assert!(shards.iter().all(|shard| !shard.is_empty()));
+        assert_eq!(
+            shards.iter().map(HashSet::len).sum::<usize>(),
+            entities.len()
+        );
+        assert_eq!(
+            shards.into_iter().flatten().collect::<HashSet<_>>(),
+            entities
+        );
+        assert!(idx.lookup_shard(4).is_empty());
+
+        // Equal values share a shard.
+        let shard = ShardIndex::<ComponentIndex<Number>, 4>::shard_of(&Number(7));
+        let twin = world.spawn(Number(7)).id();
+        assert!(state
+            .get_mut(&mut world)
+            .lookup_shard(shard)
+            .contains(&twin));
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    enum Category {
+        Weapon,
+        Armor,
+    }
+
+    trait Categorize {
+        fn category(&self) -> Category;
+    }
+
+    trait_index!(CategoryIndex: Categorize => category -> Category);
+
+    #[derive(Component)]
+    struct Sword;
+
+    impl Categorize for Sword {
+        fn category(&self) -> Category {
+            Category::Weapon
+        }
+    }
+
+    #[derive(Component)]
+    struct Shield(bool);
+
+    impl Categorize for Shield {
+        fn category(&self) -> Category {
+            // Spiked shields double as weapons.
+            if self.0 {
+                Category::Weapon
+            } else {
+                Category::Armor
+            }
+        }
+    }
+
+    #[test]
+    fn test_trait_index() {
+        let mut world = World::new();
+        let sword = world.spawn(Sword).id();
+        let spiked = world.spawn(Shield(true)).id();
+        let plain = world.spawn(Shield(false)).id();
+        let mut state =
+            SystemState::<(Index<CategoryIndex<Sword>>, Index<CategoryIndex<Shield>>)>::new(
+                &mut world,
+            );
+        let (mut swords, mut shields) = state.get_mut(&mut world);
+        assert_eq!(swords.lookup(&Category::Weapon), [sword].into());
+        assert!(swords.lookup(&Category::Armor).is_empty());
+        assert_eq!(shields.lookup(&Category::Weapon), [spiked].into());
+        assert_eq!(shields.lookup(&Category::Armor), [plain].into());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::index::{index_label, IndexInfo};
+use crate::plugin::PluginAdded;
+use crate::storage::IndexStorage;
+use bevy::core::FrameCount;
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics, RegisterDiagnostic};
+use bevy::ecs::system::StaticSystemParam;
+use bevy::ecs::world::WorldId;
+use bevy::prelude::*;
+use bevy::utils::Instant;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// The [`DiagnosticId`]s that [`IndexPlugin`][crate::plugin::IndexPlugin] records measurements
+/// under for an index.
+///
+/// Ids are derived from the type name of the [`IndexInfo`] implementation, so they are stable
+/// across runs but distinct for each index. They are the same in every [`World`], since each
+/// app has its own diagnostics, but the diagnostics' names include the
+/// [`index_label`][crate::index::index_label], such as
+/// `index/my_game::Health@WorldId(0)/entities`, so tooling that collects diagnostics from
+/// several worlds can tell them apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexDiagnosticIds {
+    /// Number of entities in the index after each refresh.
+    pub entities: DiagnosticId,
+    /// Time taken by each refresh, in milliseconds.
+    pub refresh_time: DiagnosticId,
+    /// Change in the number of entities in the index caused by each refresh.
+    pub entity_change: DiagnosticId,
+}
+
+impl IndexDiagnosticIds {
+    /// Get the ids for the index `I`.
+    pub fn of<I: IndexInfo>() -> Self {
+        Self {
+            entities: diagnostic_id::<I>("entities"),
+            refresh_time: diagnostic_id::<I>("refresh_time"),
+            entity_change: diagnostic_id::<I>("entity_change"),
+        }
+    }
+}
+
+fn diagnostic_name<I: IndexInfo>(world: WorldId, measurement: &str) -> String {
+    format!("index/{}/{}", index_label::<I>(world), measurement)
+}
+
+fn diagnostic_id<I: IndexInfo>(measurement: &str) -> DiagnosticId {
+    let name = format!("index/{}/{}", std::any::type_name::<I>(), measurement);
+    let mut high = DefaultHasher::new();
+    (0u8, &name).hash(&mut high);
+    let mut low = DefaultHasher::new();
+    (1u8, &name).hash(&mut low);
+    DiagnosticId::from_u128((high.finish() as u128) << 64 | low.finish() as u128)
+}
+
+pub(crate) fn register_diagnostics<I: IndexInfo>(app: &mut App) {
+    let ids = IndexDiagnosticIds::of::<I>();
+    let world = app.world.id();
+    app.register_diagnostic(Diagnostic::new(
+        ids.entities,
+        diagnostic_name::<I>(world, "entities"),
+        20,
+    ))
+    .register_diagnostic(
+        Diagnostic::new(
+            ids.refresh_time,
+            diagnostic_name::<I>(world, "refresh_time"),
+            20,
+        )
+        .with_suffix("ms"),
+    )
+    .register_diagnostic(Diagnostic::new(
+        ids.entity_change,
+        diagnostic_name::<I>(world, "entity_change"),
+        20,
+    ));
+}
+
+/// System that refreshes an index and records its [`IndexDiagnosticIds`] measurements.
+/// Added by [`IndexPlugin`][crate::plugin::IndexPlugin] when the `diagnostics` feature is enabled.
+pub fn refresh_index_diagnostics_system<I: IndexInfo>(
+    mut storage: ResMut<I::Storage>,
+    mut data: StaticSystemParam<<I::Storage as IndexStorage<I>>::RefreshData<'_, '_>>,
+    mut diagnostics: Diagnostics,
+    plugin: Option<ResMut<PluginAdded<I>>>,
+    frame: Option<Res<FrameCount>>,
+) {
+    let ids = IndexDiagnosticIds::of::<I>();
+    let before = storage.entity_count();
+    let start = Instant::now();
+    storage.refresh(&mut data);
+    let elapsed = start.elapsed();
+    if let Some(mut plugin) = plugin {
+        plugin.mark_refreshed(frame);
+    }
+
+    diagnostics.add_measurement(ids.refresh_time, || elapsed.as_secs_f64() * 1000.0);
+    if let Some(after) = storage.entity_count() {
+        diagnostics.add_measurement(ids.entities, || after as f64);
+        if let Some(before) = before {
+            diagnostics.add_measurement(ids.entity_change, || after as f64 - before as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use bevy::diagnostic::DiagnosticsStore;
+
+    #[derive(Component, Clone, Eq, Hash, PartialEq, Debug)]
+    struct Number(usize);
+
+    #[test]
+    fn test_diagnostics() {
+        let mut app = App::new();
+        app.add_plugins(IndexPlugin::<ComponentIndex<Number>>::new());
+        app.world.spawn(Number(1));
+        app.world.spawn(Number(2));
+        app.update();
+
+        let ids = IndexDiagnosticIds::of::<ComponentIndex<Number>>();
+        assert_ne!(ids.entities, IndexDiagnosticIds::of::<NameIndex>().entities);
+        let store = app.world.resource::<DiagnosticsStore>();
+        assert_eq!(store.get(ids.entities).unwrap().value(), Some(2.0));
+        assert_eq!(store.get(ids.entity_change).unwrap().value(), Some(2.0));
+        assert!(store.get(ids.refresh_time).unwrap().value().is_some());
+        let name = &store.get(ids.entities).unwrap().name;
+        assert!(name.contains(&format!("{:?}", app.world.id())));
+
+        app.world.spawn(Number(3));
+        app.update();
+        let store = app.world.resource::<DiagnosticsStore>();
+        assert_eq!(store.get(ids.entities).unwrap().value(), Some(3.0));
+        assert_eq!(store.get(ids.entity_change).unwrap().value(), Some(1.0));
+    }
+}
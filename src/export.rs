@@ -0,0 +1,140 @@
+use crate::index::{Index, IndexInfo};
+use crate::storage::{Bucket, HashmapStorage};
+use bevy::prelude::*;
+use serde::Serialize;
+use std::fmt::Display;
+use std::io::{self, Write};
+
+/// The format to [`export`][Index::export] an index in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A `value,entity` header, then one line for each entity. Values are written with
+    /// their [`Display`] implementation, and quoted if they contain a comma, quote, or
+    /// line break.
+    Csv,
+    /// An array of `{"value": ..., "entity": ...}` objects, one for each entity. Values are
+    /// written with their [`Serialize`] implementation.
+    Json,
+}
+
+impl<'w, 's, I, B> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = HashmapStorage<I, B>>,
+    I::Value: Display + Serialize,
+    B: Bucket<Entity>,
+{
+    /// Write every `(value, entity)` pair in the index as a string, for debugging.
+    ///
+    /// Entities are written as the number returned by [`Entity::to_bits`]. Pairs are in no
+    /// particular order. See [`export_to`][Self::export_to] to avoid building the whole
+    /// string in memory for large indexes.
+    pub fn export(&mut self, format: ExportFormat) -> String {
+        let mut out = Vec::new();
+        self.export_to(&mut out, format)
+            .expect("writing to a Vec can't fail");
+        String::from_utf8(out).expect("exports are valid UTF-8")
+    }
+
+    /// Like [`export`][Self::export], but writes each pair to `writer` as it goes.
+    pub fn export_to(&mut self, mut writer: impl Write, format: ExportFormat) -> io::Result<()> {
+        let pairs = self.storage.map_ref().iter_rev();
+        match format {
+            ExportFormat::Csv => {
+                writeln!(writer, "value,entity")?;
+                for (entity, value) in pairs {
+                    writeln!(writer, "{},{}", csv_field(value), entity.to_bits())?;
+                }
+            }
+            ExportFormat::Json => {
+                write!(writer, "[")?;
+                for (i, (entity, value)) in pairs.enumerate() {
+                    if i > 0 {
+                        write!(writer, ",")?;
+                    }
+                    write!(writer, "{{\"value\":")?;
+                    serde_json::to_writer(&mut writer, value)?;
+                    write!(writer, ",\"entity\":{}}}", entity.to_bits())?;
+                }
+                write!(writer, "]")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn csv_field(value: &impl Display) -> String {
+    let field = value.to_string();
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ExportFormat;
+    use crate::prelude::*;
+    use bevy::ecs::system::SystemState;
+    use bevy::prelude::*;
+    use bevy::utils::HashSet;
+
+    struct LabelIndex;
+
+    impl IndexInfo for LabelIndex {
+        type Component = Name;
+        type Value = String;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Name, _: &()) -> String {
+            c.as_str().to_string()
+        }
+    }
+
+    fn exported(world: &mut World, format: ExportFormat) -> String {
+        let mut state = SystemState::<Index<LabelIndex>>::new(world);
+        let export = state.get_mut(world).export(format);
+        export
+    }
+
+    #[test]
+    fn test_export() {
+        let mut world = World::new();
+        let a = world.spawn(Name::new("plain")).id();
+        let b = world.spawn(Name::new("with, \"quotes\"")).id();
+        let expected: HashSet<(String, u64)> = [
+            ("plain".to_string(), a.to_bits()),
+            ("with, \"quotes\"".to_string(), b.to_bits()),
+        ]
+        .into();
+
+        let csv = exported(&mut world, ExportFormat::Csv);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("value,entity"));
+        let parsed: HashSet<(String, u64)> = lines
+            .map(|line| {
+                let (value, entity) = line.rsplit_once(',').unwrap();
+                let value = match value.strip_prefix('"') {
+                    Some(quoted) => quoted.strip_suffix('"').unwrap().replace("\"\"", "\""),
+                    None => value.to_string(),
+                };
+                (value, entity.parse().unwrap())
+            })
+            .collect();
+        assert_eq!(parsed, expected);
+
+        let json = exported(&mut world, ExportFormat::Json);
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        let parsed: HashSet<(String, u64)> = parsed
+            .iter()
+            .map(|pair| {
+                (
+                    pair["value"].as_str().unwrap().to_string(),
+                    pair["entity"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(parsed, expected);
+    }
+}
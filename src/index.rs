@@ -1,11 +1,25 @@
-use crate::storage::IndexStorage;
+use crate::plugin::PluginAdded;
+use crate::storage::{
+    AggregateStorage, Bucket, CachedStorage, EntitySetStorage, GroupMaxStorage, HashmapStorage,
+    IndexStorage, InsertionOrderStorage, InternedStorage, Measure, OrderedStorage, SeededHashSet,
+    SeededStorage, SharedStorage,
+};
+use crate::values::{TotalF32, TuplePrefix};
+#[cfg(debug_assertions)]
+use bevy::core::FrameCount;
 use bevy::ecs::archetype::Archetype;
 use bevy::ecs::component::Tick;
-use bevy::ecs::system::{ReadOnlySystemParam, StaticSystemParam, SystemMeta, SystemParam};
+use bevy::ecs::system::{
+    ReadOnlySystemParam, StaticSystemParam, SystemMeta, SystemParam, SystemParamItem,
+};
 use bevy::ecs::world::unsafe_world_cell::UnsafeWorldCell;
+use bevy::ecs::world::WorldId;
 use bevy::prelude::*;
-use bevy::utils::HashSet;
-use std::hash::Hash;
+use bevy::utils::{EntityHashSet, FixedState, HashMap, HashSet};
+use indexmap::IndexSet;
+use std::hash::{BuildHasher, Hash};
+use std::ops::RangeBounds;
+use std::sync::Arc;
 
 /// Implement this trait on your own types to specify how an index should behave.
 ///
@@ -14,255 +28,4263 @@ use std::hash::Hash;
 /// unit struct/enum.
 pub trait IndexInfo: Sized + 'static {
     /// The type of component to be indexed.
+    ///
+    /// Refreshing checks the change tick of every one of these components, visiting each
+    /// matching table in turn, so the cost of a refresh grows with the number of entities
+    /// that have the component rather than with the number that changed. Components with
+    /// the default table storage are checked in contiguous runs; components stored in a
+    /// [`SparseSet`](bevy::ecs::component::StorageType::SparseSet) are looked up one entity
+    /// at a time, which is noticeably slower to refresh for large indexes. To only visit some
+    /// of the archetypes with the component, use a
+    /// [`FilteredStorage`](crate::storage::FilteredStorage).
     type Component: Component;
     /// The type of value to be used when looking up components.
+    ///
+    /// Values are kept in the index's storage [`Resource`], so they must be `'static`. This
+    /// is already enforced by `IndexInfo`'s own `'static` bound, since an index type with a
+    /// lifetime can't implement the trait at all. Use owned types, e.g. `String` rather than
+    /// `&str`, instead of borrowing from the component:
+    /// ```
+    /// # use bevy::prelude::*;
+    /// # use bevy_mod_index::prelude::*;
+    /// struct NameString;
+    /// impl IndexInfo for NameString {
+    ///     type Component = Name;
+    ///     type Value = String;
+    ///     type Storage = HashmapStorage<Self>;
+    ///     type ValueParam = ();
+    ///     fn value(c: &Name, _: &()) -> String {
+    ///         c.as_str().to_owned()
+    ///     }
+    /// }
+    /// ```
     type Value: Send + Sync + Hash + Eq + Clone;
     /// The type of storage to use for the index.
+    ///
+    /// Storage is kept in a [`Resource`], so each index must use its own storage type.
+    /// This is automatic for the storages in this crate since they are generic over the
+    /// `IndexInfo`, and in debug builds, two indexes sharing a storage type will panic.
     type Storage: IndexStorage<Self>;
+    /// Additional data from the world that is passed to [`value`][Self::value], such as
+    /// a [`Resource`]. Use `()` if the value only depends on the component.
+    ///
+    /// This is fetched alongside the index, so its access is registered like any other
+    /// parameter of the systems that use the index.
+    ///
+    /// Values are only re-computed for components that changed, so with a caching storage
+    /// like [`HashmapStorage`], changes to this data are not reflected for other components.
+    /// It is best suited for data that rarely changes, like configuration; use
+    /// [`NoStorage`][crate::storage::NoStorage] if it changes often.
+    ///
+    /// ```
+    /// # use bevy::prelude::*;
+    /// # use bevy_mod_index::prelude::*;
+    /// #[derive(Resource)]
+    /// struct CameraPosition(Vec3);
+    ///
+    /// /// Indexes entities by their distance from the camera, in 10 unit increments.
+    /// struct CameraDistance;
+    ///
+    /// impl IndexInfo for CameraDistance {
+    ///     type Component = Transform;
+    ///     type Value = u32;
+    ///     type Storage = NoStorage<Self>;
+    ///     type ValueParam = Res<'static, CameraPosition>;
+    ///
+    ///     fn value(t: &Transform, camera: &Res<CameraPosition>) -> u32 {
+    ///         (t.translation.distance(camera.0) / 10.0) as u32
+    ///     }
+    /// }
+    /// ```
+    type ValueParam: ReadOnlySystemParam + 'static;
 
     /// The function used by [`Index::lookup`] to determine the value of a component.
     ///
     /// The values returned by this function are typically cached by the storage, so
     /// this should always return the same value given equal Components.
-    fn value(c: &Self::Component) -> Self::Value;
+    fn value(c: &Self::Component, param: &SystemParamItem<Self::ValueParam>) -> Self::Value;
+
+    /// Like [`value`][Self::value], but allows value extraction to fail.
+    ///
+    /// Components for which this returns `None` are indexed under
+    /// [`fallback_value`][Self::fallback_value] if there is one, and are left out of the
+    /// index otherwise. If you override this, [`value`][Self::value] is no longer
+    /// used by this crate.
+    ///
+    /// Defaults to always succeeding with [`value`][Self::value].
+    fn try_value(
+        c: &Self::Component,
+        param: &SystemParamItem<Self::ValueParam>,
+    ) -> Option<Self::Value> {
+        Some(Self::value(c, param))
+    }
+
+    /// Like [`try_value`][Self::try_value], but also given the entity that has the component.
+    ///
+    /// Override this for values that depend on the entity rather than only the component's
+    /// data, such as for marker components without any data. As with
+    /// [`ValueParam`][Self::ValueParam], the value is only re-computed when the component
+    /// changes. If you override this, [`value`][Self::value] and
+    /// [`try_value`][Self::try_value] are no longer used by this crate, so `value` can be
+    /// left `unreachable!()`.
+    ///
+    /// ```
+    /// # use bevy::prelude::*;
+    /// # use bevy::ecs::system::SystemParamItem;
+    /// # use bevy_mod_index::prelude::*;
+    /// #[derive(Component)]
+    /// struct Spawner;
+    ///
+    /// /// Spreads spawners over 4 groups by entity, so each group can be updated on a
+    /// /// different frame.
+    /// impl IndexInfo for Spawner {
+    ///     type Component = Self;
+    ///     type Value = u32;
+    ///     type Storage = HashmapStorage<Self>;
+    ///     type ValueParam = ();
+    ///
+    ///     fn value(_: &Self, _: &()) -> u32 {
+    ///         unreachable!("value_for_entity is overridden")
+    ///     }
+    ///
+    ///     fn value_for_entity(entity: Entity, _: &Self, _: &()) -> Option<u32> {
+    ///         Some(entity.index() % 4)
+    ///     }
+    /// }
+    /// ```
+    fn value_for_entity(
+        _entity: Entity,
+        c: &Self::Component,
+        param: &SystemParamItem<Self::ValueParam>,
+    ) -> Option<Self::Value> {
+        Self::try_value(c, param)
+    }
+
+    /// The entity that this component's value depends on, if any.
+    ///
+    /// Only used by [`DependentStorage`][crate::storage::DependentStorage], which re-indexes
+    /// the component whenever the referenced entity changes. Defaults to `None`.
+    fn dependency(_c: &Self::Component) -> Option<Entity> {
+        None
+    }
+
+    /// All of the entities that this component's value depends on, for values that are
+    /// computed from several other entities, like an aggregate over an entity's [`Children`].
+    ///
+    /// Only used by [`DependentStorage`][crate::storage::DependentStorage]. Defaults to the
+    /// [`dependency`][Self::dependency], if any.
+    fn dependencies(c: &Self::Component) -> impl Iterator<Item = Entity> {
+        Self::dependency(c).into_iter()
+    }
+
+    /// Whether manually [refreshing][Index::refresh] an index picks up changes made earlier
+    /// in the same system.
+    ///
+    /// Set this to `false` for standard bevy change detection semantics, where a system
+    /// never sees its own changes, to avoid feedback loops in systems that both mutate
+    /// and look up indexed components. Those changes are still seen by the next refresh
+    /// in any other system. Has no effect on [`NoStorage`][crate::storage::NoStorage],
+    /// which always reads the world directly.
+    ///
+    /// Defaults to `true`.
+    const SEE_OWN_CHANGES: bool = true;
+
+    /// When the index is refreshed.
+    ///
+    /// With [`RefreshPolicy::Lazy`], the index is refreshed every time a system that uses it
+    /// runs, so lookups are always up to date, but every such system pays to check for
+    /// changes. With [`RefreshPolicy::Eager`], only the system added by
+    /// [`IndexPlugin`][crate::plugin::IndexPlugin] refreshes the index, so lookups are cheap
+    /// but only see changes made before the plugin's system last ran. Eager indexes panic
+    /// when used without the plugin, and in debug builds warn once if the plugin's system
+    /// goes a full frame without running after the index is first used. Debug builds also
+    /// check in [`lookup`][Index::lookup] that a plugin refreshing in [`First`] has already
+    /// refreshed the index this frame.
+    ///
+    /// Either way, [`refresh`][Index::refresh] can still be called manually.
+    ///
+    /// Defaults to [`RefreshPolicy::Lazy`].
+    const REFRESH: RefreshPolicy = RefreshPolicy::Lazy;
+
+    /// Whether [`HashmapStorage`] remembers the tick that each entity's value last changed
+    /// at, for [`Index::changed_since`]. This costs an extra map update for every change.
+    ///
+    /// Defaults to `false`.
+    const TRACK_CHANGE_TICKS: bool = false;
+
+    /// Whether [`HashmapStorage`] keeps track of the entities that entered, exited or changed
+    /// value in the index each frame, for [`Index::entered`], [`Index::exited`] and
+    /// [`Index::take_single_changed`]. This costs a few extra set updates for every change,
+    /// and makes refreshes read bevy's [frame count][bevy::core::FrameCount].
+    ///
+    /// Defaults to `false`.
+    const TRACK_TRANSITIONS: bool = false;
+
+    /// How many of the most recent changes made by refreshes to keep for
+    /// [`Index::recent_changes`], for indexes using [`HashmapStorage`].
+    ///
+    /// Defaults to 0, which records nothing.
+    #[cfg(feature = "history")]
+    const HISTORY_LEN: usize = 0;
+
+    /// The value to index components under when [`try_value`][Self::try_value] fails,
+    /// so that every entity with the component stays discoverable.
+    ///
+    /// Defaults to `None`, which leaves those entities out of the index.
+    fn fallback_value() -> Option<Self::Value> {
+        None
+    }
+
+    /// Whether components with the given value should be indexed at all.
+    ///
+    /// When this returns `false`, the entity is removed from the index entirely rather than
+    /// being kept under that value. This is useful to keep e.g. a `Dead` state out of an
+    /// index over state machine components.
+    ///
+    /// Defaults to always indexing.
+    fn should_index(_value: &Self::Value) -> bool {
+        true
+    }
+}
+
+/// The value that `entity`'s component should be indexed under, if any.
+pub(crate) fn indexed_value<I: IndexInfo>(
+    entity: Entity,
+    c: &I::Component,
+    param: &SystemParamItem<I::ValueParam>,
+) -> Option<I::Value> {
+    I::value_for_entity(entity, c, param)
+        .or_else(I::fallback_value)
+        .filter(I::should_index)
+}
+
+/// When an index is refreshed. See [`IndexInfo::REFRESH`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefreshPolicy {
+    /// Refresh whenever a system that uses the index runs.
+    Lazy,
+    /// Refresh only when the [`IndexPlugin`][crate::plugin::IndexPlugin]'s system runs.
+    Eager,
 }
 
 /// A [`SystemParam`] that allows you to lookup [`Component`]s that match a certain value.
+///
+/// Outside of an [`App`], e.g. in unit tests, an index can be used from a closure with
+/// [`run_system_once`][bevy::ecs::system::RunSystemOnce::run_system_once]. The index's
+/// storage lives in the [`World`], so it stays up to date across calls:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy::ecs::system::RunSystemOnce;
+/// # use bevy_mod_index::prelude::*;
+/// let mut world = World::new();
+/// let entity = world.spawn(Name::new("Player")).id();
+/// let found = world.run_system_once(|mut idx: Index<NameIndex>| {
+///     idx.lookup(&"Player".to_string())
+/// });
+/// assert!(found.contains(&entity));
+/// ```
+///
+/// Each [`World`] keeps its own storage for an index, so apps that run several worlds, like
+/// a server hosting multiple game instances, can use the same index type in all of them
+/// without the indexes seeing each other's entities. Use [`label`][Index::label] to tell
+/// them apart in logs and tooling.
+///
+/// Refreshing writes to the index's storage [`Resource`] and reads every indexed component,
+/// so bevy never runs two systems that use the same `Index` at the same time, nor one that
+/// uses it alongside a system that mutates the indexed component. For indexes that many
+/// systems read, refresh them in one place with an
+/// [`IndexPlugin`][crate::plugin::IndexPlugin] and look them up with [`ReadIndex`] instead,
+/// which only reads the storage and so can run in parallel with other readers.
 pub struct Index<'w, 's, I: IndexInfo + 'static> {
-    storage: ResMut<'w, I::Storage>,
-    refresh_data:
+    pub(crate) storage: ResMut<'w, I::Storage>,
+    pub(crate) refresh_data:
         StaticSystemParam<'w, 's, <I::Storage as IndexStorage<I>>::RefreshData<'static, 'static>>,
+    world_id: WorldId,
+    refreshed_this_frame: bool,
+}
+
+/// A label for the index `I` in the world `world`, which is distinct for each index type and
+/// world, for telling indexes apart when running several worlds.
+///
+/// This is the index's type name followed by the world's id, e.g. `my_game::Health@WorldId(0)`.
+pub fn index_label<I: IndexInfo>(world: WorldId) -> String {
+    format!("{}@{:?}", std::any::type_name::<I>(), world)
 }
 
 // todo impl deref instead? need to move storage?
 impl<'w, 's, I: IndexInfo> Index<'w, 's, I> {
     /// Get all of the entities with relevant components that evaluate to the given value
     /// using [`I::value`][`IndexInfo::value`].
+    ///
+    /// In debug builds, this panics for [`RefreshPolicy::Eager`] indexes whose plugin refreshes
+    /// in [`First`] but hasn't refreshed them yet this frame, e.g. when looked up in
+    /// [`Startup`], unless [`refresh`][Index::refresh] was called manually.
     pub fn lookup(&mut self, val: &I::Value) -> HashSet<Entity> {
+        debug_assert!(
+            self.refreshed_this_frame,
+            "{} uses RefreshPolicy::Eager, but was looked up before the system added by \
+             IndexPlugin::<{0}> refreshed it this frame.",
+            std::any::type_name::<I>()
+        );
         self.storage.lookup(val, &mut self.refresh_data)
     }
 
+    /// Like [`lookup`][Index::lookup], but takes anything that converts into the value, so
+    /// that small values can be passed directly, e.g. `idx.lookup_into(10)` for a value type
+    /// that implements `From<usize>`.
+    ///
+    /// The converted value is dropped after the lookup, so prefer [`lookup`][Index::lookup]
+    /// for values that are expensive to build or that you already have.
+    pub fn lookup_into(&mut self, val: impl Into<I::Value>) -> HashSet<Entity> {
+        self.lookup(&val.into())
+    }
+
+    /// Like [`lookup`][Index::lookup], but takes the value by value and drops it afterwards.
+    ///
+    /// This helps when the value is built only for the lookup, e.g. a `String` from
+    /// `format!`, and the caller has no further use for it: the value can be passed
+    /// straight in instead of being bound to a variable to borrow from. It never saves a
+    /// clone of a value the caller still needs afterwards; use [`lookup`][Index::lookup]
+    /// for those.
+    pub fn lookup_owned(&mut self, val: I::Value) -> HashSet<Entity> {
+        self.lookup(&val)
+    }
+
+    /// Panic if the number of entities with the given value isn't `expected`, e.g. in tests
+    /// of systems that use the index.
+    ///
+    /// The panic message includes the whole storage, formatted with `{:#?}`.
+    ///
+    /// This only checks in debug builds, and does nothing when `debug_assertions` are off.
+    #[track_caller]
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub fn assert_count(&mut self, val: &I::Value, expected: usize)
+    where
+        I::Value: std::fmt::Debug,
+        I::Storage: std::fmt::Debug,
+    {
+        #[cfg(debug_assertions)]
+        {
+            let found = self.lookup(val).len();
+            assert_eq!(
+                found,
+                expected,
+                "{} has {} entities with value {:?}, expected {}\nindex contents: {:#?}",
+                std::any::type_name::<I>(),
+                found,
+                val,
+                expected,
+                *self.storage,
+            );
+        }
+    }
+
+    /// Get all of the entities with the given value, or the entities with `fallback` if
+    /// there are none.
+    ///
+    /// The two sets are not merged; the fallback's entities are only returned in place of
+    /// an empty lookup.
+    pub fn lookup_or(&mut self, val: &I::Value, fallback: &I::Value) -> HashSet<Entity> {
+        let set = self.lookup(val);
+        if set.is_empty() {
+            self.lookup(fallback)
+        } else {
+            set
+        }
+    }
+
+    /// Look up `val` in this index, then look up the entities of `other` under the values
+    /// that `key` derives from each of them, for lookups that go through two levels like
+    /// "members of the squads in this platoon".
+    ///
+    /// Entities that derive the same value are only looked up in `other` once, and the
+    /// result is the union of all of those lookups.
+    pub fn lookup_then<U: IndexInfo>(
+        &mut self,
+        val: &I::Value,
+        other: &mut Index<U>,
+        key: impl Fn(Entity) -> U::Value,
+    ) -> HashSet<Entity> {
+        let keys: HashSet<U::Value> = self.lookup(val).into_iter().map(key).collect();
+        keys.iter().flat_map(|k| other.lookup(k)).collect()
+    }
+
+    /// Get the first of `vals` that has any entities, along with its entities.
+    ///
+    /// Candidates are looked up in order and the rest are skipped once one is found, so
+    /// this is useful for fallback chains like "nearest tier with any members". Returns
+    /// `None` if every candidate is empty.
+    pub fn lookup_first<'a>(
+        &mut self,
+        vals: impl IntoIterator<Item = &'a I::Value>,
+    ) -> Option<(I::Value, HashSet<Entity>)>
+    where
+        I::Value: 'a,
+    {
+        vals.into_iter().find_map(|val| {
+            let set = self.lookup(val);
+            (!set.is_empty()).then(|| (val.clone(), set))
+        })
+    }
+
+    /// The id of the [`World`] that this index's storage is in.
+    pub fn world_id(&self) -> WorldId {
+        self.world_id
+    }
+
+    /// A label for this index that is distinct across worlds. See [`index_label`].
+    pub fn label(&self) -> String {
+        index_label::<I>(self.world_id)
+    }
+
     /// Refresh the underlying [`IndexStorage`] for this index.
     ///
     /// This may or may not be necessary to call manually depending on the particular [`IndexStorage`] used.
     pub fn refresh(&mut self) {
-        self.storage.refresh(&mut self.refresh_data)
+        self.storage.refresh(&mut self.refresh_data);
+        self.refreshed_this_frame = true;
     }
-}
 
-#[doc(hidden)]
-pub struct IndexFetchState<'w, 's, I: IndexInfo + 'static> {
-    storage_state: <ResMut<'w, I::Storage> as SystemParam>::State,
-    refresh_data_state: <StaticSystemParam<
-        'w,
-        's,
-        <I::Storage as IndexStorage<I>>::RefreshData<'static, 'static>,
-    > as SystemParam>::State,
-}
-unsafe impl<'w, 's, I> SystemParam for Index<'w, 's, I>
-where
-    I: IndexInfo + 'static,
-{
-    type State = IndexFetchState<'static, 'static, I>;
-    type Item<'_w, '_s> = Index<'_w, '_s, I>;
-    fn init_state(world: &mut World, system_meta: &mut SystemMeta) -> Self::State {
-        world.init_resource::<I::Storage>();
-        IndexFetchState {
-            storage_state: <ResMut<'w, I::Storage> as SystemParam>::init_state(world, system_meta),
-            refresh_data_state: <StaticSystemParam<
-                'w,
-                's,
-                <I::Storage as IndexStorage<I>>::RefreshData<'static, 'static>,
-            > as SystemParam>::init_state(world, system_meta),
-        }
-    }
-    fn new_archetype(state: &mut Self::State, archetype: &Archetype, system_meta: &mut SystemMeta) {
-        <ResMut<'w, I::Storage> as SystemParam>::new_archetype(
-            &mut state.storage_state,
-            archetype,
-            system_meta,
-        );
-        <StaticSystemParam<'w, 's, <I::Storage as IndexStorage<I>>::RefreshData<'static, 'static>> as SystemParam>::new_archetype(
-            &mut state.refresh_data_state,
-            archetype,
-            system_meta,
-        );
+    /// Make the next refresh reconcile the whole index with the world, e.g. after
+    /// [`swap`][Self::swap] or tooling edited it directly. See [`IndexStorage::invalidate`].
+    ///
+    /// The index has already been refreshed for this system, so call
+    /// [`refresh`][Self::refresh] afterwards to reconcile it right away.
+    pub fn invalidate(&mut self) {
+        self.storage.invalidate()
     }
-    fn apply(state: &mut Self::State, system_meta: &SystemMeta, world: &mut World) {
-        <ResMut<'w, I::Storage> as SystemParam>::apply(
-            &mut state.storage_state,
-            system_meta,
-            world,
-        );
-        <StaticSystemParam<'w, 's, <I::Storage as IndexStorage<I>>::RefreshData<'static, 'static>> as SystemParam>::apply(
-            &mut state.refresh_data_state,
-            system_meta,
-            world,
-        );
+
+    /// Get a lightweight [`IndexHandle`] for this index, which can be passed around
+    /// to helper functions without naming the full `Index` type.
+    ///
+    /// The handle reads the index as of its last refresh; it does not refresh on its own.
+    pub fn handle(&mut self) -> IndexHandle<'_, 'w, 's, I> {
+        IndexHandle { index: self }
     }
-    unsafe fn get_param<'w2, 's2>(
-        state: &'s2 mut Self::State,
-        system_meta: &SystemMeta,
-        world: UnsafeWorldCell<'w2>,
-        change_tick: Tick,
-    ) -> Self::Item<'w2, 's2> {
-        let mut res = Index {
-            storage: <ResMut<'w, I::Storage>>::get_param(
-                &mut state.storage_state,
-                system_meta,
-                world,
-                change_tick,
-            ),
-            refresh_data: <StaticSystemParam<
-                'w,
-                's,
-                <I::Storage as IndexStorage<I>>::RefreshData<'static, 'static>,
-            > as SystemParam>::get_param(
-                &mut state.refresh_data_state,
-                system_meta,
-                world,
-                change_tick,
-            ),
-        };
-        res.refresh();
-        res
+}
+
+/// A borrowed handle to an [`Index`] with shorthand lookup methods.
+/// Created with [`Index::handle`].
+pub struct IndexHandle<'a, 'w, 's, I: IndexInfo + 'static> {
+    index: &'a mut Index<'w, 's, I>,
+}
+
+impl<'a, 'w, 's, I: IndexInfo> IndexHandle<'a, 'w, 's, I> {
+    /// Get all of the entities with the given value. See [`Index::lookup`].
+    pub fn get(&mut self, val: &I::Value) -> HashSet<Entity> {
+        self.index.lookup(val)
+    }
+
+    /// Check whether `entity` has the given value.
+    pub fn contains(&mut self, val: &I::Value, entity: Entity) -> bool {
+        self.index.lookup(val).contains(&entity)
     }
 }
-unsafe impl<'w, 's, I: IndexInfo + 'static> ReadOnlySystemParam for Index<'w, 's, I>
+
+impl<'a, 'w, 's, I, B> IndexHandle<'a, 'w, 's, I>
 where
-    ResMut<'w, I::Storage>: ReadOnlySystemParam,
-    StaticSystemParam<'w, 's, <I::Storage as IndexStorage<I>>::RefreshData<'static, 'static>>:
-        ReadOnlySystemParam,
+    I: IndexInfo<Storage = HashmapStorage<I, B>>,
+    B: Bucket<Entity>,
 {
+    /// Get the number of entities with the given value, without copying them.
+    pub fn count(&self, val: &I::Value) -> usize {
+        self.index
+            .storage
+            .map_ref()
+            .get_ref(val)
+            .map_or(0, Bucket::len)
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use crate::prelude::*;
-    use bevy::prelude::*;
+impl<'w, 's, I, B> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = HashmapStorage<I, B>>,
+    B: Bucket<Entity>,
+{
+    /// Get the value that `entity` is indexed under, or `None` if it isn't in the index.
+    ///
+    /// This clones the value; see [`value_ref_of`][Self::value_ref_of] to borrow it instead.
+    pub fn value_of(&mut self, entity: Entity) -> Option<I::Value> {
+        self.storage.value_of(entity).cloned()
+    }
 
-    #[derive(Component, Clone, Eq, Hash, PartialEq, Debug)]
-    struct Number(usize);
+    /// Whether `entity` is in the index under any value, i.e. it has the indexed component
+    /// and its value wasn't filtered out.
+    ///
+    /// Like [`value_of`][Self::value_of], this reflects the index as of its last refresh,
+    /// which lazy indexes do whenever they are fetched.
+    pub fn contains_entity(&mut self, entity: Entity) -> bool {
+        self.storage.value_of(entity).is_some()
+    }
 
-    //todo: maybe make this a derive macro
-    impl IndexInfo for Number {
-        type Component = Self;
-        type Value = Self;
-        type Storage = HashmapStorage<Self>;
+    /// Like [`value_of`][Self::value_of], but borrows the value from the index instead
+    /// of cloning it.
+    ///
+    /// The returned reference borrows the whole `Index`, so it must be dropped before the
+    /// index can be used again.
+    pub fn value_ref_of(&mut self, entity: Entity) -> Option<&I::Value> {
+        self.storage.value_of(entity)
+    }
 
-        fn value(c: &Self::Component) -> Self::Value {
-            c.clone()
-        }
+    /// Immediately move `entity` into the bucket for `new_value`, without waiting for
+    /// the next refresh.
+    ///
+    /// This is useful when game logic has just changed an entity's component and needs
+    /// later lookups in the same system to reflect it. Refreshing only re-computes values
+    /// for changed components, so the next refresh will not undo this unless the component
+    /// is changed again afterwards, in which case [`I::value`][`IndexInfo::value`] wins.
+    ///
+    /// The edit that prompted the update still marks the component as changed, so the next
+    /// refresh re-computes its value as usual. When `new_value` matches what
+    /// [`I::value`][`IndexInfo::value`] returns, that finds the entity already in the right
+    /// bucket and leaves it there, so nothing is applied twice. When it doesn't match, the
+    /// refresh moves the entity to the computed value.
+    #[doc(alias = "note_changed")]
+    pub fn update(&mut self, entity: Entity, new_value: I::Value) {
+        self.storage.update(entity, &new_value)
     }
 
-    fn add_some_numbers(mut commands: Commands) {
-        commands.spawn(Number(10));
-        commands.spawn(Number(10));
-        commands.spawn(Number(20));
-        commands.spawn(Number(30));
+    /// Immediately exchange the buckets of `a` and `b`, e.g. when they trade places, without
+    /// waiting for their components to change and the next refresh.
+    ///
+    /// If only one of them is in the index, the other is moved into its bucket and it is
+    /// removed. Like [`update`][Self::update], this makes the index disagree with the
+    /// components until they are changed to match, at which point the next refresh
+    /// re-computes their values as usual. Until then, lookups return the swapped buckets,
+    /// and [`verify`][Self::verify] reports both entities.
+    pub fn swap(&mut self, a: Entity, b: Entity) {
+        self.storage.swap(a, b)
     }
 
-    fn checker(number: usize, amount: usize) -> impl Fn(Index<Number>) {
-        move |mut idx: Index<Number>| {
-            let set = idx.lookup(&Number(number));
-            assert_eq!(
-                set.len(),
-                amount,
-                "Index returned {} matches for {}, expectd {}.",
-                set.len(),
-                number,
-                amount,
-            );
-        }
+    /// [`Refresh`][Index::refresh] the index, first reserving space for all of the newly
+    /// added components.
+    ///
+    /// This checks every component for changes one extra time, but avoids growing the map
+    /// several times when a large batch of entities is added at once, e.g. when a level is
+    /// loaded. For small batches, a plain refresh is faster.
+    pub fn refresh_reserving(&mut self) {
+        self.storage.reserve_for_refresh(&self.refresh_data);
+        self.refresh();
     }
 
-    fn adder_all(n: usize) -> impl Fn(Query<&mut Number>) {
-        move |mut nums: Query<&mut Number>| {
-            for mut num in &mut nums {
-                num.0 += n;
-            }
-        }
+    /// Refresh only `entities`, instead of checking every component in the index.
+    ///
+    /// This bounds the work done when you already know which entities may have changed,
+    /// e.g. from the output of a physics step. Only entities whose components changed since
+    /// the last full [`refresh`][Index::refresh] are re-computed, and entities without the
+    /// component are removed. Other entities are left as they are, and are still updated by
+    /// the next full refresh. It is mostly useful for indexes with
+    /// [`RefreshPolicy::Eager`], since lazy indexes are fully refreshed whenever they are used.
+    pub fn refresh_entities(&mut self, entities: &[Entity]) {
+        self.storage.refresh_entities(entities, &self.refresh_data)
     }
 
-    fn adder_some(
-        n: usize,
-        condition: usize,
-    ) -> impl Fn(ParamSet<(Query<&mut Number>, Index<Number>)>) {
-        move |mut nums_and_index: ParamSet<(Query<&mut Number>, Index<Number>)>| {
-            for entity in nums_and_index.p1().lookup(&Number(condition)).into_iter() {
-                let mut nums = nums_and_index.p0();
-                let mut nref: Mut<Number> = nums.get_mut(entity).unwrap();
-                nref.0 += n;
-            }
-        }
+    /// Drive refreshes of this index from a channel of entities whose values may have
+    /// changed, instead of checking every component, e.g. the bodies reported as moved by a
+    /// physics engine.
+    ///
+    /// After one full refresh, each [`refresh`][Index::refresh] drains the entities waiting
+    /// in `receiver` and re-indexes just those, like
+    /// [`refresh_entities`][Self::refresh_entities], along with removing entities whose
+    /// component was removed. Changes to entities that are never sent are missed until the
+    /// channel is removed with [`clear_refresh_channel`][Self::clear_refresh_channel].
+    ///
+    /// The senders can be used from any thread. The receiver is kept in the index's storage
+    /// behind a [`Mutex`][std::sync::Mutex] and drained without blocking by whichever
+    /// system refreshes the index, so entities sent while a refresh is running may only be
+    /// seen by the next one. Replacing or removing the channel drops any entities still in
+    /// it.
+    #[cfg(feature = "channel")]
+    pub fn set_refresh_channel(&mut self, receiver: std::sync::mpsc::Receiver<Entity>) {
+        self.storage.set_refresh_channel(Some(receiver));
     }
 
-    #[test]
-    fn test_index_lookup() {
-        App::new()
-            .add_systems(Startup, add_some_numbers)
-            .add_systems(Update, checker(10, 2))
-            .add_systems(Update, checker(20, 1))
-            .add_systems(Update, checker(30, 1))
-            .add_systems(Update, checker(40, 0))
-            .run();
+    /// Go back to checking every component when refreshing, after
+    /// [`set_refresh_channel`][Self::set_refresh_channel].
+    ///
+    /// Changes made while the channel was set are only seen by a full refresh if their
+    /// components were changed since the last full refresh, which the next refresh does.
+    #[cfg(feature = "channel")]
+    pub fn clear_refresh_channel(&mut self) {
+        self.storage.set_refresh_channel(None);
     }
 
-    #[test]
-    fn test_changing_values() {
-        App::new()
-            .add_systems(Startup, add_some_numbers)
-            .add_systems(PreUpdate, checker(10, 2))
-            .add_systems(PreUpdate, checker(20, 1))
-            .add_systems(PreUpdate, checker(30, 1))
-            .add_systems(Update, adder_all(5))
-            .add_systems(PostUpdate, checker(10, 0))
-            .add_systems(PostUpdate, checker(20, 0))
-            .add_systems(PostUpdate, checker(30, 0))
-            .add_systems(PostUpdate, checker(15, 2))
-            .add_systems(PostUpdate, checker(25, 1))
-            .add_systems(PostUpdate, checker(35, 1))
-            .run();
+    /// Remove every entity with the given value from the index, returning them.
+    ///
+    /// This is useful for work queues, where entities accumulate under a value until they
+    /// are all processed at once. Refreshing only re-computes values for changed components,
+    /// so the taken entities stay out of the index until their component changes again.
+    /// Changes made earlier in the same system still count, since the next refresh
+    /// sees them.
+    pub fn take(&mut self, val: &I::Value) -> HashSet<Entity> {
+        let tick = self.refresh_data.ticks.this_run();
+        self.storage
+            .take(val, tick)
+            .map(|bucket| bucket.into_iter().collect())
+            .unwrap_or_default()
     }
 
-    #[test]
-    fn test_changing_with_index() {
-        App::new()
-            .add_systems(Startup, add_some_numbers)
-            .add_systems(PreUpdate, checker(10, 2))
-            .add_systems(PreUpdate, checker(20, 1))
-            .add_systems(Update, adder_some(10, 10))
-            .add_systems(PostUpdate, checker(10, 0))
-            .add_systems(PostUpdate, checker(20, 3))
-            .run();
+    /// Remove every mapping from the index, returning them as `(value, entity)` pairs.
+    ///
+    /// Note that this also resets the index's refresh state, so the next refresh (e.g. when
+    /// the index is used in another system) re-indexes every component that still exists,
+    /// changed or not. To keep an entity from reappearing, remove its component or change it
+    /// so that it is no longer indexed.
+    pub fn drain(&mut self) -> impl Iterator<Item = (I::Value, Entity)> + '_ {
+        self.storage.drain()
     }
 
-    #[test]
-    fn test_same_system_detection() {
-        let manual_refresh_system =
-            |mut nums_and_index: ParamSet<(Query<&mut Number>, Index<Number>)>| {
-                let mut idx = nums_and_index.p1();
-                let twenties = idx.lookup(&Number(20));
-                assert_eq!(twenties.len(), 1);
+    /// Get the number of entities in each bucket of this index.
+    ///
+    /// Useful for finding values that hold a disproportionate number of entities.
+    pub fn bucket_sizes(&mut self) -> BucketSizes<I::Value> {
+        let mut sizes = self.storage.bucket_sizes();
+        sizes.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+        BucketSizes {
+            total: sizes.iter().map(|(_, n)| n).sum(),
+            max: sizes.first().map(|(_, n)| *n).unwrap_or(0),
+            sizes,
+        }
+    }
 
-                for entity in twenties.into_iter() {
-                    nums_and_index.p0().get_mut(entity).unwrap().0 += 5;
-                }
-                idx = nums_and_index.p1(); // reborrow here so earlier p0 borrow succeeds
+    /// Get all of the entities with the given value, along with their indexed component.
+    ///
+    /// This saves a separate query when the component is needed anyway, and skips any
+    /// entities that no longer have the component.
+    pub fn lookup_with<'a>(
+        &'a mut self,
+        val: &I::Value,
+    ) -> impl Iterator<Item = (Entity, &'a I::Component)> + use<'a, 'w, 's, I, B> {
+        self.storage.lookup_with(val, &self.refresh_data)
+    }
 
-                // Already refreshed
-                assert_eq!(idx.lookup(&Number(20)).len(), 0);
-                assert_eq!(idx.lookup(&Number(25)).len(), 1);
+    /// Get the entities that entered the index this frame, by gaining the indexed component
+    /// or a value for it, and are still in it. Like [`Added`], but through the index.
+    ///
+    /// Frames are counted by bevy's [`FrameCount`], which is included in the
+    /// `MinimalPlugins` and `DefaultPlugins`. Without it, this only includes the entities
+    /// that entered in the last refresh. An entity that entered and exited within the same
+    /// frame, or the other way around, is in neither [`entered`][Self::entered] nor
+    /// [`exited`][Self::exited].
+    ///
+    /// Panics unless [`IndexInfo::TRACK_TRANSITIONS`] is set.
+    pub fn entered(&mut self) -> Vec<Entity> {
+        self.assert_tracks_transitions("entered");
+        self.storage.entered()
+    }
 
-                idx.refresh();
-                assert_eq!(idx.lookup(&Number(20)).len(), 0);
-                assert_eq!(idx.lookup(&Number(25)).len(), 1);
-            };
+    /// Get the entities that exited the index this frame, by losing the indexed component,
+    /// being despawned or being [taken][Self::take], and haven't entered it again. Like
+    /// [`RemovedComponents`], but through the index. See [`entered`][Self::entered] for how
+    /// frames are counted.
+    ///
+    /// Panics unless [`IndexInfo::TRACK_TRANSITIONS`] is set.
+    pub fn exited(&mut self) -> Vec<Entity> {
+        self.assert_tracks_transitions("exited");
+        self.storage.exited()
+    }
 
-        App::new()
-            .add_systems(Startup, add_some_numbers)
-            .add_systems(Update, manual_refresh_system)
-            .run();
+    /// Take the only entity that was given a new value this frame, e.g. the one that was
+    /// just selected, so that later calls in the same frame return `None`. Entering the
+    /// index counts as being given a value. Frames are counted like in
+    /// [`entered`][Self::entered].
+    ///
+    /// Returns `None` if no entity changed, and also logs a warning if several did, since
+    /// this is meant for workflows where at most one entity changes per frame. Panics unless
+    /// [`IndexInfo::TRACK_TRANSITIONS`] is set.
+    pub fn take_single_changed(&mut self) -> Option<Entity> {
+        self.assert_tracks_transitions("take_single_changed");
+        let changed = self.storage.changed_this_frame();
+        match changed.len() {
+            0 => None,
+            1 => {
+                let entity = *changed.iter().next().unwrap();
+                self.storage.forget_changed(entity);
+                Some(entity)
+            }
+            n => {
+                warn!(
+                    "{}: expected at most one changed entity, but {} changed this frame",
+                    self.label(),
+                    n
+                );
+                None
+            }
+        }
+    }
+
+    fn assert_tracks_transitions(&self, method: &str) {
+        assert!(
+            I::TRACK_TRANSITIONS,
+            "{}: {} needs IndexInfo::TRACK_TRANSITIONS",
+            self.label(),
+            method
+        );
+    }
+
+    /// Get the entities whose value changed in a refresh after `tick`, along with their
+    /// current values, e.g. to replicate only what changed since a client last acknowledged
+    /// an update. Pass the [`SystemChangeTick::this_run`][bevy::ecs::system::SystemChangeTick::this_run]
+    /// of the system that sent it.
+    ///
+    /// An entity is included once however many times its value changed, and entities that
+    /// were removed from the index are not included. Like [`Tick`]s in general, `tick`
+    /// must be no older than about a billion ticks to be compared correctly.
+    ///
+    /// Panics unless [`IndexInfo::TRACK_CHANGE_TICKS`] is set.
+    pub fn changed_since(&mut self, tick: Tick) -> Vec<(Entity, I::Value)> {
+        assert!(
+            I::TRACK_CHANGE_TICKS,
+            "{}: changed_since needs IndexInfo::TRACK_CHANGE_TICKS",
+            self.label()
+        );
+        self.storage
+            .changed_since(tick, self.refresh_data.ticks.this_run())
+    }
+
+    /// Get each distinct value along with the number of entities that have it, in no
+    /// particular order.
+    ///
+    /// Only the values are cloned, not their buckets, and values without entities are never
+    /// included. See [`bucket_sizes`][Self::bucket_sizes] for the same data sorted by size.
+    pub fn value_counts(&mut self) -> Vec<(I::Value, usize)> {
+        self.storage.bucket_sizes()
+    }
+
+    /// The number of distinct values that at least one entity is indexed under, e.g. how
+    /// many teams currently exist.
+    ///
+    /// This is not the number of entities: many entities can share a value. Use
+    /// [`entity_count_for`][Self::entity_count_for] to count the entities with one value.
+    /// Values are dropped as soon as their last entity leaves, so this doesn't need to look
+    /// at any buckets.
+    #[doc(alias = "count_distinct")]
+    pub fn value_count(&mut self) -> usize {
+        self.storage.map_ref().key_count()
+    }
+
+    /// The number of entities indexed under the given value, which is 0 if there are none.
+    ///
+    /// This is the same as `lookup(val).len()`, without copying the bucket.
+    pub fn entity_count_for(&mut self, val: &I::Value) -> usize {
+        self.storage.bucket(val).len()
+    }
+
+    /// Remove every entity for which `is_live` returns false from the index, dropping any
+    /// values that are left without entities.
+    ///
+    /// This is a manual fix for entities that were despawned or lost their component while
+    /// no system using the index ran, which the index can miss (see the README's
+    /// troubleshooting section). `is_live` is called once per indexed entity, and is usually
+    /// backed by a query, e.g. `idx.prune(|e| entities.contains(e))` with a `Query<Entity>`.
+    pub fn prune(&mut self, is_live: impl Fn(Entity) -> bool) {
+        self.storage.prune(is_live);
+    }
+
+    /// Register `callback` to be called whenever a refresh indexes an entity under a new
+    /// value, with the entity, its previous value (`None` if it wasn't indexed before), and
+    /// its new value.
+    ///
+    /// Callbacks fire during [`refresh`][Index::refresh], in the order they were registered,
+    /// before the refresh returns. Since indexes refresh when a system using them is fetched,
+    /// this usually means before the body of the next such system runs, so a lookup in that
+    /// system already sees the new values. Entities whose component changed without changing
+    /// their value are not reported, and neither are entities that are removed from the
+    /// index.
+    ///
+    /// Callbacks are kept in the index's storage resource, and last until it is removed.
+    pub fn on_change(
+        &mut self,
+        callback: impl FnMut(Entity, Option<I::Value>, I::Value) + Send + Sync + 'static,
+    ) {
+        self.storage.on_change(Box::new(callback));
+    }
+
+    /// Get up to `n` of the entities with the given value, without copying the rest of the
+    /// bucket.
+    ///
+    /// Which entities are returned is arbitrary for the default [`HashSet`] buckets, and may
+    /// change between calls as the bucket changes. With [`InsertionOrderStorage`], these are
+    /// the `n` entities that were added to the bucket first.
+    ///
+    /// [`InsertionOrderStorage`]: crate::storage::InsertionOrderStorage
+    pub fn lookup_n(&mut self, val: &I::Value, n: usize) -> Vec<Entity> {
+        self.storage.bucket(val).iter().take(n).copied().collect()
+    }
+
+    /// Get the last [`HISTORY_LEN`][IndexInfo::HISTORY_LEN] changes that refreshing this
+    /// index made, oldest first, along with the tick of the refresh that made each of them.
+    ///
+    /// This is meant for debugging: after something goes wrong, it shows which entities the
+    /// index recently moved or removed and when. Changes made directly with methods like
+    /// [`update`][Self::update] or [`take`][Self::take] are not recorded.
+    #[cfg(feature = "history")]
+    pub fn recent_changes(&mut self) -> &[(Tick, IndexChange<I::Value>)] {
+        self.storage.recent_changes()
+    }
+
+    /// Call `f` with the bucket of entities that have the given value, without copying it,
+    /// and return its result.
+    ///
+    /// `f` is passed an empty bucket if no entity has the value. Like
+    /// [`lookup`][Index::lookup], this reads the index as of its last refresh.
+    pub fn with_bucket<R>(&mut self, val: &I::Value, f: impl FnOnce(&B) -> R) -> R {
+        f(self.storage.bucket(val))
+    }
+
+    /// Get all of the entities whose value satisfies `pred`.
+    ///
+    /// `pred` is called once for every distinct value in the index, so this is much
+    /// slower than [`lookup`][Index::lookup] for indexes with many values.
+    pub fn lookup_where(&mut self, pred: impl Fn(&I::Value) -> bool) -> HashSet<Entity> {
+        self.storage.lookup_where(pred)
+    }
+
+    /// Get a compact id for `val`, e.g. for sending lookups over the network.
+    ///
+    /// Ids are assigned sequentially starting from 0 the first time each value is passed
+    /// in, and stay the same for as long as the index's storage exists, even if no entity
+    /// has the value anymore. Every value that has been given an id is kept in memory.
+    pub fn value_id(&mut self, val: &I::Value) -> u64 {
+        self.storage.value_id(val)
+    }
+
+    /// Get all of the entities with the value that was given `id` by
+    /// [`value_id`][Self::value_id]. Unknown ids have no entities.
+    pub fn lookup_by_value_id(&mut self, id: u64) -> HashSet<Entity> {
+        match self.storage.value_for_id(id) {
+            Some(val) => self.storage.bucket(val).to_hash_set(),
+            None => HashSet::new(),
+        }
+    }
+
+    /// Hash a value the way [`lookup_by_hash`][Self::lookup_by_hash] expects.
+    ///
+    /// The same fixed hasher is used for every index, so the result is the same every time
+    /// within a build of a program, but may differ between platforms or crate versions.
+    pub fn hash_value(val: &I::Value) -> u64 {
+        FixedState.hash_one(val)
+    }
+
+    /// Get all of the entities whose value has the given [`hash_value`][Self::hash_value],
+    /// for tooling that can't name the index's value type.
+    ///
+    /// Distinct values can share a hash, in which case the entities for all of them are
+    /// returned. The first call hashes every distinct value in the index; after that, the
+    /// storage keeps its values grouped by hash as refreshes add and remove them.
+    pub fn lookup_by_hash(&mut self, hash: u64) -> HashSet<Entity> {
+        self.storage.lookup_by_hash(hash)
+    }
+
+    /// Split the entities with the given value into groups by a secondary `key`.
+    ///
+    /// `key` is called exactly once for each entity in the bucket, and no intermediate
+    /// [`HashSet`] is created.
+    pub fn partition<K: Hash + Eq>(
+        &mut self,
+        val: &I::Value,
+        key: impl Fn(Entity) -> K,
+    ) -> HashMap<K, Vec<Entity>> {
+        let mut groups = HashMap::<K, Vec<Entity>>::new();
+        for &entity in self.storage.bucket(val).iter() {
+            groups.entry(key(entity)).or_default().push(entity);
+        }
+        groups
+    }
+
+    /// Group all of the entities in the index by a `label` computed from their values,
+    /// e.g. for display.
+    ///
+    /// `label` is called once for each distinct value. Values whose labels are equal have
+    /// their buckets merged into one set under that label, so the result has one entry per
+    /// distinct label with at least one entity. The index itself is not changed.
+    pub fn grouped_labeled<L: Hash + Eq>(
+        &mut self,
+        label: impl Fn(&I::Value) -> L,
+    ) -> HashMap<L, HashSet<Entity>> {
+        let mut groups = HashMap::<L, HashSet<Entity>>::new();
+        for (val, bucket) in self.storage.map_ref().iter() {
+            groups
+                .entry(label(val))
+                .or_default()
+                .extend(bucket.iter().copied());
+        }
+        groups
+    }
+
+    /// Compute the changes that [`refresh`][Index::refresh] would make to the index right
+    /// now, without applying them.
+    ///
+    /// Since indexes are refreshed when they are fetched, this reports changes made
+    /// earlier in the current system, or in other systems since then.
+    pub fn dry_run(&mut self) -> Vec<IndexChange<I::Value>> {
+        self.storage.pending_changes(&self.refresh_data)
+    }
+
+    /// Refresh the index, then check every mapping against the components in the world.
+    ///
+    /// This is intended for tests and debug builds, since it scans every component
+    /// and every stored mapping.
+    pub fn verify(&mut self) -> Result<(), IndexInconsistency<I::Value>> {
+        self.refresh();
+        let discrepancies = self.storage.discrepancies(&self.refresh_data);
+        if discrepancies.is_empty() {
+            Ok(())
+        } else {
+            Err(IndexInconsistency { discrepancies })
+        }
+    }
+}
+
+impl<'w, 's, I> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = InsertionOrderStorage<I>>,
+{
+    /// Get all of the entities with the given value, in the order they were added to
+    /// that value's bucket.
+    pub fn lookup_ordered(&mut self, val: &I::Value) -> &IndexSet<Entity> {
+        self.storage.bucket(val)
+    }
+}
+
+impl<'w, 's, I, const SEED: u64> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = SeededStorage<I, SEED>>,
+{
+    /// Get all of the entities with the given value, in an order that is the same from run
+    /// to run.
+    pub fn lookup_seeded(&mut self, val: &I::Value) -> &SeededHashSet<Entity, SEED> {
+        self.storage.bucket(val)
+    }
+}
+
+impl<'w, 's, I, M> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = AggregateStorage<I, M>>,
+    M: Measure,
+{
+    /// Get the sum of the [`Measure`]s of the entities with the given value, which is 0 if
+    /// none of them have an `M` component.
+    pub fn group_sum(&mut self, group: &I::Value) -> f64 {
+        self.storage.group_total(group).0
+    }
+
+    /// Get the number of entities with the given value that have an `M` component.
+    pub fn group_count(&mut self, group: &I::Value) -> usize {
+        self.storage.group_total(group).1
+    }
+
+    /// Get the average [`Measure`] of the entities with the given value, or `None` if none
+    /// of them have an `M` component.
+    pub fn group_avg(&mut self, group: &I::Value) -> Option<f64> {
+        let (sum, count) = self.storage.group_total(group);
+        (count > 0).then(|| sum / count as f64)
+    }
+}
+
+impl<'w, 's, I, M> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = GroupMaxStorage<I, M>>,
+    M: Component + Ord + Clone,
+{
+    /// Get the largest `M` among the entities with the given value, and the entity that
+    /// has it, or `None` if none of them have an `M` component.
+    pub fn group_max(&mut self, group: &I::Value) -> Option<(M, Entity)> {
+        self.storage
+            .group_max(group)
+            .map(|(measure, entity)| (measure.clone(), entity))
+    }
+}
+
+impl<'w, 's, I> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = EntitySetStorage<I>>,
+{
+    /// Get all of the entities with the given value without copying them.
+    pub fn lookup_entityset(&mut self, val: &I::Value) -> &EntityHashSet<Entity> {
+        self.storage.bucket(val)
+    }
+}
+
+impl<'w, 's, I> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = InternedStorage<I>>,
+{
+    /// Get the interned value that `entity` is indexed under, which is shared with every
+    /// other entity that has the same value, or `None` if it isn't in the index.
+    pub fn interned_value_of(&mut self, entity: Entity) -> Option<Arc<I::Value>> {
+        self.storage.value_of(entity).cloned()
+    }
+}
+
+impl<'w, 's, I> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = SharedStorage<I>>,
+{
+    /// Get all of the entities with the given value without copying them.
+    ///
+    /// The returned set is a snapshot: it is not updated by later refreshes.
+    pub fn lookup_shared(&mut self, val: &I::Value) -> Arc<HashSet<Entity>> {
+        self.storage.bucket(val).share()
+    }
+}
+
+impl<'w, 's, I> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = CachedStorage<I>>,
+{
+    /// Get all of the entities with the given value, sharing the set from an earlier call
+    /// instead of copying the entities again if the value's bucket hasn't changed since.
+    ///
+    /// The first lookup of each value copies its entities like [`lookup`][Index::lookup]
+    /// does. A refresh that adds or removes entities from a bucket drops its cached set,
+    /// while refreshes that leave it alone keep it across frames.
+    pub fn lookup_cached(&mut self, val: &I::Value) -> Arc<HashSet<Entity>> {
+        self.storage.bucket(val).cached()
+    }
+}
+
+/// A change to the mapping of a single entity in an index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexChange<V> {
+    /// The entity was added to the bucket for `new`, moving from the bucket for `old` if any.
+    Insert {
+        /// The affected entity.
+        entity: Entity,
+        /// The value the entity was previously indexed under.
+        old: Option<V>,
+        /// The value the entity is now indexed under.
+        new: V,
+    },
+    /// The entity was removed from the index.
+    Remove {
+        /// The affected entity.
+        entity: Entity,
+        /// The value the entity was previously indexed under.
+        old: V,
+    },
+}
+
+/// Error returned by [`Index::verify`] when an index does not match the world.
+#[derive(Debug, Clone)]
+pub struct IndexInconsistency<V> {
+    /// Every entity whose stored value is not the value of its current component.
+    pub discrepancies: Vec<Discrepancy<V>>,
+}
+
+impl<V: std::fmt::Debug> std::fmt::Display for IndexInconsistency<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} entities are incorrectly indexed:",
+            self.discrepancies.len()
+        )?;
+        for d in &self.discrepancies {
+            writeln!(
+                f,
+                "  {:?}: expected {:?}, found {:?}",
+                d.entity, d.expected, d.actual
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<V: std::fmt::Debug> std::error::Error for IndexInconsistency<V> {}
+
+/// A single entity whose mapping in an index is incorrect. See [`IndexInconsistency`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Discrepancy<V> {
+    /// The entity with the incorrect mapping.
+    pub entity: Entity,
+    /// The value the entity should be indexed under, or `None` if it should not be indexed,
+    /// e.g. because it was despawned or no longer has the component.
+    pub expected: Option<V>,
+    /// The value the entity is actually indexed under, if any.
+    pub actual: Option<V>,
+}
+
+/// Summary of how entities are distributed between the values of an index,
+/// returned by [`Index::bucket_sizes`].
+#[derive(Debug, Clone)]
+pub struct BucketSizes<V> {
+    /// Each distinct value and the number of entities that have it, largest first.
+    pub sizes: Vec<(V, usize)>,
+    /// Total number of entities in the index.
+    pub total: usize,
+    /// Number of entities in the largest bucket, or 0 if the index is empty.
+    pub max: usize,
+}
+
+impl<'w, 's, I> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = OrderedStorage<I>>,
+    I::Value: Ord,
+{
+    /// Get all of the entities whose values are within `range`.
+    pub fn lookup_range(&mut self, range: impl RangeBounds<I::Value>) -> HashSet<Entity> {
+        self.storage
+            .range(range)
+            .flat_map(|(_, set)| set.iter().copied())
+            .collect()
+    }
+
+    /// Get all of the entities whose tuple values start with `prefix`, e.g. every cell in a
+    /// zone when values are `(zone, row, slot)`. See [`TuplePrefix`] for the supported
+    /// prefixes.
+    ///
+    /// ```
+    /// # use bevy::prelude::*;
+    /// # use bevy_mod_index::prelude::*;
+    /// #[derive(Component)]
+    /// struct Cell {
+    ///     zone: u8,
+    ///     row: u8,
+    ///     slot: u16,
+    /// }
+    ///
+    /// impl IndexInfo for Cell {
+    ///     type Component = Self;
+    ///     type Value = (u8, u8, u16);
+    ///     type Storage = OrderedStorage<Self>;
+    ///     type ValueParam = ();
+    ///
+    ///     fn value(c: &Self, _: &()) -> (u8, u8, u16) {
+    ///         (c.zone, c.row, c.slot)
+    ///     }
+    /// }
+    ///
+    /// fn zone_rows(mut idx: Index<Cell>) {
+    ///     let zone = idx.lookup_prefix(&3u8);
+    ///     let row = idx.lookup_prefix(&(3u8, 1u8));
+    /// }
+    /// ```
+    pub fn lookup_prefix<P>(&mut self, prefix: &P) -> HashSet<Entity>
+    where
+        I::Value: TuplePrefix<P>,
+    {
+        self.lookup_range(I::Value::prefix_range(prefix))
+    }
+
+    /// Iterate over every value in the index and the entities that have it, in order of
+    /// value. Values without any entities are skipped.
+    ///
+    /// Like other lookups, this reflects the index as of its last [`refresh`][Self::refresh].
+    pub fn iter_ordered(&self) -> impl Iterator<Item = (&I::Value, &HashSet<Entity>)> {
+        self.storage.range(..)
+    }
+
+    /// Iterate over the values within `range` and the entities that have each of them, in
+    /// order of value, e.g. to process each group in a band of values separately. Values
+    /// without any entities are skipped.
+    ///
+    /// This is the per-value counterpart to [`lookup_range`][Self::lookup_range]. A range
+    /// that ends before it starts, like `5..2`, is empty.
+    pub fn iter_range(
+        &mut self,
+        range: impl RangeBounds<I::Value>,
+    ) -> impl Iterator<Item = (&I::Value, &HashSet<Entity>)> {
+        self.storage.range(range)
+    }
+
+    /// Get each distinct value along with the number of entities that have it, in order of
+    /// value, e.g. for rendering a leaderboard.
+    ///
+    /// Only the values are cloned, not their buckets, and values without entities are
+    /// skipped.
+    pub fn value_counts_sorted(&mut self) -> Vec<(I::Value, usize)> {
+        self.storage
+            .range(..)
+            .filter(|(_, set)| !set.is_empty())
+            .map(|(val, set)| (val.clone(), set.len()))
+            .collect()
+    }
+}
+
+impl<'w, 's, I> Index<'w, 's, I>
+where
+    I: IndexInfo<Storage = OrderedStorage<I>, Value = TotalF32>,
+{
+    /// Get all of the entities whose values are within `epsilon` of `val`, inclusive.
+    pub fn lookup_approx(&mut self, val: &TotalF32, epsilon: f32) -> HashSet<Entity> {
+        self.lookup_range(TotalF32(val.0 - epsilon)..=TotalF32(val.0 + epsilon))
+    }
+}
+
+impl<'w, 's, I> Index<'w, 's, I>
+where
+    I: IndexInfo<Value = [i32; 3]>,
+{
+    /// Get all of the entities whose coordinates are within `radius` of `center` along
+    /// every axis, e.g. the voxels neighboring a position when `radius` is 1.
+    ///
+    /// This does a [`lookup`][Index::lookup], copying a set of entities, for each of the
+    /// `(2 * radius + 1)^3` coordinates in the cube, so it is best suited to small radii.
+    /// Coordinates beyond the range of `i32` are skipped, and a negative `radius` finds no
+    /// entities.
+    pub fn lookup_cube(&mut self, center: [i32; 3], radius: i32) -> HashSet<Entity> {
+        let axis = |c: i32| c.saturating_sub(radius)..=c.saturating_add(radius);
+        let [x, y, z] = center;
+        let mut set = HashSet::new();
+        if radius < 0 {
+            return set;
+        }
+        for x in axis(x) {
+            for y in axis(y) {
+                for z in axis(z) {
+                    set.extend(self.lookup(&[x, y, z]));
+                }
+            }
+        }
+        set
+    }
+}
+
+/// A [`SystemParam`] that looks up entities across two indexes that share a value type.
+///
+/// This is useful when different components describe the same thing, e.g. `PlayerTeam` and
+/// `NpcTeam` components that both map to a `Team` value. [`lookup`][Self::lookup] returns
+/// entities from either index, so an entity with both components is only returned once.
+/// If its components produce different values, it can be found under each of them.
+#[derive(SystemParam)]
+pub struct UnionIndex<'w, 's, A, B>
+where
+    A: IndexInfo + 'static,
+    B: IndexInfo<Value = A::Value> + 'static,
+{
+    a: Index<'w, 's, A>,
+    b: Index<'w, 's, B>,
+}
+
+impl<'w, 's, A, B> UnionIndex<'w, 's, A, B>
+where
+    A: IndexInfo + 'static,
+    B: IndexInfo<Value = A::Value> + 'static,
+{
+    /// Get all of the entities whose component from either index evaluates to the given
+    /// value.
+    pub fn lookup(&mut self, val: &A::Value) -> HashSet<Entity> {
+        let mut set = self.a.lookup(val);
+        set.extend(self.b.lookup(val));
+        set
+    }
+
+    /// Refresh both underlying indexes.
+    pub fn refresh(&mut self) {
+        self.a.refresh();
+        self.b.refresh();
+    }
+}
+
+/// A read-only [`SystemParam`] for looking up entities in an index without refreshing it.
+///
+/// Unlike [`Index`], this only reads the index's storage, so systems using it can run in
+/// parallel with each other. In exchange, it never refreshes the index itself, so lookups
+/// only reflect the last time an [`Index`] for the same [`IndexInfo`] was used or an
+/// [`IndexPlugin`][crate::plugin::IndexPlugin] refreshed it. Until then, lookups are empty.
+/// Pair it with the plugin to keep it up to date:
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// # #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// # struct Team(u32);
+/// fn count_blue(idx: ReadIndex<ComponentIndex<Team>>, names: Query<&Name>) {
+///     let blue = idx.get(&Team(0));
+/// }
+///
+/// App::new()
+///     .add_plugins(IndexPlugin::<ComponentIndex<Team>>::new())
+///     .add_systems(Update, count_blue);
+/// ```
+#[derive(SystemParam)]
+pub struct ReadIndex<'w, I>
+where
+    I: IndexInfo + 'static,
+{
+    storage: Option<Res<'w, <I as IndexInfo>::Storage>>,
+}
+
+impl<'w, I, B> ReadIndex<'w, I>
+where
+    I: IndexInfo<Storage = HashmapStorage<I, B>>,
+    B: Bucket<Entity>,
+{
+    /// Get all of the entities with the given value as of the index's last refresh.
+    pub fn get(&self, val: &I::Value) -> HashSet<Entity> {
+        self.storage
+            .as_ref()
+            .map(|storage| storage.map_ref().get(val))
+            .unwrap_or_default()
+    }
+}
+
+/// Tracks which [`IndexInfo`] each storage resource belongs to, so that two
+/// indexes can't accidentally share one.
+#[cfg(debug_assertions)]
+#[derive(Resource, Default)]
+struct StorageOwners(HashMap<std::any::TypeId, (std::any::TypeId, &'static str)>);
+
+// Storage is a resource keyed by its type, so if two different `IndexInfo`s name the same
+// storage type they would silently overwrite each other's data.
+#[cfg(debug_assertions)]
+fn check_storage_owner<I: IndexInfo>(world: &mut World) {
+    use std::any::{type_name, TypeId};
+
+    let mut owners = world.get_resource_or_insert_with(StorageOwners::default);
+    let (owner, owner_name) = *owners
+        .0
+        .entry(TypeId::of::<I::Storage>())
+        .or_insert((TypeId::of::<I>(), type_name::<I>()));
+    if owner != TypeId::of::<I>() {
+        panic!(
+            "Indexes `{}` and `{}` both use `{}` as their storage. Each index must have its own \
+             storage type, or they will overwrite each other's data.",
+            owner_name,
+            type_name::<I>(),
+            type_name::<I::Storage>(),
+        );
+    }
+}
+
+#[cfg(debug_assertions)]
+type RefreshCheckParam<'w, I> = (Res<'w, PluginAdded<I>>, Option<Res<'w, FrameCount>>);
+
+/// Warns once if an eager index is used but the [`IndexPlugin`][crate::plugin::IndexPlugin]
+/// system that refreshes it never runs, for example because it was added to a schedule that
+/// is not run.
+#[cfg(debug_assertions)]
+struct RefreshCheck<I: IndexInfo + 'static> {
+    state: <RefreshCheckParam<'static, I> as SystemParam>::State,
+    first_used_frame: Option<u32>,
+    warned: bool,
+}
+
+#[cfg(debug_assertions)]
+impl<I: IndexInfo + 'static> RefreshCheck<I> {
+    /// Returns whether the index counts as refreshed this frame, which it only doesn't if
+    /// its plugin refreshes in [`First`] and hasn't done so yet.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`SystemParam::get_param`].
+    unsafe fn check(
+        &mut self,
+        system_meta: &SystemMeta,
+        world: UnsafeWorldCell,
+        change_tick: Tick,
+    ) -> bool {
+        let (plugin, frame) = <RefreshCheckParam<I> as SystemParam>::get_param(
+            &mut self.state,
+            system_meta,
+            world,
+            change_tick,
+        );
+        let Some(frame) = frame else {
+            return true;
+        };
+        let refreshed = !plugin.refreshes_first || plugin.last_refresh_frame == Some(frame.0);
+        let first_used_frame = *self.first_used_frame.get_or_insert(frame.0);
+        if !self.warned
+            && crate::plugin::refresh_missed(plugin.last_refresh_frame, first_used_frame, frame.0)
+        {
+            self.warned = true;
+            warn!(
+                "{} uses RefreshPolicy::Eager, but the system added by IndexPlugin::<{0}> has not \
+                 run since the index was first used, so the index will not see any changes. \
+                 Check that the plugin's schedule is run.",
+                std::any::type_name::<I>()
+            );
+        }
+        refreshed
+    }
+}
+
+type StorageParam<'w, I> = ResMut<'w, <I as IndexInfo>::Storage>;
+type RefreshDataParam<'w, 's, I> = StaticSystemParam<
+    'w,
+    's,
+    <<I as IndexInfo>::Storage as IndexStorage<I>>::RefreshData<'static, 'static>,
+>;
+
+/// The parts of the world that every index system param is built from.
+pub(crate) struct IndexParts<'w, 's, I: IndexInfo + 'static> {
+    storage: StorageParam<'w, I>,
+    refresh_data: RefreshDataParam<'w, 's, I>,
+    world_id: WorldId,
+    refreshed_this_frame: bool,
+}
+
+/// [`SystemParam::State`] shared by all index system params, which handles fetching an
+/// index's storage and refresh data.
+///
+/// New params reuse this through `impl_index_system_param!` instead of implementing
+/// [`SystemParam`] themselves, so that the unsafe parts live in one place.
+///
+/// The refresh data's state includes the [`QueryState`][bevy::ecs::query::QueryState] of
+/// its components query, which is created once when the system is initialized and only
+/// updated with new archetypes afterwards, so each run only pays for building the
+/// [`Query`] around it.
+#[doc(hidden)]
+pub struct IndexFetchState<I: IndexInfo + 'static> {
+    storage_state: <StorageParam<'static, I> as SystemParam>::State,
+    refresh_data_state: <RefreshDataParam<'static, 'static, I> as SystemParam>::State,
+    #[cfg(debug_assertions)]
+    refresh_check: Option<RefreshCheck<I>>,
+}
+
+impl<I: IndexInfo + 'static> IndexFetchState<I> {
+    fn init(world: &mut World, system_meta: &mut SystemMeta) -> Self {
+        world.init_resource::<I::Storage>();
+        #[cfg(debug_assertions)]
+        check_storage_owner::<I>(world);
+        if I::REFRESH == RefreshPolicy::Eager && !world.contains_resource::<PluginAdded<I>>() {
+            panic!(
+                "{} uses RefreshPolicy::Eager, but IndexPlugin::<{0}> was not added to the app.",
+                std::any::type_name::<I>()
+            );
+        }
+        Self {
+            storage_state: <StorageParam<I> as SystemParam>::init_state(world, system_meta),
+            refresh_data_state: <RefreshDataParam<I> as SystemParam>::init_state(
+                world,
+                system_meta,
+            ),
+            #[cfg(debug_assertions)]
+            refresh_check: (I::REFRESH == RefreshPolicy::Eager).then(|| RefreshCheck {
+                state: <RefreshCheckParam<I> as SystemParam>::init_state(world, system_meta),
+                first_used_frame: None,
+                warned: false,
+            }),
+        }
+    }
+
+    fn new_archetype(&mut self, archetype: &Archetype, system_meta: &mut SystemMeta) {
+        <StorageParam<I> as SystemParam>::new_archetype(
+            &mut self.storage_state,
+            archetype,
+            system_meta,
+        );
+        <RefreshDataParam<I> as SystemParam>::new_archetype(
+            &mut self.refresh_data_state,
+            archetype,
+            system_meta,
+        );
+    }
+
+    fn apply(&mut self, system_meta: &SystemMeta, world: &mut World) {
+        <StorageParam<I> as SystemParam>::apply(&mut self.storage_state, system_meta, world);
+        <RefreshDataParam<I> as SystemParam>::apply(
+            &mut self.refresh_data_state,
+            system_meta,
+            world,
+        );
+        world.resource_scope(|world, mut storage: Mut<I::Storage>| {
+            storage.apply(world);
+        });
+    }
+
+    /// # Safety
+    ///
+    /// Same as [`SystemParam::get_param`].
+    unsafe fn fetch<'w, 's>(
+        &'s mut self,
+        system_meta: &SystemMeta,
+        world: UnsafeWorldCell<'w>,
+        change_tick: Tick,
+    ) -> IndexParts<'w, 's, I> {
+        #[cfg(debug_assertions)]
+        let refreshed_this_frame = match &mut self.refresh_check {
+            Some(check) => check.check(system_meta, world, change_tick),
+            None => true,
+        };
+        #[cfg(not(debug_assertions))]
+        let refreshed_this_frame = true;
+        IndexParts {
+            storage: <StorageParam<I> as SystemParam>::get_param(
+                &mut self.storage_state,
+                system_meta,
+                world,
+                change_tick,
+            ),
+            refresh_data: <RefreshDataParam<I> as SystemParam>::get_param(
+                &mut self.refresh_data_state,
+                system_meta,
+                world,
+                change_tick,
+            ),
+            world_id: world.id(),
+            refreshed_this_frame,
+        }
+    }
+}
+
+/// Implement [`SystemParam`] for an index param `$param<'w, 's, I>` that has a
+/// `fn from_parts(IndexParts<'w, 's, I>) -> Self` constructor, using [`IndexFetchState`].
+macro_rules! impl_index_system_param {
+    ($param:ident) => {
+        // SAFETY: All access is declared and fetched through `IndexFetchState`, which only
+        // delegates to the params it is made of.
+        unsafe impl<'w, 's, I: IndexInfo + 'static> SystemParam for $param<'w, 's, I> {
+            type State = IndexFetchState<I>;
+            type Item<'w2, 's2> = $param<'w2, 's2, I>;
+
+            fn init_state(world: &mut World, system_meta: &mut SystemMeta) -> Self::State {
+                IndexFetchState::init(world, system_meta)
+            }
+
+            fn new_archetype(
+                state: &mut Self::State,
+                archetype: &Archetype,
+                system_meta: &mut SystemMeta,
+            ) {
+                state.new_archetype(archetype, system_meta);
+            }
+
+            fn apply(state: &mut Self::State, system_meta: &SystemMeta, world: &mut World) {
+                state.apply(system_meta, world);
+            }
+
+            unsafe fn get_param<'w2, 's2>(
+                state: &'s2 mut Self::State,
+                system_meta: &SystemMeta,
+                world: UnsafeWorldCell<'w2>,
+                change_tick: Tick,
+            ) -> Self::Item<'w2, 's2> {
+                $param::from_parts(state.fetch(system_meta, world, change_tick))
+            }
+        }
+    };
+}
+
+impl<'w, 's, I: IndexInfo + 'static> Index<'w, 's, I> {
+    fn from_parts(parts: IndexParts<'w, 's, I>) -> Self {
+        let mut index = Index {
+            storage: parts.storage,
+            refresh_data: parts.refresh_data,
+            world_id: parts.world_id,
+            refreshed_this_frame: parts.refreshed_this_frame,
+        };
+        if I::REFRESH == RefreshPolicy::Lazy {
+            index.refresh();
+        }
+        index
+    }
+}
+
+impl_index_system_param!(Index);
+
+unsafe impl<'w, 's, I: IndexInfo + 'static> ReadOnlySystemParam for Index<'w, 's, I>
+where
+    ResMut<'w, I::Storage>: ReadOnlySystemParam,
+    StaticSystemParam<'w, 's, <I::Storage as IndexStorage<I>>::RefreshData<'static, 'static>>:
+        ReadOnlySystemParam,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use crate::index::{Discrepancy, IndexChange, IndexHandle};
+    use crate::plugin::PluginAdded;
+    use crate::prelude::*;
+    use crate::storage::HashmapStorageRefreshData;
+    use bevy::core::FrameCount;
+    use bevy::ecs::component::Tick;
+    use bevy::ecs::system::{
+        ReadOnlySystemParam, RunSystemOnce, StaticSystemParam, SystemChangeTick, SystemState,
+    };
+    use bevy::prelude::*;
+    use bevy::utils::{HashMap, HashSet};
+    use std::marker::PhantomData;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Component, Clone, Eq, Hash, PartialEq, Debug)]
+    struct Number(usize);
+
+    impl From<usize> for Number {
+        fn from(n: usize) -> Self {
+            Number(n)
+        }
+    }
+
+    //todo: maybe make this a derive macro
+    impl IndexInfo for Number {
+        type Component = Self;
+        type Value = Self;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Self::Component, _: &()) -> Self::Value {
+            c.clone()
+        }
+    }
+
+    fn add_some_numbers(mut commands: Commands) {
+        commands.spawn(Number(10));
+        commands.spawn(Number(10));
+        commands.spawn(Number(20));
+        commands.spawn(Number(30));
+    }
+
+    fn checker(number: usize, amount: usize) -> impl Fn(Index<Number>) {
+        move |mut idx: Index<Number>| idx.assert_count(&Number(number), amount)
+    }
+
+    fn adder_all(n: usize) -> impl Fn(Query<&mut Number>) {
+        move |mut nums: Query<&mut Number>| {
+            for mut num in &mut nums {
+                num.0 += n;
+            }
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn adder_some(
+        n: usize,
+        condition: usize,
+    ) -> impl Fn(ParamSet<(Query<&mut Number>, Index<Number>)>) {
+        move |mut nums_and_index: ParamSet<(Query<&mut Number>, Index<Number>)>| {
+            for entity in nums_and_index.p1().lookup(&Number(condition)).into_iter() {
+                let mut nums = nums_and_index.p0();
+                let mut nref: Mut<Number> = nums.get_mut(entity).unwrap();
+                nref.0 += n;
+            }
+        }
+    }
+
+    #[test]
+    fn test_index_lookup() {
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(Update, checker(10, 2))
+            .add_systems(Update, checker(20, 1))
+            .add_systems(Update, checker(30, 1))
+            .add_systems(Update, checker(40, 0))
+            .run();
+    }
+
+    #[test]
+    fn test_changing_values() {
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(PreUpdate, checker(10, 2))
+            .add_systems(PreUpdate, checker(20, 1))
+            .add_systems(PreUpdate, checker(30, 1))
+            .add_systems(Update, adder_all(5))
+            .add_systems(PostUpdate, checker(10, 0))
+            .add_systems(PostUpdate, checker(20, 0))
+            .add_systems(PostUpdate, checker(30, 0))
+            .add_systems(PostUpdate, checker(15, 2))
+            .add_systems(PostUpdate, checker(25, 1))
+            .add_systems(PostUpdate, checker(35, 1))
+            .run();
+    }
+
+    #[test]
+    fn test_changing_with_index() {
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(PreUpdate, checker(10, 2))
+            .add_systems(PreUpdate, checker(20, 1))
+            .add_systems(Update, adder_some(10, 10))
+            .add_systems(PostUpdate, checker(10, 0))
+            .add_systems(PostUpdate, checker(20, 3))
+            .run();
+    }
+
+    #[test]
+    fn test_same_system_detection() {
+        let manual_refresh_system =
+            |mut nums_and_index: ParamSet<(Query<&mut Number>, Index<Number>)>| {
+                let mut idx = nums_and_index.p1();
+                let twenties = idx.lookup(&Number(20));
+                assert_eq!(twenties.len(), 1);
+
+                for entity in twenties.into_iter() {
+                    nums_and_index.p0().get_mut(entity).unwrap().0 += 5;
+                }
+                idx = nums_and_index.p1(); // reborrow here so earlier p0 borrow succeeds
+
+                // Already refreshed
+                assert_eq!(idx.lookup(&Number(20)).len(), 0);
+                assert_eq!(idx.lookup(&Number(25)).len(), 1);
+
+                idx.refresh();
+                assert_eq!(idx.lookup(&Number(20)).len(), 0);
+                assert_eq!(idx.lookup(&Number(25)).len(), 1);
+            };
+
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(Update, manual_refresh_system)
+            .run();
+    }
+
+    #[test]
+    fn test_update() {
+        let update_system = |mut nums_and_index: ParamSet<(Query<&mut Number>, Index<Number>)>| {
+            let twenties = nums_and_index.p1().lookup(&Number(20));
+            assert_eq!(twenties.len(), 1);
+
+            for entity in twenties.into_iter() {
+                nums_and_index.p0().get_mut(entity).unwrap().0 = 25;
+                nums_and_index.p1().update(entity, Number(25));
+            }
+
+            let mut idx = nums_and_index.p1();
+            assert_eq!(idx.lookup(&Number(20)).len(), 0);
+            assert_eq!(idx.lookup(&Number(25)).len(), 1);
+        };
+
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(Update, update_system)
+            .add_systems(PostUpdate, checker(20, 0))
+            .add_systems(PostUpdate, checker(25, 1))
+            .run();
+    }
+
+    #[test]
+    fn test_update_then_refresh() {
+        let mut world = World::new();
+        let entity = world.spawn(Number(1)).id();
+        world.increment_change_tick();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+
+        state.get_mut(&mut world).update(entity, Number(3));
+        world.increment_change_tick();
+        // The component didn't change, so refreshing doesn't undo the update.
+        assert_eq!(
+            state.get_mut(&mut world).lookup(&Number(3)),
+            [entity].into()
+        );
+
+        // Once it does, the computed value wins.
+        world.get_mut::<Number>(entity).unwrap().0 = 2;
+        world.increment_change_tick();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(2)), [entity].into());
+        assert!(idx.lookup(&Number(3)).is_empty());
+    }
+
+    #[test]
+    fn test_with_bucket() {
+        let mut world = World::new();
+        let entity = world.spawn(Number(1)).id();
+        world.spawn(Number(1));
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+
+        assert_eq!(idx.with_bucket(&Number(1), |bucket| bucket.len()), 2);
+        assert!(idx.with_bucket(&Number(1), |bucket| bucket.contains(&entity)));
+        assert!(idx.with_bucket(&Number(2), |bucket| bucket.is_empty()));
+    }
+
+    #[test]
+    fn test_on_change() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let changes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = changes.clone();
+        let mut idx = state.get_mut(&mut world);
+        idx.on_change(move |entity, old, new| seen.lock().unwrap().push((entity, old, new)));
+
+        world.increment_change_tick();
+        world.get_mut::<Number>(a).unwrap().0 = 2;
+        let b = world.spawn(Number(3)).id();
+        state.get_mut(&mut world);
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![(a, Some(Number(1)), Number(2)), (b, None, Number(3))]
+        );
+
+        // Touching a component without changing its value isn't reported.
+        world.increment_change_tick();
+        world.get_mut::<Number>(a).unwrap().0 = 2;
+        state.get_mut(&mut world);
+        assert_eq!(changes.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_grouped_labeled() {
+        let mut world = World::new();
+        world.spawn_batch([Number(1), Number(2), Number(2), Number(15)]);
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+
+        let groups = idx.grouped_labeled(|n| if n.0 < 10 { "small" } else { "large" });
+        assert_eq!(groups.len(), 2);
+        // The buckets for 1 and 2 are merged.
+        assert_eq!(groups["small"].len(), 3);
+        assert_eq!(groups["large"].len(), 1);
+        assert_eq!(idx.lookup(&Number(2)).len(), 2);
+    }
+
+    #[test]
+    fn test_from_entries() {
+        let mut world = World::new();
+        let entities: Vec<_> = (0..4).map(|_| world.spawn_empty().id()).collect();
+        world.insert_resource(HashmapStorage::<Number>::from_entries(
+            entities
+                .iter()
+                .map(|&e| (Number(e.index() as usize % 2), e)),
+        ));
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(0)), [entities[0], entities[2]].into());
+        assert_eq!(idx.lookup(&Number(1)).len(), 2);
+    }
+
+    #[test]
+    fn test_lookup_into() {
+        let mut world = World::new();
+        let ten = world.spawn(Number(10)).id();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup_into(10), [ten].into());
+        assert_eq!(idx.lookup_into(10), idx.lookup(&Number(10)));
+        assert!(idx.lookup_into(Number(11)).is_empty());
+    }
+
+    #[test]
+    fn test_lookup_n() {
+        let mut world = World::new();
+        let entities: Vec<_> = (0..5)
+            .map(|_| world.spawn((Number(1), Ordered(1))).id())
+            .collect();
+        let mut state = SystemState::<(Index<Number>, Index<Ordered>)>::new(&mut world);
+        let (mut idx, mut ordered) = state.get_mut(&mut world);
+
+        let some = idx.lookup_n(&Number(1), 3);
+        assert_eq!(some.len(), 3);
+        assert!(some.iter().all(|e| entities.contains(e)));
+        assert_eq!(idx.lookup_n(&Number(1), 10).len(), 5);
+        assert!(idx.lookup_n(&Number(2), 3).is_empty());
+
+        assert_eq!(ordered.lookup_n(&1, 2), entities[..2]);
+    }
+
+    #[test]
+    fn test_index_param_state() {
+        let mut world = World::new();
+        let mut system = IntoSystem::into_system(|_: Index<Number>| {});
+        system.initialize(&mut world);
+
+        // Initializing the param creates the storage, and declares access to it and the
+        // indexed component.
+        assert!(world.contains_resource::<HashmapStorage<Number>>());
+        let access = system.component_access();
+        let storage_id = world
+            .components()
+            .resource_id::<HashmapStorage<Number>>()
+            .unwrap();
+        assert!(access.has_write(storage_id));
+        assert!(access.has_read(world.component_id::<Number>().unwrap()));
+        assert!(!access.has_write(world.component_id::<Number>().unwrap()));
+    }
+
+    #[cfg(feature = "history")]
+    struct Tracked;
+
+    #[cfg(feature = "history")]
+    impl IndexInfo for Tracked {
+        type Component = Number;
+        type Value = usize;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+        const HISTORY_LEN: usize = 3;
+
+        fn value(c: &Number, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    #[cfg(feature = "history")]
+    #[test]
+    fn test_recent_changes() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let mut state = SystemState::<Index<Tracked>>::new(&mut world);
+        let insert_a = IndexChange::Insert {
+            entity: a,
+            old: None,
+            new: 1,
+        };
+        let changes = state.get_mut(&mut world).recent_changes().to_vec();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].1, insert_a);
+        let first_tick = changes[0].0;
+
+        world.get_mut::<Number>(a).unwrap().0 = 2;
+        state.get_mut(&mut world);
+        let b = world.spawn(Number(3)).id();
+        state.get_mut(&mut world);
+        world.despawn(b);
+        let now = world.read_change_tick();
+        let mut idx = state.get_mut(&mut world);
+
+        // Only the last 3 changes are kept, with the ticks of the refreshes that made them.
+        let changes = idx.recent_changes();
+        assert_eq!(
+            changes.iter().map(|(_, c)| c.clone()).collect::<Vec<_>>(),
+            vec![
+                IndexChange::Insert {
+                    entity: a,
+                    old: Some(1),
+                    new: 2,
+                },
+                IndexChange::Insert {
+                    entity: b,
+                    old: None,
+                    new: 3,
+                },
+                IndexChange::Remove { entity: b, old: 3 },
+            ]
+        );
+        assert!(changes[0].0.is_newer_than(first_tick, now));
+        assert!(changes[2].0.is_newer_than(changes[0].0, now));
+    }
+
+    #[derive(Component)]
+    struct Marker;
+
+    impl IndexInfo for Marker {
+        type Component = Self;
+        type Value = u32;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(_: &Self, _: &()) -> u32 {
+            unreachable!("value_for_entity is overridden")
+        }
+
+        fn value_for_entity(entity: Entity, _: &Self, _: &()) -> Option<u32> {
+            Some(entity.index() % 4)
+        }
+    }
+
+    #[test]
+    fn test_zst_by_entity() {
+        let mut world = World::new();
+        let entities: Vec<_> = (0..8).map(|_| world.spawn(Marker).id()).collect();
+        world.spawn(Number(0));
+        let mut state = SystemState::<Index<Marker>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        for group in 0..4 {
+            let expected: HashSet<_> = entities
+                .iter()
+                .copied()
+                .filter(|e| e.index() % 4 == group)
+                .collect();
+            assert_eq!(idx.lookup(&group), expected);
+        }
+        assert!(idx.verify().is_ok());
+
+        world.entity_mut(entities[0]).remove::<Marker>();
+        let mut idx = state.get_mut(&mut world);
+        assert!(!idx.contains_entity(entities[0]));
+    }
+
+    fn assert_read_only<P: ReadOnlySystemParam>() {}
+
+    #[test]
+    fn test_read_index() {
+        assert_read_only::<(ReadIndex<Number>, Query<&Number>, Res<Time>)>();
+
+        let mut world = World::new();
+        let entity = world.spawn(Number(1)).id();
+        let mut read = SystemState::<(ReadIndex<Number>, Query<&Number>)>::new(&mut world);
+        // Nothing has refreshed the index yet.
+        assert!(read.get(&world).0.get(&Number(1)).is_empty());
+
+        let mut index = SystemState::<Index<Number>>::new(&mut world);
+        index.get_mut(&mut world);
+        let (idx, numbers) = read.get(&world);
+        assert_eq!(idx.get(&Number(1)), [entity].into());
+        assert_eq!(numbers.iter().count(), 1);
+
+        // Changes are only seen once an `Index` refreshes again.
+        world.get_mut::<Number>(entity).unwrap().0 = 2;
+        assert!(read.get(&world).0.get(&Number(2)).is_empty());
+        index.get_mut(&mut world);
+        assert_eq!(read.get(&world).0.get(&Number(2)), [entity].into());
+    }
+
+    #[test]
+    fn test_read_index_runs_in_parallel() {
+        fn read(_: ReadIndex<Number>) {}
+        fn write(_: Index<Number>) {}
+
+        let mut world = World::new();
+        let mut readers = Schedule::default();
+        readers.add_systems((read, read));
+        readers.initialize(&mut world).unwrap();
+        assert!(readers.graph().conflicting_systems().is_empty());
+
+        let mut writers = Schedule::default();
+        writers.add_systems((write, write));
+        writers.initialize(&mut world).unwrap();
+        assert_eq!(writers.graph().conflicting_systems().len(), 1);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_test_mapping() {
+        let mut world = World::new();
+        let real = world.spawn(Number(1)).id();
+        let fake = world.spawn_empty().id();
+        let mut storage = HashmapStorage::<Number>::default();
+        storage.set_test_mapping(vec![(Number(1), fake), (Number(2), real)]);
+        world.insert_resource(storage);
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+
+        // The injected mapping survives refreshes, changes and removals.
+        world.get_mut::<Number>(real).unwrap().0 = 3;
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(1)), [fake].into());
+        assert_eq!(idx.lookup(&Number(2)), [real].into());
+        world.entity_mut(real).remove::<Number>();
+        state.apply(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(2)), [real].into());
+
+        world.entity_mut(real).insert(Number(3));
+        world
+            .resource_mut::<HashmapStorage<Number>>()
+            .clear_test_mapping();
+        let mut idx = state.get_mut(&mut world);
+        assert!(idx.lookup(&Number(1)).is_empty());
+        assert_eq!(idx.lookup(&Number(3)), [real].into());
+    }
+
+    #[cfg(feature = "channel")]
+    #[test]
+    fn test_refresh_channel() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn(Number(1)).id();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut idx = state.get_mut(&mut world);
+        // Fetching the index already did a full refresh, which the channel needs first.
+        idx.set_refresh_channel(receiver);
+        assert_eq!(idx.lookup(&Number(1)).len(), 2);
+
+        world.increment_change_tick();
+        world.get_mut::<Number>(a).unwrap().0 = 2;
+        world.get_mut::<Number>(b).unwrap().0 = 2;
+        let c = world.spawn(Number(2)).id();
+        std::thread::spawn(move || sender.send(a).unwrap())
+            .join()
+            .unwrap();
+
+        // Only the entity that was sent is re-indexed, not `b`, or the newly spawned `c`.
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(2)), [a].into());
+        assert_eq!(idx.lookup(&Number(1)), [b].into());
+
+        idx.clear_refresh_channel();
+        idx.refresh();
+        assert_eq!(idx.lookup(&Number(2)), [a, b, c].into());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(
+        expected = "has 1 entities with value Number(1), expected 2\nindex contents: HashmapStorage {"
+    )]
+    fn test_assert_count() {
+        let mut world = World::new();
+        world.spawn(Number(1));
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        idx.assert_count(&Number(1), 1);
+        idx.assert_count(&Number(2), 0);
+        idx.assert_count(&Number(1), 2);
+    }
+
+    #[derive(Component)]
+    struct Cell(u8, u8, u16);
+
+    impl IndexInfo for Cell {
+        type Component = Self;
+        type Value = (u8, u8, u16);
+        type Storage = OrderedStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Self, _: &()) -> (u8, u8, u16) {
+            (c.0, c.1, c.2)
+        }
+    }
+
+    #[test]
+    fn test_lookup_prefix() {
+        let mut world = World::new();
+        let a = world.spawn(Cell(1, 0, 0)).id();
+        let b = world.spawn(Cell(1, 0, u16::MAX)).id();
+        let c = world.spawn(Cell(1, u8::MAX, 7)).id();
+        world.spawn(Cell(0, u8::MAX, u16::MAX));
+        world.spawn(Cell(2, 0, 0));
+        let mut state = SystemState::<Index<Cell>>::new(&mut world);
+
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&(1, 0, u16::MAX)), [b].into());
+        assert_eq!(idx.lookup_prefix(&1u8), [a, b, c].into());
+        assert_eq!(idx.lookup_prefix(&(1u8, 0u8)), [a, b].into());
+        assert_eq!(idx.lookup_prefix(&(1u8, u8::MAX)), [c].into());
+        assert!(idx.lookup_prefix(&3u8).is_empty());
+    }
+
+    #[test]
+    fn test_contains_entity() {
+        let mut world = World::new();
+        let entity = world.spawn(Number(1)).id();
+        let other = world.spawn_empty().id();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert!(idx.contains_entity(entity));
+        assert!(!idx.contains_entity(other));
+
+        world.entity_mut(entity).remove::<Number>();
+        assert!(!state.get_mut(&mut world).contains_entity(entity));
+    }
+
+    #[test]
+    fn test_multiple_worlds() {
+        let mut world_a = World::new();
+        let mut world_b = World::new();
+        let a = world_a.spawn(Number(1)).id();
+        world_b.spawn(Number(1));
+        world_b.spawn(Number(1));
+        let mut state_a = SystemState::<Index<Number>>::new(&mut world_a);
+        let mut state_b = SystemState::<Index<Number>>::new(&mut world_b);
+
+        let mut idx_a = state_a.get_mut(&mut world_a);
+        assert_eq!(idx_a.lookup(&Number(1)), [a].into());
+        let label_a = idx_a.label();
+        let mut idx_b = state_b.get_mut(&mut world_b);
+        assert_eq!(idx_b.lookup(&Number(1)).len(), 2);
+        assert_ne!(idx_b.label(), label_a);
+        assert_eq!(idx_b.world_id(), world_b.id());
+
+        // Changes in one world don't affect the other.
+        world_b.spawn(Number(2));
+        assert!(state_a.get_mut(&mut world_a).lookup(&Number(2)).is_empty());
+        assert_eq!(state_b.get_mut(&mut world_b).lookup(&Number(2)).len(), 1);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn(Number(2)).id();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        world.increment_change_tick();
+
+        let mut idx = state.get_mut(&mut world);
+        idx.swap(a, b);
+        assert_eq!(idx.lookup(&Number(1)), [b].into());
+        idx.refresh();
+        assert_eq!(idx.lookup(&Number(1)), [b].into());
+
+        idx.invalidate();
+        idx.refresh();
+        assert_eq!(idx.lookup(&Number(1)), [a].into());
+        assert_eq!(idx.lookup(&Number(2)), [b].into());
+
+        // Entities that lost their component while the index wasn't watching are dropped.
+        world.entity_mut(b).remove::<Number>();
+        world.clear_trackers();
+        world.clear_trackers();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(2)), [b].into());
+        idx.invalidate();
+        idx.refresh();
+        assert!(idx.lookup(&Number(2)).is_empty());
+        assert!(idx.verify().is_ok());
+    }
+
+    #[test]
+    fn test_invalidate_ordered() {
+        let mut world = World::new();
+        let a = world.spawn(Cell(1, 0, 0)).id();
+        let b = world.spawn(Cell(2, 0, 0)).id();
+        let mut state = SystemState::<Index<Cell>>::new(&mut world);
+        state.get_mut(&mut world).refresh();
+        world.increment_change_tick();
+
+        world.entity_mut(b).remove::<Cell>();
+        world.clear_trackers();
+        world.clear_trackers();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&(2, 0, 0)), [b].into());
+        idx.invalidate();
+        idx.refresh();
+        assert!(idx.lookup(&(2, 0, 0)).is_empty());
+        assert_eq!(idx.lookup(&(1, 0, 0)), [a].into());
+    }
+
+    #[derive(Component)]
+    struct Disabled;
+
+    struct EnabledNumber;
+
+    impl IndexInfo for EnabledNumber {
+        type Component = Number;
+        type Value = Number;
+        type Storage = SkipDisabledStorage<Self, Disabled>;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> Number {
+            c.clone()
+        }
+    }
+
+    #[test]
+    fn test_skip_disabled_storage() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn((Number(1), Disabled)).id();
+        let mut state = SystemState::<Index<EnabledNumber>>::new(&mut world);
+        world.increment_change_tick();
+
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(1)), [a].into());
+
+        world.entity_mut(a).insert(Disabled);
+        world.entity_mut(b).remove::<Disabled>();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(1)), [b].into());
+
+        // Disabled entities stay out when their value changes, and come back with the
+        // latest value.
+        world.get_mut::<Number>(a).unwrap().0 = 2;
+        let mut idx = state.get_mut(&mut world);
+        assert!(idx.lookup(&Number(2)).is_empty());
+        world.entity_mut(a).remove::<Disabled>();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(2)), [a].into());
+        assert_eq!(idx.lookup(&Number(1)), [b].into());
+    }
+
+    #[test]
+    fn test_skip_disabled_storage_ignores_disabled_changes() {
+        let mut world = World::new();
+        let a = world.spawn((Number(1), Disabled)).id();
+        let b = world.spawn(Number(1)).id();
+        let mut state = SystemState::<Index<EnabledNumber>>::new(&mut world);
+        world.increment_change_tick();
+        state.get_mut(&mut world).refresh();
+
+        let changes = Arc::new(AtomicUsize::new(0));
+        let counter = changes.clone();
+        world
+            .resource_mut::<SkipDisabledStorage<EnabledNumber, Disabled>>()
+            .inner
+            .on_change(Box::new(move |_, _, _| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }));
+
+        // Changing a disabled entity's value doesn't touch the index at all.
+        world.get_mut::<Number>(a).unwrap().0 = 2;
+        world.increment_change_tick();
+        let mut idx = state.get_mut(&mut world);
+        idx.refresh();
+        assert!(idx.lookup(&Number(2)).is_empty());
+        assert_eq!(changes.load(Ordering::Relaxed), 0);
+
+        world.entity_mut(b).insert(Disabled);
+        world.increment_change_tick();
+        let mut idx = state.get_mut(&mut world);
+        idx.refresh();
+        assert!(idx.lookup(&Number(1)).is_empty());
+    }
+
+    struct UndisabledNumber;
+
+    impl IndexInfo for UndisabledNumber {
+        type Component = Number;
+        type Value = Number;
+        type Storage = FilteredStorage<Self, Without<Disabled>>;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> Number {
+            c.clone()
+        }
+    }
+
+    #[test]
+    fn test_filtered_storage() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn((Number(1), Disabled)).id();
+        let mut state = SystemState::<Index<UndisabledNumber>>::new(&mut world);
+        world.increment_change_tick();
+
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(1)), [a].into());
+
+        // Entities move in and out as they start and stop matching, without changing.
+        world.entity_mut(a).insert(Disabled);
+        world.entity_mut(b).remove::<Disabled>();
+        world.increment_change_tick();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(1)), [b].into());
+
+        world.get_mut::<Number>(a).unwrap().0 = 2;
+        world.increment_change_tick();
+        let mut idx = state.get_mut(&mut world);
+        assert!(idx.lookup(&Number(2)).is_empty());
+        world.entity_mut(a).remove::<Disabled>();
+        world.despawn(b);
+        world.increment_change_tick();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(2)), [a].into());
+        assert!(idx.lookup(&Number(1)).is_empty());
+    }
+
+    #[test]
+    fn test_refresh_rebuilds_after_many_changes() {
+        let mut world = World::new();
+        let entities: Vec<Entity> = (0..5).map(|i| world.spawn(Number(i)).id()).collect();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        world.increment_change_tick();
+        state.get_mut(&mut world).refresh();
+
+        // A few changes are applied entity by entity.
+        world.get_mut::<Number>(entities[0]).unwrap().0 = 10;
+        world.increment_change_tick();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(10)), [entities[0]].into());
+        assert_eq!(idx.storage.rebuilds, 0);
+        world.increment_change_tick();
+
+        // Change 4 of 5 components and remove the last, which is enough to rebuild.
+        for &entity in &entities[..4] {
+            world.get_mut::<Number>(entity).unwrap().0 += 10;
+        }
+        world.entity_mut(entities[4]).remove::<Number>();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.storage.rebuilds, 1);
+        assert!(idx.lookup(&Number(0)).is_empty());
+        assert!(idx.lookup(&Number(4)).is_empty());
+        assert_eq!(idx.lookup(&Number(20)), [entities[0]].into());
+        assert_eq!(idx.lookup(&Number(13)), [entities[3]].into());
+        let total: usize = idx.value_counts().iter().map(|(_, count)| count).sum();
+        assert_eq!(total, 4);
+        assert!(idx.verify().is_ok());
+    }
+
+    struct TrackedNumber;
+
+    impl IndexInfo for TrackedNumber {
+        type Component = Number;
+        type Value = Number;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+        const TRACK_CHANGE_TICKS: bool = true;
+        const TRACK_TRANSITIONS: bool = true;
+
+        fn value(c: &Number, _: &()) -> Number {
+            c.clone()
+        }
+    }
+
+    #[test]
+    fn test_changed_since() {
+        let mut world = World::new();
+        let entities: Vec<Entity> = (0..3).map(|i| world.spawn(Number(i)).id()).collect();
+        let mut state = SystemState::<(Index<TrackedNumber>, SystemChangeTick)>::new(&mut world);
+        world.increment_change_tick();
+
+        let (mut idx, ticks) = state.get_mut(&mut world);
+        let acked = ticks.this_run();
+        assert_eq!(idx.changed_since(Tick::new(0)).len(), 3);
+        assert!(idx.changed_since(acked).is_empty());
+
+        world.get_mut::<Number>(entities[1]).unwrap().0 = 10;
+        // Changes that leave the value the same don't count.
+        world.get_mut::<Number>(entities[2]).unwrap().0 = 2;
+        let (mut idx, ticks) = state.get_mut(&mut world);
+        assert_eq!(idx.changed_since(acked), vec![(entities[1], Number(10))]);
+        let acked = ticks.this_run();
+
+        // Changing every entity rebuilds the index, which keeps track of changes too.
+        for (i, &entity) in entities.iter().enumerate() {
+            world.get_mut::<Number>(entity).unwrap().0 = i + 20;
+        }
+        let (mut idx, _) = state.get_mut(&mut world);
+        let mut changed = idx.changed_since(acked);
+        changed.sort_by_key(|(_, value)| value.0);
+        assert_eq!(
+            changed,
+            entities
+                .iter()
+                .enumerate()
+                .map(|(i, &entity)| (entity, Number(i + 20)))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "TRACK_CHANGE_TICKS")]
+    fn test_changed_since_untracked() {
+        let mut world = World::new();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        state.get_mut(&mut world).changed_since(Tick::new(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "TRACK_TRANSITIONS")]
+    fn test_entered_untracked() {
+        let mut world = World::new();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        state.get_mut(&mut world).entered();
+    }
+
+    #[test]
+    fn test_entered_exited() {
+        let mut world = World::new();
+        world.insert_resource(FrameCount(0));
+        let a = world.spawn(Number(1)).id();
+        let mut state = SystemState::<Index<TrackedNumber>>::new(&mut world);
+        world.increment_change_tick();
+
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.entered(), vec![a]);
+        assert!(idx.exited().is_empty());
+
+        // Later refreshes in the same frame add to the frame's transitions.
+        let b = world.spawn(Number(2)).id();
+        let mut idx = state.get_mut(&mut world);
+        let entered: HashSet<Entity> = idx.entered().into_iter().collect();
+        assert_eq!(entered, [a, b].into());
+
+        world.resource_mut::<FrameCount>().0 += 1;
+        world.entity_mut(a).remove::<Number>();
+        // Changing a value is not a transition.
+        world.get_mut::<Number>(b).unwrap().0 = 3;
+        let mut idx = state.get_mut(&mut world);
+        assert!(idx.entered().is_empty());
+        assert_eq!(idx.exited(), vec![a]);
+
+        // Entering and exiting in the same frame cancel out.
+        world.resource_mut::<FrameCount>().0 += 1;
+        let c = world.spawn(Number(4)).id();
+        state.get_mut(&mut world);
+        world.despawn(c);
+        world.entity_mut(b).remove::<Number>();
+        world.entity_mut(b).insert(Number(3));
+        let mut idx = state.get_mut(&mut world);
+        assert!(idx.entered().is_empty());
+        assert!(idx.exited().is_empty());
+    }
+
+    #[test]
+    fn test_storage_debug() {
+        let mut world = World::new();
+        world.spawn_batch([Number(1), Number(1), Number(2)]);
+        let mut state = SystemState::<(Index<Number>, SystemChangeTick)>::new(&mut world);
+        let (idx, ticks) = state.get_mut(&mut world);
+        let tick = ticks.this_run().get();
+        assert_eq!(
+            format!("{:?}", *idx.storage),
+            format!("HashmapStorage {{ values: 2, entities: 3, last_refresh_tick: Some({tick}) }}")
+        );
+        assert!(format!("{:#?}", *idx.storage).contains("buckets: {"));
+    }
+
+    #[test]
+    fn test_take_single_changed() {
+        let mut world = World::new();
+        world.insert_resource(FrameCount(0));
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn(Number(2)).id();
+        let mut state = SystemState::<Index<TrackedNumber>>::new(&mut world);
+        world.increment_change_tick();
+
+        // Both entered this frame.
+        assert_eq!(state.get_mut(&mut world).take_single_changed(), None);
+
+        world.resource_mut::<FrameCount>().0 += 1;
+        assert_eq!(state.get_mut(&mut world).take_single_changed(), None);
+
+        world.resource_mut::<FrameCount>().0 += 1;
+        world.get_mut::<Number>(b).unwrap().0 = 3;
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.take_single_changed(), Some(b));
+        assert_eq!(idx.take_single_changed(), None);
+
+        world.resource_mut::<FrameCount>().0 += 1;
+        world.get_mut::<Number>(a).unwrap().0 = 4;
+        world.get_mut::<Number>(b).unwrap().0 = 4;
+        assert_eq!(state.get_mut(&mut world).take_single_changed(), None);
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut world = World::new();
+        let entities: Vec<_> = (0..10).map(|n| world.spawn(Number(n % 3)).id()).collect();
+        let mut state = SystemState::<(Index<Number>, Query<Entity>)>::new(&mut world);
+        state.get_mut(&mut world).0.refresh();
+
+        // Despawn half of the entities, and drop the removal events before the index sees them.
+        for &entity in entities.iter().step_by(2) {
+            world.despawn(entity);
+        }
+        world.clear_trackers();
+        world.clear_trackers();
+
+        let (mut idx, query) = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(0)).len(), 4);
+        idx.prune(|e| query.contains(e));
+        // Entities 3 and 9 are left with 0, 1 and 7 with 1, and 5 with 2.
+        assert_eq!(idx.lookup(&Number(0)), [entities[3], entities[9]].into());
+        assert_eq!(idx.lookup(&Number(1)), [entities[1], entities[7]].into());
+        assert_eq!(idx.lookup(&Number(2)), [entities[5]].into());
+        assert_eq!(idx.value_count(), 3);
+
+        // Values left without entities are dropped.
+        world.despawn(entities[5]);
+        world.clear_trackers();
+        world.clear_trackers();
+        let (mut idx, query) = state.get_mut(&mut world);
+        idx.prune(|e| query.contains(e));
+        assert_eq!(idx.value_count(), 2);
+    }
+
+    #[test]
+    fn test_value_and_entity_counts() {
+        let mut world = World::new();
+        // 1 entity with 0, 2 with 1, 3 with 2.
+        world.spawn_batch((0..3).flat_map(|n| (0..=n).map(move |_| Number(n))));
+        let mut state = SystemState::<(Index<Number>, Query<(Entity, &Number)>)>::new(&mut world);
+        let (mut idx, _) = state.get_mut(&mut world);
+
+        assert_eq!(idx.value_count(), 3);
+        for n in 0..3 {
+            assert_eq!(idx.entity_count_for(&Number(n)), n + 1);
+        }
+        assert_eq!(idx.entity_count_for(&Number(3)), 0);
+
+        // Removing the only 0 and moving both 1s away leaves a single value.
+        world.increment_change_tick();
+        let (_, query) = state.get_mut(&mut world);
+        let entities: Vec<(Entity, usize)> = query.iter().map(|(e, n)| (e, n.0)).collect();
+        for (entity, n) in entities {
+            match n {
+                0 => {
+                    world.entity_mut(entity).remove::<Number>();
+                }
+                1 => world.get_mut::<Number>(entity).unwrap().0 = 2,
+                _ => {}
+            }
+        }
+        let (mut idx, _) = state.get_mut(&mut world);
+        assert_eq!(idx.value_count(), 1);
+        assert_eq!(idx.entity_count_for(&Number(2)), 5);
+    }
+
+    #[test]
+    fn test_first_ticks() {
+        // Components that exist before anything has run, and ones spawned by the very
+        // first system, are both seen by the first refreshes.
+        let mut app = App::new();
+        app.world.spawn(Number(1));
+        app.add_systems(PreStartup, checker(1, 1))
+            .add_systems(Startup, |mut commands: Commands| {
+                commands.spawn(Number(2));
+            })
+            .add_systems(PostStartup, (checker(1, 1), checker(2, 1)))
+            .add_systems(First, (checker(1, 1), checker(2, 1)));
+        app.update();
+
+        let mut world = World::new();
+        world.spawn(Number(1));
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        assert_eq!(state.get_mut(&mut world).lookup(&Number(1)).len(), 1);
+        world.spawn(Number(1));
+        assert_eq!(state.get_mut(&mut world).lookup(&Number(1)).len(), 2);
+        world.increment_change_tick();
+        world.spawn(Number(1));
+        assert_eq!(state.get_mut(&mut world).lookup(&Number(1)).len(), 3);
+    }
+
+    #[test]
+    fn test_bucket_sizes() {
+        let check_sizes = |mut idx: Index<Number>| {
+            let sizes = idx.bucket_sizes();
+            assert_eq!(sizes.sizes[0], (Number(10), 2));
+            assert_eq!(sizes.sizes.len(), 3);
+            assert_eq!(sizes.total, 4);
+            assert_eq!(sizes.max, 2);
+        };
+
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(Update, check_sizes)
+            .run();
+    }
+
+    #[derive(Component)]
+    struct Parsed(&'static str);
+
+    impl IndexInfo for Parsed {
+        type Component = Self;
+        type Value = Option<usize>;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Self, _: &()) -> Option<usize> {
+            c.0.parse().ok()
+        }
+
+        fn try_value(c: &Self, _: &()) -> Option<Option<usize>> {
+            c.0.parse().ok().map(Some)
+        }
+
+        fn fallback_value() -> Option<Option<usize>> {
+            Some(None)
+        }
+    }
+
+    #[test]
+    fn test_fallback_value() {
+        let check = |mut idx: Index<Parsed>| {
+            assert_eq!(idx.lookup(&Some(1)).len(), 1);
+            assert_eq!(idx.lookup(&None).len(), 2);
+        };
+
+        let mut app = App::new();
+        app.add_systems(Update, check);
+        app.world.spawn(Parsed("1"));
+        app.world.spawn(Parsed("one"));
+        app.world.spawn(Parsed("uno"));
+        app.update();
+    }
+
+    #[derive(Component)]
+    struct Ordered(usize);
+
+    impl IndexInfo for Ordered {
+        type Component = Self;
+        type Value = usize;
+        type Storage = InsertionOrderStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Self, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    #[test]
+    fn test_lookup_ordered() {
+        let mut world = World::new();
+        let entities: Vec<Entity> = (0..3).map(|_| world.spawn(Ordered(1)).id()).collect();
+        let mut state = SystemState::<Index<Ordered>>::new(&mut world);
+        let ordered: Vec<Entity> = state
+            .get_mut(&mut world)
+            .lookup_ordered(&1)
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(ordered, entities);
+
+        // Moving an entity out of a bucket and back puts it at the end.
+        world.get_mut::<Ordered>(entities[0]).unwrap().0 = 2;
+        assert_eq!(state.get_mut(&mut world).lookup_ordered(&1).len(), 2);
+        world.get_mut::<Ordered>(entities[0]).unwrap().0 = 1;
+        let ordered: Vec<Entity> = state
+            .get_mut(&mut world)
+            .lookup_ordered(&1)
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(ordered, vec![entities[1], entities[2], entities[0]]);
+    }
+
+    #[derive(Component)]
+    struct OtherNumber(usize);
+
+    impl IndexInfo for OtherNumber {
+        type Component = Self;
+        type Value = Number;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Self, _: &()) -> Number {
+            Number(c.0)
+        }
+    }
+
+    #[test]
+    fn test_union_index() {
+        let check = |mut idx: UnionIndex<Number, OtherNumber>| {
+            assert_eq!(idx.lookup(&Number(10)).len(), 3);
+            assert_eq!(idx.lookup(&Number(20)).len(), 2);
+            assert_eq!(idx.lookup(&Number(40)).len(), 1);
+        };
+
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(Startup, |mut commands: Commands| {
+                commands.spawn(OtherNumber(10));
+                commands.spawn(OtherNumber(40));
+                // Only counted once
+                commands.spawn((Number(20), OtherNumber(20)));
+            })
+            .add_systems(Update, check)
+            .run();
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut world = World::new();
+        let entity = world.spawn(Number(1)).id();
+        world.spawn(Number(2));
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        assert!(state.get_mut(&mut world).verify().is_ok());
+
+        // Bypass the component to make the index inconsistent.
+        state.get_mut(&mut world).update(entity, Number(3));
+        let err = state.get_mut(&mut world).verify().unwrap_err();
+        assert_eq!(
+            err.discrepancies,
+            vec![Discrepancy {
+                entity,
+                expected: Some(Number(1)),
+                actual: Some(Number(3)),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_swap() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn(Number(2)).id();
+        let unindexed = world.spawn_empty().id();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        world.increment_change_tick();
+
+        let mut idx = state.get_mut(&mut world);
+        idx.swap(a, b);
+        assert_eq!(idx.lookup(&Number(1)), [b].into());
+        assert_eq!(idx.lookup(&Number(2)), [a].into());
+        // The index now disagrees with the components, even after refreshing.
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.verify().unwrap_err().discrepancies.len(), 2);
+
+        // Updating the components to match reconciles them.
+        world.get_mut::<Number>(a).unwrap().0 = 2;
+        world.get_mut::<Number>(b).unwrap().0 = 1;
+        let mut idx = state.get_mut(&mut world);
+        assert!(idx.verify().is_ok());
+        assert_eq!(idx.lookup(&Number(2)), [a].into());
+
+        // Swapping with an entity that isn't indexed moves it into the bucket.
+        idx.swap(a, unindexed);
+        assert_eq!(idx.lookup(&Number(2)), [unindexed].into());
+        assert!(!idx.contains_entity(a));
+    }
+
+    #[test]
+    fn test_partition() {
+        let check = |mut idx: Index<Number>, names: Query<&Name>| {
+            let groups = idx.partition(&Number(10), |e| names.get(e).is_ok());
+            assert_eq!(groups.len(), 2);
+            assert_eq!(groups[&true].len(), 1);
+            assert_eq!(groups[&false].len(), 2);
+            assert!(idx.partition(&Number(40), |_| ()).is_empty());
+        };
+
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(Startup, |mut commands: Commands| {
+                commands.spawn((Number(10), Name::new("named")));
+            })
+            .add_systems(Update, check)
+            .run();
+    }
+
+    #[test]
+    fn test_new_archetype_same_frame() {
+        let inserter = |mut commands: Commands, names: Query<Entity, With<Name>>| {
+            for entity in &names {
+                commands.entity(entity).insert(Number(50));
+            }
+        };
+
+        let mut app = App::new();
+        app.add_systems(
+            Update,
+            (checker(50, 0), inserter, apply_deferred, checker(50, 1)).chain(),
+        );
+        // The entity's archetype changes when the component is added, so it must be matched
+        // by the index's query right away.
+        app.world.spawn(Name::new("no number yet"));
+        app.update();
+    }
+
+    #[derive(Component)]
+    struct Rare(usize);
+
+    impl IndexInfo for Rare {
+        type Component = Self;
+        type Value = usize;
+        type Storage = SparseStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Self, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    struct InternedName;
+
+    impl IndexInfo for InternedName {
+        type Component = Name;
+        type Value = String;
+        type Storage = InternedStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Name, _: &()) -> String {
+            c.to_string()
+        }
+    }
+
+    #[test]
+    fn test_interned_storage() {
+        let mut world = World::new();
+        let a = world.spawn(Name::new("shared")).id();
+        let b = world.spawn(Name::new("shared")).id();
+        let c = world.spawn(Name::new("other")).id();
+        let mut state = SystemState::<Index<InternedName>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&"shared".to_string()), [a, b].into());
+
+        // Entities with equal values share one copy of it.
+        let (value_a, value_b) = (idx.interned_value_of(a), idx.interned_value_of(b));
+        assert!(Arc::ptr_eq(
+            value_a.as_ref().unwrap(),
+            value_b.as_ref().unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            value_a.as_ref().unwrap(),
+            &idx.interned_value_of(c).unwrap()
+        ));
+
+        // Moving to an existing value reuses its copy, and the old value is dropped once no
+        // entity has it.
+        world.get_mut::<Name>(c).unwrap().set("shared");
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&"shared".to_string()).len(), 3);
+        assert!(Arc::ptr_eq(
+            value_a.as_ref().unwrap(),
+            &idx.interned_value_of(c).unwrap()
+        ));
+        assert!(idx.lookup(&"other".to_string()).is_empty());
+    }
+
+    struct NameIgnoringCase;
+
+    impl IndexInfo for NameIgnoringCase {
+        type Component = Name;
+        type Value = KeyWrapper<String, CaseInsensitive>;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Name, _: &()) -> Self::Value {
+            KeyWrapper::new(c.to_string())
+        }
+    }
+
+    #[test]
+    fn test_key_wrapper() {
+        let mut world = World::new();
+        let upper = world.spawn(Name::new("Foo")).id();
+        let lower = world.spawn(Name::new("foo")).id();
+        world.spawn(Name::new("bar"));
+        let mut state = SystemState::<Index<NameIgnoringCase>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(
+            idx.lookup(&KeyWrapper::new("FOO".to_string())),
+            [upper, lower].into()
+        );
+        assert_eq!(idx.value_count(), 2);
+        // The natural value is kept.
+        assert_eq!(idx.value_of(upper).unwrap().get(), "Foo");
+    }
+
+    #[test]
+    fn test_sparse_storage() {
+        let mut world = World::new();
+        let a = world.spawn(Rare(1)).id();
+        let b = world.spawn(Rare(1)).id();
+        let mut state = SystemState::<Index<Rare>>::new(&mut world);
+        assert_eq!(state.get_mut(&mut world).lookup(&1).len(), 2);
+
+        world.get_mut::<Rare>(a).unwrap().0 = 2;
+        world.despawn(b);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&1).len(), 0);
+        assert_eq!(idx.lookup(&2).len(), 1);
+    }
+
+    #[derive(Component)]
+    struct Timer(f32);
+
+    impl IndexInfo for Timer {
+        type Component = Self;
+        type Value = TotalF32;
+        type Storage = OrderedStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Self, _: &()) -> TotalF32 {
+            TotalF32(c.0)
+        }
+    }
+
+    #[test]
+    fn test_lookup_approx() {
+        let mut world = World::new();
+        for t in [0.5, 0.74, 0.75, 1.0, 1.25, 1.26, 2.0] {
+            world.spawn(Timer(t));
+        }
+        let mut state = SystemState::<Index<Timer>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup_approx(&TotalF32(1.0), 0.25).len(), 3);
+        assert_eq!(idx.lookup_approx(&TotalF32(1.0), 0.0).len(), 1);
+        assert_eq!(idx.lookup_approx(&TotalF32(1.5), 0.1).len(), 0);
+        assert_eq!(idx.lookup_range(TotalF32(1.0)..).len(), 4);
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut world = World::new();
+        world.spawn(Number(1));
+        world.spawn(Number(1));
+        world.spawn(Number(2));
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+
+        let mut idx = state.get_mut(&mut world);
+        let mut drained: Vec<usize> = idx.drain().map(|(v, _)| v.0).collect();
+        drained.sort();
+        assert_eq!(drained, vec![1, 1, 2]);
+        assert_eq!(idx.lookup(&Number(1)).len(), 0);
+
+        // The next refresh rebuilds from the world.
+        assert_eq!(state.get_mut(&mut world).lookup(&Number(1)).len(), 2);
+    }
+
+    struct HideOwnChanges;
+
+    impl IndexInfo for HideOwnChanges {
+        type Component = Number;
+        type Value = Number;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+        const SEE_OWN_CHANGES: bool = false;
+
+        fn value(c: &Number, _: &()) -> Number {
+            c.clone()
+        }
+    }
+
+    #[test]
+    fn test_rebuild_hides_own_changes() {
+        let mut world = World::new();
+        let entities: Vec<Entity> = (0..5).map(|i| world.spawn(Number(i)).id()).collect();
+        let mut state = SystemState::<Index<HideOwnChanges>>::new(&mut world);
+        world.increment_change_tick();
+        state.get_mut(&mut world).refresh();
+        world.increment_change_tick();
+
+        // Enough changes from other systems to rebuild, and one at the refresh's own tick.
+        for &entity in &entities[..4] {
+            world.get_mut::<Number>(entity).unwrap().0 += 10;
+        }
+        world.increment_change_tick();
+        world.get_mut::<Number>(entities[4]).unwrap().0 += 10;
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.storage.rebuilds, 1);
+        assert_eq!(idx.lookup(&Number(13)), [entities[3]].into());
+        assert!(idx.lookup(&Number(14)).is_empty());
+        assert_eq!(idx.lookup(&Number(4)), [entities[4]].into());
+
+        world.increment_change_tick();
+        assert_eq!(
+            state.get_mut(&mut world).lookup(&Number(14)),
+            [entities[4]].into()
+        );
+    }
+
+    #[test]
+    fn test_own_change_visibility() {
+        let see_own = |mut params: ParamSet<(Query<&mut Number>, Index<Number>)>| {
+            for mut num in &mut params.p0() {
+                num.0 += 1;
+            }
+            let mut idx = params.p1();
+            idx.refresh();
+            assert_eq!(idx.lookup(&Number(11)).len(), 2);
+        };
+        let hide_own = |mut params: ParamSet<(Query<&mut Number>, Index<HideOwnChanges>)>| {
+            for mut num in &mut params.p0() {
+                num.0 += 1;
+            }
+            let mut idx = params.p1();
+            idx.refresh();
+            assert_eq!(idx.lookup(&Number(12)).len(), 0);
+            // Still indexed under the value from the last refresh in another system.
+            assert_eq!(idx.lookup(&Number(10)).len(), 2);
+        };
+        let check_hidden = |mut idx: Index<HideOwnChanges>| {
+            assert_eq!(idx.lookup(&Number(12)).len(), 2);
+        };
+
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(PreUpdate, |_: Index<HideOwnChanges>| {})
+            .add_systems(Update, (see_own, hide_own, check_hidden).chain())
+            .run();
+    }
+
+    struct Recent;
+
+    impl IndexInfo for Recent {
+        type Component = Number;
+        type Value = Number;
+        type Storage = LruStorage<Self, 2>;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> Number {
+            c.clone()
+        }
+    }
+
+    #[test]
+    fn test_lru_storage() {
+        let mut world = World::new();
+        world.spawn(Number(1));
+        world.spawn(Number(2));
+        let mut state = SystemState::<Index<Recent>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(1)).len(), 1);
+        assert_eq!(idx.lookup(&Number(2)).len(), 1);
+        assert_eq!(idx.lookup(&Number(1)).len(), 1);
+
+        // 2 was used least recently, so it is dropped.
+        world.increment_change_tick();
+        let three = world.spawn(Number(3)).id();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(1)).len(), 1);
+        assert_eq!(idx.lookup(&Number(3)).len(), 1);
+        assert_eq!(idx.lookup(&Number(2)).len(), 0);
+
+        // Looking it up brings it back with the next refresh, dropping the oldest of the others.
+        world.increment_change_tick();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(2)).len(), 1);
+        assert_eq!(idx.lookup(&Number(3)).len(), 1);
+        assert_eq!(idx.lookup(&Number(1)).len(), 0);
+
+        // Changing a component re-indexes it, alongside the dropped entity it joins.
+        world.increment_change_tick();
+        world.get_mut::<Number>(three).unwrap().0 = 1;
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Number(1)).len(), 2);
+    }
+
+    #[test]
+    fn test_handle() {
+        fn count_tens(handle: IndexHandle<Number>) -> usize {
+            handle.count(&Number(10))
+        }
+
+        let mut world = World::new();
+        let ten = world.spawn(Number(10)).id();
+        world.spawn(Number(10));
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(count_tens(idx.handle()), 2);
+
+        let mut handle = idx.handle();
+        assert_eq!(handle.get(&Number(10)).len(), 2);
+        assert_eq!(handle.count(&Number(20)), 0);
+        assert!(handle.contains(&Number(10), ten));
+        assert!(!handle.contains(&Number(20), ten));
+    }
+
+    #[derive(bevy::app::AppLabel, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+    struct Sub;
+
+    #[test]
+    fn test_sub_app() {
+        let mut sub_app = App::new();
+        sub_app
+            .add_systems(Update, checker(1, 1))
+            .add_systems(Update, checker(2, 0));
+        sub_app.world.spawn(Number(1));
+
+        let mut app = App::new();
+        app.add_systems(Update, checker(1, 0))
+            .add_systems(Update, checker(2, 2))
+            .insert_sub_app(Sub, bevy::app::SubApp::new(sub_app, |_, _| {}));
+        app.world.spawn(Number(2));
+        app.world.spawn(Number(2));
+        app.update();
+
+        // Each world has its own storage.
+        assert!(app.world.contains_resource::<HashmapStorage<Number>>());
+        assert!(app
+            .sub_app(Sub)
+            .world
+            .contains_resource::<HashmapStorage<Number>>());
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn(Number(2)).id();
+        world.spawn(Number(3));
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        state.get_mut(&mut world);
+
+        world.get_mut::<Number>(a).unwrap().0 = 5;
+        world.entity_mut(b).remove::<Number>();
+
+        // Fetch the storage without refreshing it, like an `Index` would see changes made
+        // after it was fetched.
+        type Unrefreshed<'w, 's> = (
+            ResMut<'w, HashmapStorage<Number>>,
+            StaticSystemParam<'w, 's, HashmapStorageRefreshData<'static, 'static, Number>>,
+        );
+        let mut raw_state = SystemState::<Unrefreshed>::new(&mut world);
+        let (storage, data) = raw_state.get_mut(&mut world);
+        let mut changes = storage.pending_changes(&data);
+        changes.sort_by_key(|c| matches!(c, IndexChange::Insert { .. }));
+        assert_eq!(
+            changes,
+            vec![
+                IndexChange::Remove {
+                    entity: b,
+                    old: Number(2)
+                },
+                IndexChange::Insert {
+                    entity: a,
+                    old: Some(Number(1)),
+                    new: Number(5)
+                },
+            ]
+        );
+
+        // Nothing was applied.
+        assert_eq!(storage.pending_changes(&data).len(), 2);
+        assert_eq!(storage.bucket(&Number(1)).len(), 1);
+        assert_eq!(state.get_mut(&mut world).dry_run().len(), 0);
+    }
+
+    #[derive(Resource)]
+    struct Scale(usize);
+
+    struct Scaled;
+
+    impl IndexInfo for Scaled {
+        type Component = Number;
+        type Value = usize;
+        type Storage = NoStorage<Self>;
+        type ValueParam = Res<'static, Scale>;
+
+        fn value(c: &Number, scale: &Res<Scale>) -> usize {
+            c.0 * scale.0
+        }
+    }
+
+    #[test]
+    fn test_value_param() {
+        let mut world = World::new();
+        world.spawn(Number(1));
+        world.spawn(Number(2));
+        world.insert_resource(Scale(10));
+        let mut state = SystemState::<Index<Scaled>>::new(&mut world);
+        assert_eq!(state.get_mut(&mut world).lookup(&20).len(), 1);
+
+        world.resource_mut::<Scale>().0 = 5;
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&20).len(), 0);
+        assert_eq!(idx.lookup(&10).len(), 1);
+    }
+
+    #[test]
+    fn test_value_of() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn(Name::new("no number")).id();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.value_of(a), Some(Number(1)));
+        assert_eq!(idx.value_ref_of(a), Some(&Number(1)));
+        assert_eq!(idx.value_of(b), None);
+        assert_eq!(idx.value_ref_of(b), None);
+    }
+
+    #[test]
+    fn test_lookup_where() {
+        let check = |mut idx: Index<Number>| {
+            assert_eq!(idx.lookup_where(|n| n.0 % 20 == 10).len(), 3);
+            assert_eq!(idx.lookup_where(|n| n.0 > 15).len(), 2);
+            assert_eq!(idx.lookup_where(|_| false).len(), 0);
+        };
+
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(Update, check)
+            .run();
+    }
+
+    type NumberAlias = Number;
+
+    struct NumberCopy;
+
+    impl IndexInfo for NumberCopy {
+        type Component = Number;
+        type Value = Number;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> Number {
+            Number(c.0 + 1)
+        }
+    }
+
+    #[test]
+    fn test_distinct_storage() {
+        let check = |mut a: Index<Number>, mut c: Index<NumberCopy>| {
+            assert_eq!(a.lookup(&Number(10)).len(), 2);
+            assert_eq!(c.lookup(&Number(10)).len(), 0);
+            assert_eq!(c.lookup(&Number(11)).len(), 2);
+        };
+        // An alias is the same index, so it sees the same data.
+        let check_alias = |mut b: Index<NumberAlias>| {
+            assert_eq!(b.lookup(&Number(10)).len(), 2);
+        };
+
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(Update, (check, check_alias))
+            .run();
+    }
+
+    #[derive(Resource, Default)]
+    struct SharedStorage;
+
+    impl<I: IndexInfo> IndexStorage<I> for SharedStorage {
+        type RefreshData<'w, 's> = ();
+
+        fn lookup<'w, 's>(
+            &mut self,
+            _val: &I::Value,
+            _data: &mut StaticSystemParam<()>,
+        ) -> bevy::utils::HashSet<Entity> {
+            Default::default()
+        }
+
+        fn refresh<'w, 's>(&mut self, _data: &mut StaticSystemParam<()>) {}
+    }
+
+    struct SharedA;
+    struct SharedB;
+
+    impl IndexInfo for SharedA {
+        type Component = Number;
+        type Value = Number;
+        type Storage = SharedStorage;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> Number {
+            c.clone()
+        }
+    }
+
+    impl IndexInfo for SharedB {
+        type Component = Number;
+        type Value = Number;
+        type Storage = SharedStorage;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> Number {
+            c.clone()
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "both use")]
+    #[cfg(debug_assertions)]
+    fn test_shared_storage_detection() {
+        let mut world = World::new();
+        SystemState::<Index<SharedA>>::new(&mut world);
+        SystemState::<Index<SharedB>>::new(&mut world);
+    }
+
+    #[test]
+    fn test_apply_evicts_despawned() {
+        let despawn_twenties = |mut commands: Commands, mut idx: Index<Number>| {
+            for entity in idx.lookup(&Number(20)) {
+                commands.entity(entity).despawn();
+            }
+        };
+        // Read the storage directly so that no refresh happens.
+        let check_storage = |storage: Res<HashmapStorage<Number>>| {
+            assert_eq!(storage.bucket(&Number(20)).len(), 0);
+            assert_eq!(storage.bucket(&Number(10)).len(), 2);
+        };
+
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(
+                Update,
+                (despawn_twenties, apply_deferred, check_storage).chain(),
+            )
+            .run();
+    }
+
+    #[test]
+    fn test_lookup_with() {
+        let check = |mut idx: Index<NumberCopy>| {
+            let found: Vec<usize> = idx.lookup_with(&Number(11)).map(|(_, n)| n.0).collect();
+            assert_eq!(found, vec![10, 10]);
+            assert_eq!(idx.lookup_with(&Number(10)).count(), 0);
+        };
+
+        App::new()
+            .add_systems(Startup, add_some_numbers)
+            .add_systems(Update, check)
+            .run();
+    }
+
+    #[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    enum State {
+        Idle,
+        Active,
+        Dead,
+    }
+
+    impl IndexInfo for State {
+        type Component = Self;
+        type Value = Self;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Self, _: &()) -> Self {
+            *c
+        }
+
+        fn should_index(value: &Self) -> bool {
+            *value != State::Dead
+        }
+    }
+
+    #[test]
+    fn test_should_index() {
+        let mut world = World::new();
+        let entity = world.spawn(State::Idle).id();
+        let mut state = SystemState::<Index<State>>::new(&mut world);
+
+        for s in [State::Active, State::Dead, State::Idle, State::Dead] {
+            *world.get_mut::<State>(entity).unwrap() = s;
+            let mut idx = state.get_mut(&mut world);
+            let expected = if s == State::Dead { None } else { Some(s) };
+            assert_eq!(idx.value_of(entity), expected);
+            assert_eq!(idx.lookup(&State::Dead).len(), 0);
+            assert!(idx.verify().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_lookup_or() {
+        let mut world = World::new();
+        let one = world.spawn(Number(1)).id();
+        let two = world.spawn(Number(2)).id();
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+
+        assert_eq!(idx.lookup_or(&Number(1), &Number(2)), [one].into());
+        assert_eq!(idx.lookup_or(&Number(3), &Number(2)), [two].into());
+        assert!(idx.lookup_or(&Number(3), &Number(4)).is_empty());
+    }
+
+    #[derive(Component, Clone, Eq, Hash, PartialEq, Debug)]
+    struct Squad(u32);
+
+    impl IndexInfo for Squad {
+        type Component = Self;
+        type Value = Self;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Self, _: &()) -> Self {
+            c.clone()
+        }
+    }
+
+    #[test]
+    fn test_lookup_then() {
+        let mut world = World::new();
+        // Squad leaders are grouped by platoon number, and lead the squad of their own id.
+        let leaders: Vec<Entity> = (0..3)
+            .map(|i| world.spawn((Number(i / 2), Squad(i as u32))).id())
+            .collect();
+        let members: Vec<Entity> = (0..3).map(|i| world.spawn(Squad(i as u32)).id()).collect();
+        let led = |leader: Entity| {
+            let i = leaders.iter().position(|&l| l == leader).unwrap();
+            Squad(i as u32)
+        };
+        let mut state = SystemState::<(Index<Number>, Index<Squad>)>::new(&mut world);
+        let (mut platoons, mut squads) = state.get_mut(&mut world);
+
+        assert_eq!(
+            platoons.lookup_then(&Number(0), &mut squads, led),
+            [leaders[0], leaders[1], members[0], members[1]].into()
+        );
+        assert_eq!(
+            platoons.lookup_then(&Number(1), &mut squads, led),
+            [leaders[2], members[2]].into()
+        );
+        assert!(platoons
+            .lookup_then(&Number(2), &mut squads, led)
+            .is_empty());
+    }
+
+    struct CachedNumber;
+
+    impl IndexInfo for CachedNumber {
+        type Component = Number;
+        type Value = usize;
+        type Storage = crate::storage::CachedStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    #[test]
+    fn test_lookup_cached() {
+        let mut world = World::new();
+        let one = world.spawn(Number(1)).id();
+        let two = world.spawn(Number(2)).id();
+        let mut state = SystemState::<Index<CachedNumber>>::new(&mut world);
+        world.increment_change_tick();
+        let cached = state.get_mut(&mut world).lookup_cached(&1);
+        assert_eq!(*cached, [one].into());
+
+        // Changes to other buckets keep the cached set.
+        world.get_mut::<Number>(two).unwrap().0 = 3;
+        let mut idx = state.get_mut(&mut world);
+        assert!(Arc::ptr_eq(&cached, &idx.lookup_cached(&1)));
+        assert_eq!(*idx.lookup_cached(&3), [two].into());
+
+        // Moving an entity into the bucket drops it.
+        world.increment_change_tick();
+        world.get_mut::<Number>(two).unwrap().0 = 1;
+        let recached = state.get_mut(&mut world).lookup_cached(&1);
+        assert!(!Arc::ptr_eq(&cached, &recached));
+        assert_eq!(*recached, [one, two].into());
+        assert!(state.get_mut(&mut world).lookup_cached(&3).is_empty());
+
+        // And so does removing one.
+        world.increment_change_tick();
+        world.entity_mut(one).remove::<Number>();
+        assert_eq!(*state.get_mut(&mut world).lookup_cached(&1), [two].into());
+    }
+
+    #[test]
+    fn test_lookup_first() {
+        let mut world = World::new();
+        let two = world.spawn(Number(2)).id();
+        world.spawn(Number(3));
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+
+        assert_eq!(
+            idx.lookup_first(&[Number(1), Number(2), Number(3)]),
+            Some((Number(2), [two].into()))
+        );
+        assert_eq!(idx.lookup_first(&[Number(4), Number(5)]), None);
+        assert_eq!(idx.lookup_first(&[]), None);
+    }
+
+    struct Health;
+    struct Mana;
+
+    #[derive(Component)]
+    struct Stat<K: Send + Sync + 'static> {
+        value: u32,
+        phantom: PhantomData<K>,
+    }
+
+    impl<K: Send + Sync + 'static> Stat<K> {
+        fn new(value: u32) -> Self {
+            Self {
+                value,
+                phantom: PhantomData,
+            }
+        }
+    }
+
+    struct StatIndex<K>(PhantomData<K>);
+
+    impl<K: Send + Sync + 'static> IndexInfo for StatIndex<K> {
+        type Component = Stat<K>;
+        type Value = u32;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Stat<K>, _: &()) -> u32 {
+            c.value
+        }
+    }
+
+    #[test]
+    fn test_generic_components() {
+        let mut world = World::new();
+        let a = world
+            .spawn((Stat::<Health>::new(10), Stat::<Mana>::new(5)))
+            .id();
+        let b = world
+            .spawn((Stat::<Health>::new(5), Stat::<Mana>::new(10)))
+            .id();
+        let mut state =
+            SystemState::<(Index<StatIndex<Health>>, Index<StatIndex<Mana>>)>::new(&mut world);
+
+        let (mut health, mut mana) = state.get_mut(&mut world);
+        assert_eq!(health.lookup(&10), [a].into());
+        assert_eq!(mana.lookup(&10), [b].into());
+
+        world.get_mut::<Stat<Mana>>(a).unwrap().value = 10;
+        let (mut health, mut mana) = state.get_mut(&mut world);
+        assert_eq!(health.lookup(&10), [a].into());
+        assert_eq!(mana.lookup(&10), [a, b].into());
+    }
+
+    struct SharedNumber;
+
+    impl IndexInfo for SharedNumber {
+        type Component = Number;
+        type Value = usize;
+        type Storage = crate::storage::SharedStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    #[test]
+    fn test_lookup_shared() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let mut state = SystemState::<Index<SharedNumber>>::new(&mut world);
+
+        let mut idx = state.get_mut(&mut world);
+        let first = idx.lookup_shared(&1);
+        let again = idx.lookup_shared(&1);
+        assert!(Arc::ptr_eq(&first, &again));
+        drop(again);
+
+        let b = world.spawn(Number(1)).id();
+        let mut idx = state.get_mut(&mut world);
+        // The earlier lookup keeps its snapshot while the bucket is copied for the change.
+        assert_eq!(*first, [a].into());
+        assert_eq!(*idx.lookup_shared(&1), [a, b].into());
+        assert_eq!(idx.lookup(&1), [a, b].into());
+        assert!(idx.verify().is_ok());
+    }
+
+    struct Rank;
+
+    impl IndexInfo for Rank {
+        type Component = Number;
+        type Value = usize;
+        type Storage = OrderedStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    #[test]
+    fn test_iter_ordered() {
+        let mut world = World::new();
+        let a = world.spawn(Number(3)).id();
+        let b = world.spawn(Number(1)).id();
+        let c = world.spawn(Number(3)).id();
+        let mut state = SystemState::<Index<Rank>>::new(&mut world);
+
+        let idx = state.get_mut(&mut world);
+        let buckets: Vec<_> = idx.iter_ordered().map(|(v, s)| (*v, s.clone())).collect();
+        assert_eq!(buckets, vec![(1, [b].into()), (3, [a, c].into())]);
+
+        world.get_mut::<Number>(b).unwrap().0 = 5;
+        world.despawn(a);
+        world.despawn(c);
+        let mut idx = state.get_mut(&mut world);
+        idx.refresh();
+        let buckets: Vec<_> = idx.iter_ordered().map(|(v, s)| (*v, s.clone())).collect();
+        assert_eq!(buckets, vec![(5, [b].into())]);
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn test_iter_range() {
+        let mut world = World::new();
+        let entities: Vec<_> = (0..5).map(|n| world.spawn(Number(n)).id()).collect();
+        world.spawn(Number(2));
+        let mut state = SystemState::<Index<Rank>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+
+        let values = |iter: &mut dyn Iterator<Item = (&usize, &HashSet<Entity>)>| {
+            iter.map(|(v, s)| (*v, s.len())).collect::<Vec<_>>()
+        };
+        assert_eq!(values(&mut idx.iter_range(1..3)), vec![(1, 1), (2, 2)]);
+        assert_eq!(values(&mut idx.iter_range(3..)), vec![(3, 1), (4, 1)]);
+        assert_eq!(values(&mut idx.iter_range(..=1)), vec![(0, 1), (1, 1)]);
+        let (_, bucket) = idx.iter_range(4..).next().unwrap();
+        assert_eq!(*bucket, [entities[4]].into());
+
+        // Reversed and empty bounds yield nothing.
+        assert!(idx.iter_range(3..1).next().is_none());
+        assert!(idx.iter_range(2..2).next().is_none());
+        assert!(idx.lookup_range(4..=0).is_empty());
+        let exclusive = (std::ops::Bound::Excluded(2), std::ops::Bound::Excluded(2));
+        assert!(idx.iter_range(exclusive).next().is_none());
+    }
+
+    #[test]
+    fn test_value_counts() {
+        let mut world = World::new();
+        let moved = world.spawn(Number(1)).id();
+        world.spawn_batch([Number(3), Number(3), Number(3), Number(2)]);
+        let mut state = SystemState::<(Index<Number>, Index<Rank>)>::new(&mut world);
+        let (mut idx, mut rank) = state.get_mut(&mut world);
+
+        let mut counts = idx.value_counts();
+        counts.sort_unstable_by_key(|(val, _)| val.0);
+        assert_eq!(counts, vec![(Number(1), 1), (Number(2), 1), (Number(3), 3)]);
+        assert_eq!(rank.value_counts_sorted(), vec![(1, 1), (2, 1), (3, 3)]);
+
+        // Emptied values are left out.
+        world.get_mut::<Number>(moved).unwrap().0 = 2;
+        let (mut idx, mut rank) = state.get_mut(&mut world);
+        assert_eq!(idx.value_counts().len(), 2);
+        assert_eq!(rank.value_counts_sorted(), vec![(2, 2), (3, 3)]);
+    }
+
+    struct NumberValue;
+
+    impl IndexInfo for NumberValue {
+        type Component = Number;
+        type Value = usize;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    struct SparseNumber;
+
+    impl IndexInfo for SparseNumber {
+        type Component = Number;
+        type Value = usize;
+        type Storage = SparseStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    /// Small deterministic PRNG (xorshift64) so failures can be reproduced from the seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    const FUZZ_VALUES: usize = 6;
+
+    // Perform random spawns, despawns, mutations, and component removals across several
+    // frames, checking every lookup against a scan of the world.
+    fn fuzz<I: IndexInfo<Component = Number, Value = usize>>(seed: u64) {
+        let mut rng = Rng(seed);
+        let mut world = World::new();
+        let mut state = SystemState::<Index<I>>::new(&mut world);
+        let mut scan = world.query::<(Entity, &Number)>();
+        let mut entities: Vec<Entity> = Vec::new();
+
+        for frame in 0..50 {
+            for _ in 0..rng.below(8) {
+                match rng.below(5) {
+                    0 | 1 => entities.push(world.spawn(Number(rng.below(FUZZ_VALUES))).id()),
+                    _ if entities.is_empty() => {}
+                    2 => {
+                        let e = entities[rng.below(entities.len())];
+                        if let Some(mut num) = world.get_mut::<Number>(e) {
+                            num.0 = rng.below(FUZZ_VALUES);
+                        }
+                    }
+                    3 => {
+                        let e = entities.swap_remove(rng.below(entities.len()));
+                        world.despawn(e);
+                    }
+                    _ => {
+                        // Toggle the component so that entities can come back with a new value.
+                        let e = entities[rng.below(entities.len())];
+                        if world.entity_mut(e).take::<Number>().is_none() {
+                            world.entity_mut(e).insert(Number(rng.below(FUZZ_VALUES)));
+                        }
+                    }
+                }
+            }
+
+            let mut expected = vec![HashSet::new(); FUZZ_VALUES];
+            for (e, num) in scan.iter(&world) {
+                expected[num.0].insert(e);
+            }
+            let mut idx = state.get_mut(&mut world);
+            for (val, set) in expected.iter().enumerate() {
+                assert_eq!(
+                    &idx.lookup(&val),
+                    set,
+                    "seed {seed}, frame {frame}: wrong entities for {val}"
+                );
+            }
+            drop(idx);
+            world.clear_trackers();
+        }
+    }
+
+    #[test]
+    fn test_fuzz_storages() {
+        for seed in 1..=20 {
+            fuzz::<NumberValue>(seed);
+            fuzz::<SharedNumber>(seed);
+            fuzz::<Rank>(seed);
+            fuzz::<SparseNumber>(seed);
+        }
+    }
+
+    #[test]
+    fn test_map_ref() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn(Number(2)).id();
+        SystemState::<Index<Number>>::new(&mut world).get_mut(&mut world);
+
+        let map = world.resource::<HashmapStorage<Number>>().map_ref();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.key_count(), 2);
+        assert_eq!(map.key_of(&a), Some(&Number(1)));
+        assert_eq!(map.get(&Number(2)), [b].into());
+    }
+
+    #[test]
+    fn test_lookup_by_hash() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        world.spawn(Number(2));
+        let mut state = SystemState::<Index<Number>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+
+        let hash = Index::<Number>::hash_value(&Number(1));
+        assert_eq!(hash, Index::<Number>::hash_value(&Number(1)));
+        assert_eq!(idx.lookup_by_hash(hash), [a].into());
+        assert!(idx
+            .lookup_by_hash(Index::<Number>::hash_value(&Number(3)))
+            .is_empty());
+
+        // Refreshes keep the values grouped by hash as they come and go.
+        world.increment_change_tick();
+        world.get_mut::<Number>(a).unwrap().0 = 3;
+        let mut idx = state.get_mut(&mut world);
+        assert!(idx.lookup_by_hash(hash).is_empty());
+        assert_eq!(
+            idx.lookup_by_hash(Index::<Number>::hash_value(&Number(3))),
+            [a].into()
+        );
+    }
+
+    struct EagerValue;
+
+    impl IndexInfo for EagerValue {
+        type Component = Number;
+        type Value = usize;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+        const REFRESH: RefreshPolicy = RefreshPolicy::Eager;
+
+        fn value(c: &Number, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    #[test]
+    fn test_refresh_entities() {
+        let mut world = World::new();
+        world.insert_resource(PluginAdded::<EagerValue>::new());
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn(Number(1)).id();
+        let c = world.spawn(Number(1)).id();
+        let mut state = SystemState::<Index<EagerValue>>::new(&mut world);
+        state.get_mut(&mut world).refresh();
+
+        for e in [a, b] {
+            world.get_mut::<Number>(e).unwrap().0 = 2;
+        }
+        world.despawn(c);
+        let mut idx = state.get_mut(&mut world);
+        idx.refresh_entities(&[a, c]);
+        assert_eq!(idx.lookup(&2), [a].into());
+        assert_eq!(idx.lookup(&1), [b].into());
+
+        idx.refresh();
+        assert_eq!(idx.lookup(&2), [a, b].into());
+        assert!(idx.lookup(&1).is_empty());
+    }
+
+    #[derive(Component)]
+    struct Voxel([i32; 3]);
+
+    impl IndexInfo for Voxel {
+        type Component = Self;
+        type Value = [i32; 3];
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Self, _: &()) -> [i32; 3] {
+            c.0
+        }
+    }
+
+    #[test]
+    fn test_array_keys() {
+        let mut world = World::new();
+        let origin = world.spawn(Voxel([0, 0, 0])).id();
+        let corner = world.spawn(Voxel([1, -1, 1])).id();
+        let far = world.spawn(Voxel([0, 0, 2])).id();
+        let mut state = SystemState::<Index<Voxel>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+
+        assert_eq!(idx.lookup(&[1, -1, 1]), [corner].into());
+        assert!(idx.lookup(&[1, 1, 1]).is_empty());
+        assert_eq!(idx.lookup_cube([0, 0, 0], 0), [origin].into());
+        assert_eq!(idx.lookup_cube([0, 0, 0], 1), [origin, corner].into());
+        assert_eq!(idx.lookup_cube([0, 0, 1], 1), [origin, corner, far].into());
+        assert!(idx.lookup_cube([0, 0, 0], -1).is_empty());
+        assert!(idx.lookup_cube([0, 0, 0], i32::MIN).is_empty());
+
+        // The cube is cut off at the edges of the coordinate range instead of overflowing.
+        let edge = world.spawn(Voxel([i32::MAX, i32::MIN, 0])).id();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup_cube([i32::MAX, i32::MIN, 0], 1), [edge].into());
+    }
+
+    #[test]
+    fn test_run_system_once() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn(Number(1)).id();
+        let lookup = |world: &mut World, n| {
+            world.run_system_once(move |mut idx: Index<NumberValue>| idx.lookup(&n))
+        };
+        assert_eq!(lookup(&mut world, 1), [a, b].into());
+
+        world.get_mut::<Number>(a).unwrap().0 = 2;
+        world.despawn(b);
+        assert_eq!(lookup(&mut world, 1), HashSet::new());
+        assert_eq!(lookup(&mut world, 2), [a].into());
+    }
+
+    // Every system that uses an index has its own reader for removals, so a system can see
+    // a removal after another system has already indexed the re-added component.
+    fn check_remove_and_readd<I: IndexInfo<Component = Number, Value = usize>>() {
+        let mut world = World::new();
+        let e = world.spawn(Number(1)).id();
+        let mut first = SystemState::<Index<I>>::new(&mut world);
+        let mut second = SystemState::<Index<I>>::new(&mut world);
+        first.get_mut(&mut world);
+        second.get_mut(&mut world);
+
+        world.entity_mut(e).remove::<Number>();
+        world.entity_mut(e).insert(Number(1));
+        world.increment_change_tick();
+        assert_eq!(first.get_mut(&mut world).lookup(&1), [e].into());
+        world.increment_change_tick();
+        assert_eq!(second.get_mut(&mut world).lookup(&1), [e].into());
+        assert_eq!(first.get_mut(&mut world).lookup(&1), [e].into());
+    }
+
+    #[test]
+    fn test_remove_and_readd_same_value() {
+        check_remove_and_readd::<NumberValue>();
+        check_remove_and_readd::<SharedNumber>();
+        check_remove_and_readd::<Rank>();
+        check_remove_and_readd::<SparseNumber>();
+    }
+
+    #[test]
+    fn test_refresh_reserving() {
+        let mut world = World::new();
+        world.insert_resource(PluginAdded::<EagerValue>::new());
+        world.spawn_batch((0..1000).map(|i| Number(i % 10)));
+        let mut state = SystemState::<Index<EagerValue>>::new(&mut world);
+
+        let mut idx = state.get_mut(&mut world);
+        idx.refresh_reserving();
+        assert_eq!(idx.lookup(&3).len(), 100);
+
+        world.spawn_batch((0..1000).map(|i| Number(i % 10)));
+        world.increment_change_tick();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&3).len(), 100);
+        idx.refresh_reserving();
+        assert_eq!(idx.lookup(&3).len(), 200);
+        assert!(idx.verify().is_ok());
+    }
+
+    #[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    struct Team(u32);
+
+    #[derive(Component)]
+    struct Targeting(Entity);
+
+    struct TargetTeam;
+
+    impl IndexInfo for TargetTeam {
+        type Component = Targeting;
+        type Value = Team;
+        type Storage = DependentStorage<Self, Team>;
+        type ValueParam = Query<'static, 'static, &'static Team>;
+
+        fn value(_: &Targeting, _: &Query<&Team>) -> Team {
+            unreachable!()
+        }
+
+        fn try_value(c: &Targeting, teams: &Query<&Team>) -> Option<Team> {
+            teams.get(c.0).ok().copied()
+        }
+
+        fn dependency(c: &Targeting) -> Option<Entity> {
+            Some(c.0)
+        }
+    }
+
+    #[test]
+    fn test_dependent_storage() {
+        let mut world = World::new();
+        let target = world.spawn(Team(1)).id();
+        let other = world.spawn(Team(3)).id();
+        let source = world.spawn(Targeting(target)).id();
+        let mut state = SystemState::<Index<TargetTeam>>::new(&mut world);
+        assert_eq!(state.get_mut(&mut world).lookup(&Team(1)), [source].into());
+
+        // Changing the target re-indexes the source.
+        world.get_mut::<Team>(target).unwrap().0 = 2;
+        let mut idx = state.get_mut(&mut world);
+        assert!(idx.lookup(&Team(1)).is_empty());
+        assert_eq!(idx.lookup(&Team(2)), [source].into());
+
+        // After retargeting, only the new target matters.
+        world.get_mut::<Targeting>(source).unwrap().0 = other;
+        assert_eq!(state.get_mut(&mut world).lookup(&Team(3)), [source].into());
+        world.get_mut::<Team>(target).unwrap().0 = 4;
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup(&Team(3)), [source].into());
+        assert!(idx.lookup(&Team(4)).is_empty());
+
+        // Without a target, the source has no value.
+        world.despawn(other);
+        assert!(state.get_mut(&mut world).lookup(&Team(3)).is_empty());
+    }
+
+    #[test]
+    fn test_no_empty_buckets() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn(Number(2)).id();
+        let mut hashmap = SystemState::<Index<NumberValue>>::new(&mut world);
+        let mut ordered = SystemState::<Index<Rank>>::new(&mut world);
+        hashmap.get_mut(&mut world);
+        ordered.get_mut(&mut world);
+
+        world.despawn(a);
+        world.get_mut::<Number>(b).unwrap().0 = 3;
+        let sizes = hashmap.get_mut(&mut world).bucket_sizes();
+        assert_eq!(sizes.sizes, vec![(3, 1)]);
+        let values: Vec<_> = ordered
+            .get_mut(&mut world)
+            .iter_ordered()
+            .map(|(v, _)| *v)
+            .collect();
+        assert_eq!(values, vec![3]);
+    }
+
+    #[test]
+    fn test_value_ids() {
+        let mut world = World::new();
+        let a = world.spawn(Number(5)).id();
+        let b = world.spawn(Number(7)).id();
+        let mut state = SystemState::<Index<NumberValue>>::new(&mut world);
+
+        let mut idx = state.get_mut(&mut world);
+        let five = idx.value_id(&5);
+        let seven = idx.value_id(&7);
+        assert_ne!(five, seven);
+        assert_eq!(idx.value_id(&5), five);
+        assert_eq!(idx.lookup_by_value_id(five), [a].into());
+        assert!(idx.lookup_by_value_id(seven + 100).is_empty());
+
+        // Ids outlive the buckets they refer to.
+        world.get_mut::<Number>(b).unwrap().0 = 5;
+        let mut idx = state.get_mut(&mut world);
+        assert!(idx.lookup_by_value_id(seven).is_empty());
+        assert_eq!(idx.lookup_by_value_id(five), [a, b].into());
+        assert_eq!(idx.value_id(&7), seven);
+    }
+
+    #[derive(Component)]
+    struct Weight(u32);
+
+    struct ChildWeight;
+
+    impl IndexInfo for ChildWeight {
+        type Component = Children;
+        type Value = u32;
+        type Storage = DependentStorage<Self, Weight>;
+        type ValueParam = Query<'static, 'static, &'static Weight>;
+
+        fn value(children: &Children, weights: &Query<&Weight>) -> u32 {
+            // Bucketed to the nearest 10.
+            weights.iter_many(children).map(|w| w.0).sum::<u32>() / 10 * 10
+        }
+
+        fn dependencies(children: &Children) -> impl Iterator<Item = Entity> {
+            children.iter().copied()
+        }
+    }
+
+    #[test]
+    fn test_children_aggregate() {
+        let mut world = World::new();
+        let a = world.spawn(Weight(5)).id();
+        let b = world.spawn(Weight(6)).id();
+        let c = world.spawn(Weight(20)).id();
+        let parent = world.spawn_empty().push_children(&[a, b]).id();
+        let mut state = SystemState::<Index<ChildWeight>>::new(&mut world);
+        assert_eq!(state.get_mut(&mut world).lookup(&10), [parent].into());
+
+        // A child's component changing.
+        world.get_mut::<Weight>(b).unwrap().0 = 16;
+        assert_eq!(state.get_mut(&mut world).lookup(&20), [parent].into());
+
+        // The set of children changing.
+        world.entity_mut(parent).push_children(&[c]);
+        assert_eq!(state.get_mut(&mut world).lookup(&40), [parent].into());
+        world.entity_mut(parent).remove_children(&[a]);
+        let mut idx = state.get_mut(&mut world);
+        assert!(idx.lookup(&40).is_empty());
+        assert_eq!(idx.lookup(&30), [parent].into());
+
+        // The removed child no longer affects the parent.
+        world.get_mut::<Weight>(a).unwrap().0 = 100;
+        assert_eq!(state.get_mut(&mut world).lookup(&30), [parent].into());
+    }
+
+    #[test]
+    fn test_take() {
+        let mut world = World::new();
+        let a = world.spawn(Number(1)).id();
+        let b = world.spawn(Number(1)).id();
+        let c = world.spawn(Number(2)).id();
+        let mut state = SystemState::<Index<TrackedNumber>>::new(&mut world);
+        // Changes made at the same tick as a refresh are seen again by the next one, so
+        // separate the spawns from the refresh like separate systems would be.
+        world.increment_change_tick();
+
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.take(&Number(1)), [a, b].into());
+        assert!(idx.take(&Number(1)).is_empty());
+        assert_eq!(idx.lookup(&Number(2)), [c].into());
+        // Taken entities no longer count as having entered or changed.
+        assert_eq!(idx.entered(), vec![c]);
+        assert_eq!(idx.take_single_changed(), Some(c));
+
+        // Unchanged entities aren't re-added by the next refresh.
+        assert!(state.get_mut(&mut world).lookup(&Number(1)).is_empty());
+        world.get_mut::<Number>(a).unwrap().0 = 1;
+        assert_eq!(state.get_mut(&mut world).lookup(&Number(1)), [a].into());
+    }
+
+    #[derive(Component, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct Score(u32);
+
+    struct ScoreByTeam;
+
+    impl IndexInfo for ScoreByTeam {
+        type Component = Team;
+        type Value = Team;
+        type Storage = GroupMaxStorage<Self, Score>;
+        type ValueParam = ();
+
+        fn value(c: &Team, _: &()) -> Team {
+            *c
+        }
+    }
+
+    #[test]
+    fn test_group_max() {
+        let mut world = World::new();
+        let a = world.spawn((Team(1), Score(10))).id();
+        let b = world.spawn((Team(1), Score(5))).id();
+        let c = world.spawn((Team(2), Score(7))).id();
+        world.spawn(Team(3));
+        let mut state = SystemState::<Index<ScoreByTeam>>::new(&mut world);
+
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.group_max(&Team(1)), Some((Score(10), a)));
+        assert_eq!(idx.group_max(&Team(2)), Some((Score(7), c)));
+        assert_eq!(idx.group_max(&Team(3)), None);
+        assert_eq!(idx.lookup(&Team(3)).len(), 1);
+
+        // Decreasing the current max lets the runner-up take over.
+        world.increment_change_tick();
+        world.get_mut::<Score>(a).unwrap().0 = 1;
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.group_max(&Team(1)), Some((Score(5), b)));
+
+        // Moving between groups and losing the measure both update the max.
+        world.increment_change_tick();
+        *world.get_mut::<Team>(c).unwrap() = Team(1);
+        world.entity_mut(b).remove::<Score>();
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.group_max(&Team(1)), Some((Score(7), c)));
+        assert_eq!(idx.group_max(&Team(2)), None);
+
+        world.increment_change_tick();
+        world.despawn(c);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.group_max(&Team(1)), Some((Score(1), a)));
+    }
+
+    struct EntitySetNumber;
+
+    impl IndexInfo for EntitySetNumber {
+        type Component = Number;
+        type Value = usize;
+        type Storage = EntitySetStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    #[test]
+    fn test_lookup_entityset() {
+        let mut world = World::new();
+        let even: HashSet<Entity> = world.spawn_batch((0..10).map(|_| Number(0))).collect();
+        world.spawn_batch((0..10).map(|_| Number(1)));
+        let mut state = SystemState::<Index<EntitySetNumber>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup_entityset(&0).len(), 10);
+        assert!(idx.lookup_entityset(&0).iter().all(|e| even.contains(e)));
+        assert_eq!(idx.lookup(&0), even);
+        assert!(idx.lookup_entityset(&2).is_empty());
+    }
+
+    struct SeededNumber;
+
+    impl IndexInfo for SeededNumber {
+        type Component = Number;
+        type Value = usize;
+        type Storage = SeededStorage<Self, 42>;
+        type ValueParam = ();
+
+        fn value(c: &Number, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    #[test]
+    fn test_lookup_seeded() {
+        let order = || {
+            let mut world = World::new();
+            world.spawn_batch((0..100).map(|i| Number(i % 2)));
+            let mut state = SystemState::<Index<SeededNumber>>::new(&mut world);
+            let mut idx = state.get_mut(&mut world);
+            assert_eq!(idx.lookup_seeded(&0).len(), 50);
+            idx.lookup_seeded(&0).iter().copied().collect::<Vec<_>>()
+        };
+        assert_eq!(order(), order());
+    }
+
+    #[derive(Component, Reflect, Default, Clone, PartialEq, Eq, Hash, Debug)]
+    #[reflect(Component)]
+    struct Saved(usize);
+
+    impl IndexInfo for Saved {
+        type Component = Self;
+        type Value = Self;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+
+        fn value(c: &Self, _: &()) -> Self {
+            c.clone()
+        }
+    }
+
+    #[test]
+    fn test_scene_round_trip() {
+        let registry = AppTypeRegistry::default();
+        registry.write().register::<Saved>();
+
+        let mut world = World::new();
+        world.insert_resource(registry.clone());
+        world.spawn_batch([Saved(1), Saved(1), Saved(2)]);
+        let mut state = SystemState::<Index<Saved>>::new(&mut world);
+        assert_eq!(state.get_mut(&mut world).lookup(&Saved(1)).len(), 2);
+        let scene = DynamicScene::from_world(&world);
+
+        // Give the loaded world an extra entity so that the scene's entities are remapped.
+        let mut loaded = World::new();
+        loaded.insert_resource(registry);
+        let existing = loaded.spawn(Saved(1)).id();
+        let mut state = SystemState::<Index<Saved>>::new(&mut loaded);
+        assert_eq!(
+            state.get_mut(&mut loaded).lookup(&Saved(1)),
+            [existing].into()
+        );
+
+        let mut entity_map = HashMap::default();
+        scene.write_to_world(&mut loaded, &mut entity_map).unwrap();
+        let mut idx = state.get_mut(&mut loaded);
+        assert_eq!(idx.lookup(&Saved(1)).len(), 3);
+        assert_eq!(idx.lookup(&Saved(2)).len(), 1);
+        for &entity in entity_map.values() {
+            assert!(idx.value_of(entity).is_some());
+        }
     }
 
     fn remover(n: usize) -> impl Fn(Index<Number>, Commands) {
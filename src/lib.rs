@@ -13,10 +13,52 @@ pub mod index;
 
 /// Various types of storage for maintaining indexes.
 pub mod storage;
-mod unique_multimap;
+
+/// Helper types for use as index values.
+pub mod values;
+
+/// Plugin for refreshing indexes on a schedule.
+pub mod plugin;
+
+/// Frozen copies of indexes for systems that only read them.
+pub mod snapshot;
+
+/// Diagnostics for monitoring indexes, enabled by the `diagnostics` feature.
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+
+/// Exporting indexes for debugging, enabled by the `export` feature.
+#[cfg(feature = "export")]
+pub mod export;
+
+/// Built-in indexes over a component's own value, or a trait method on it.
+pub mod component;
+
+/// A built-in index over bevy's [`Name`][bevy::core::Name] component.
+pub mod name;
+
+/// A built-in index over when components were added.
+pub mod added;
+
+/// Type-erased lookups for editors and inspectors.
+pub mod reflect;
+
+/// The map that [`HashmapStorage`][crate::storage::HashmapStorage] keeps its data in.
+pub mod unique_multimap;
 
 /// Commonly used types.
 pub mod prelude {
-    pub use crate::index::{Index, IndexInfo};
-    pub use crate::storage::{HashmapStorage, IndexStorage, NoStorage};
+    pub use crate::added::AddedAtIndex;
+    pub use crate::component::{AggregateIndex, ComponentIndex, ShardIndex};
+    pub use crate::index::{Index, IndexInfo, ReadIndex, RefreshPolicy, UnionIndex};
+    pub use crate::name::NameIndex;
+    pub use crate::plugin::{IndexPlugin, IndexPluginGroup};
+    pub use crate::snapshot::IndexSnapshotRes;
+    pub use crate::storage::{
+        AggregateStorage, CachedStorage, DependentStorage, EntitySetStorage, FilteredStorage,
+        GroupMaxStorage, HashmapStorage, IndexStorage, InsertionOrderStorage, InternedStorage,
+        LruStorage, Measure, NoStorage, OrderedStorage, SeededStorage, SharedStorage,
+        SkipDisabledStorage, SparseStorage,
+    };
+    pub use crate::values::{Bounded, CaseInsensitive, KeyWrapper, TotalF32, TuplePrefix};
 }
@@ -0,0 +1,94 @@
+use crate::index::{Index, IndexInfo};
+use crate::storage::HashmapStorage;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+/// [`IndexInfo`] for looking up entities by their [`Name`].
+///
+/// Values are stored as owned [`String`]s, so each changed [`Name`] costs a string
+/// allocation when the index refreshes, and each distinct name is kept in memory by
+/// the index until no entity has it anymore. Renamed and despawned entities are
+/// handled like any other changed or removed component.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// fn find_player(mut idx: Index<NameIndex>) {
+///     let players = idx.lookup(&"Player".to_string());
+///     let enemies = idx.lookup_prefix("Enemy");
+/// }
+/// ```
+pub struct NameIndex;
+
+impl IndexInfo for NameIndex {
+    type Component = Name;
+    type Value = String;
+    type Storage = HashmapStorage<Self>;
+    type ValueParam = ();
+
+    fn value(c: &Name, _: &()) -> String {
+        c.as_str().to_string()
+    }
+}
+
+impl<'w, 's> Index<'w, 's, NameIndex> {
+    /// Get all of the entities whose [`Name`] starts with `prefix`.
+    ///
+    /// This checks every distinct name in the index, so it is slower than [`lookup`][Index::lookup].
+    pub fn lookup_prefix(&mut self, prefix: &str) -> HashSet<Entity> {
+        self.lookup_where(|name| name.starts_with(prefix))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use bevy::ecs::system::SystemState;
+    use bevy::prelude::*;
+
+    fn add_some_names(mut commands: Commands) {
+        commands.spawn(Name::new("Player"));
+        commands.spawn(Name::new("Enemy 1"));
+        commands.spawn(Name::new("Enemy 2"));
+    }
+
+    fn check(name: &'static str, exact: usize, prefix: usize) -> impl Fn(Index<NameIndex>) {
+        move |mut idx: Index<NameIndex>| {
+            assert_eq!(idx.lookup(&name.to_string()).len(), exact);
+            assert_eq!(idx.lookup_prefix(name).len(), prefix);
+        }
+    }
+
+    fn rename_and_despawn(mut commands: Commands, mut names: Query<(Entity, &mut Name)>) {
+        for (entity, mut name) in &mut names {
+            match name.as_str() {
+                "Player" => name.set("Enemy 3"),
+                "Enemy 1" => commands.entity(entity).despawn(),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_name_index() {
+        App::new()
+            .add_systems(Startup, add_some_names)
+            .add_systems(PreUpdate, check("Player", 1, 1))
+            .add_systems(PreUpdate, check("Enemy", 0, 2))
+            .add_systems(Update, rename_and_despawn)
+            .add_systems(PostUpdate, check("Player", 0, 0))
+            .add_systems(PostUpdate, check("Enemy", 0, 2))
+            .add_systems(PostUpdate, check("Enemy 3", 1, 1))
+            .run();
+    }
+
+    #[test]
+    fn test_lookup_owned() {
+        let mut world = World::new();
+        let enemy = world.spawn(Name::new("Enemy 1")).id();
+        let mut state = SystemState::<Index<NameIndex>>::new(&mut world);
+        let mut idx = state.get_mut(&mut world);
+        assert_eq!(idx.lookup_owned(format!("Enemy {}", 1)), [enemy].into());
+        assert!(idx.lookup_owned(format!("Enemy {}", 2)).is_empty());
+    }
+}
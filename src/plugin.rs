@@ -0,0 +1,512 @@
+use crate::index::IndexInfo;
+use crate::reflect::ReflectLookups;
+use crate::snapshot::{snapshot_index_system, IndexSnapshotRes};
+use crate::storage::{Bucket, HashmapStorage, IndexStorage};
+use bevy::app::PluginGroupBuilder;
+use bevy::core::FrameCount;
+use bevy::ecs::schedule::{InternedScheduleLabel, InternedSystemSet, ScheduleLabel, SystemConfigs};
+use bevy::ecs::system::StaticSystemParam;
+use bevy::prelude::*;
+use bevy::reflect::FromReflect;
+use std::marker::PhantomData;
+
+/// [`Plugin`] that refreshes an index once every time a particular schedule runs.
+///
+/// Indexes are always refreshed when they are used, so this is not required, but it lets
+/// you control exactly when the refresh cost is paid. By default, the refresh runs in
+/// [`First`], but any schedule can be used.
+///
+/// For deterministic simulations, refreshing in [`FixedUpdate`] means that every fixed
+/// step sees all changes made since the previous step, even when several fixed steps run
+/// within a single frame:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// # #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// # struct Cell(i32);
+/// # struct CellIndex;
+/// # impl IndexInfo for CellIndex {
+/// #     type Component = Cell;
+/// #     type Value = Cell;
+/// #     type Storage = HashmapStorage<Self>;
+/// #     type ValueParam = ();
+/// #     fn value(c: &Cell, _: &()) -> Cell { c.clone() }
+/// # }
+/// App::new().add_plugins(IndexPlugin::<CellIndex>::new().in_schedule(FixedUpdate));
+/// ```
+///
+/// Components inserted with [`Commands`] only exist once the commands are applied, so a
+/// refresh only sees them if it runs after the [`apply_deferred`] that follows the system
+/// that queued them. With the default [`First`] schedule, entities spawned by commands
+/// during a frame are seen by the next frame's refresh. To see them in the same frame, add
+/// the plugin to the schedule that spawns them and use
+/// [`refresh_after`][Self::refresh_after], which applies pending commands right before
+/// refreshing:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// # #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// # struct Cell(i32);
+/// # struct CellIndex;
+/// # impl IndexInfo for CellIndex {
+/// #     type Component = Cell;
+/// #     type Value = Cell;
+/// #     type Storage = HashmapStorage<Self>;
+/// #     type ValueParam = ();
+/// #     fn value(c: &Cell, _: &()) -> Cell { c.clone() }
+/// # }
+/// fn spawn_cells(mut commands: Commands) {
+///     commands.spawn(Cell(0));
+/// }
+///
+/// App::new()
+///     .add_plugins(
+///         IndexPlugin::<CellIndex>::new()
+///             .in_schedule(Update)
+///             .refresh_after(spawn_cells),
+///     )
+///     .add_systems(Update, spawn_cells);
+/// ```
+pub struct IndexPlugin<I: IndexInfo> {
+    schedule: InternedScheduleLabel,
+    after: Vec<InternedSystemSet>,
+    snapshot: Option<fn(&mut App) -> SystemConfigs>,
+    #[allow(clippy::type_complexity)]
+    rebuild_threshold: Option<(Option<f32>, fn(&mut App, Option<f32>))>,
+    reflect_lookup: Option<fn(&mut ReflectLookups)>,
+    phantom: PhantomData<fn() -> I>,
+}
+
+impl<I: IndexInfo> IndexPlugin<I> {
+    /// Create a plugin that refreshes the index in [`First`].
+    pub fn new() -> Self {
+        Self {
+            schedule: First.intern(),
+            after: Vec::new(),
+            snapshot: None,
+            rebuild_threshold: None,
+            reflect_lookup: None,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Refresh the index in `schedule` instead.
+    pub fn in_schedule(mut self, schedule: impl ScheduleLabel) -> Self {
+        self.schedule = schedule.intern();
+        self
+    }
+
+    /// Refresh the index after `systems` in the plugin's schedule, including the commands
+    /// they queue.
+    ///
+    /// This adds an [`apply_deferred`] right before the refresh, which applies the commands
+    /// of every system that has finished by then, not just `systems`. Can be called several
+    /// times to refresh after each of the given systems.
+    pub fn refresh_after<M>(mut self, systems: impl IntoSystemSet<M>) -> Self {
+        self.after.push(systems.into_system_set().intern());
+        self
+    }
+}
+
+impl<I, B> IndexPlugin<I>
+where
+    I: IndexInfo<Storage = HashmapStorage<I, B>>,
+    B: Bucket<Entity>,
+{
+    /// Also rebuild an [`IndexSnapshotRes`] every time the index is refreshed, so that
+    /// systems can read the index in parallel.
+    pub fn with_snapshot(mut self) -> Self {
+        self.snapshot = Some(|app| {
+            app.init_resource::<IndexSnapshotRes<I>>();
+            snapshot_index_system::<I, B>.into_configs()
+        });
+        self
+    }
+
+    /// Rebuild the index from scratch instead of updating it entity by entity when more
+    /// than `fraction` of its components changed since the last refresh, or never with
+    /// `None`. Defaults to [`DEFAULT_REBUILD_THRESHOLD`][crate::storage::DEFAULT_REBUILD_THRESHOLD].
+    ///
+    /// A rebuild only re-computes the values of changed components, but copies every other
+    /// entity into the new map, so lower this when most entities tend to change at once,
+    /// e.g. an index over positions, and use `None` to always update entity by entity.
+    /// Either way, lookups return the same entities afterwards. Indexes with
+    /// [`on_change`][crate::index::Index::on_change] callbacks or a change history never
+    /// rebuild.
+    pub fn rebuild_threshold(mut self, fraction: Option<f32>) -> Self {
+        self.rebuild_threshold = Some((fraction, |app, fraction| {
+            app.world
+                .resource_mut::<HashmapStorage<I, B>>()
+                .set_rebuild_threshold(fraction)
+        }));
+        self
+    }
+
+    /// Register a type-erased lookup for the index in [`ReflectLookups`], so that editors
+    /// and inspectors can look up reflected values in it.
+    pub fn with_reflect_lookup(mut self) -> Self
+    where
+        I::Value: FromReflect,
+    {
+        self.reflect_lookup = Some(ReflectLookups::register::<I, B>);
+        self
+    }
+}
+
+impl<I: IndexInfo> Default for IndexPlugin<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// [`PluginGroup`] of [`IndexPlugin`]s, for registering many indexes at once.
+///
+/// Each index still gets its own storage resource and refresh system, exactly as if its
+/// plugin were added on its own.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// # #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// # struct Team(u32);
+/// # #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// # struct Level(u32);
+/// let mut app = App::new();
+/// app.add_plugins(
+///     IndexPluginGroup::new()
+///         .add::<ComponentIndex<Team>>()
+///         // Plugins with their own settings can be added too.
+///         .add_plugin(IndexPlugin::<ComponentIndex<Level>>::new().in_schedule(FixedUpdate)),
+/// );
+/// # assert!(app.world.contains_resource::<HashmapStorage<ComponentIndex<Team>>>());
+/// # assert!(app.world.contains_resource::<HashmapStorage<ComponentIndex<Level>>>());
+/// ```
+pub struct IndexPluginGroup {
+    builder: PluginGroupBuilder,
+}
+
+impl IndexPluginGroup {
+    /// Create an empty group.
+    pub fn new() -> Self {
+        Self {
+            builder: PluginGroupBuilder::start::<Self>(),
+        }
+    }
+
+    /// Add an [`IndexPlugin`] with the default settings for the index `I`.
+    pub fn add<I: IndexInfo + 'static>(self) -> Self {
+        self.add_plugin(IndexPlugin::<I>::new())
+    }
+
+    /// Add an existing [`IndexPlugin`], e.g. one refreshing in a different schedule.
+    ///
+    /// Adding a plugin for an index that is already in the group replaces it.
+    pub fn add_plugin<I: IndexInfo + 'static>(self, plugin: IndexPlugin<I>) -> Self {
+        Self {
+            builder: self.builder.add(plugin),
+        }
+    }
+}
+
+impl Default for IndexPluginGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PluginGroup for IndexPluginGroup {
+    fn build(self) -> PluginGroupBuilder {
+        self.builder
+    }
+}
+
+/// Marks that an [`IndexPlugin`] was added for an index, and records the last frame that its
+/// system refreshed the index in, so that debug builds can warn if that system never runs.
+#[doc(hidden)]
+#[derive(Resource)]
+pub struct PluginAdded<I: IndexInfo> {
+    pub(crate) last_refresh_frame: Option<u32>,
+    /// Whether the plugin refreshes in [`First`], before any other system of the frame can
+    /// look the index up.
+    pub(crate) refreshes_first: bool,
+    phantom: PhantomData<fn() -> I>,
+}
+
+impl<I: IndexInfo> PluginAdded<I> {
+    pub(crate) fn new() -> Self {
+        Self {
+            last_refresh_frame: None,
+            refreshes_first: false,
+            phantom: PhantomData,
+        }
+    }
+
+    pub(crate) fn mark_refreshed(&mut self, frame: Option<Res<FrameCount>>) {
+        self.last_refresh_frame = Some(frame.map_or(0, |frame| frame.0));
+    }
+}
+
+/// Whether an eager index that was first used in `first_used_frame` has gone a full frame
+/// without the plugin's system refreshing it.
+#[cfg(debug_assertions)]
+pub(crate) fn refresh_missed(
+    last_refresh_frame: Option<u32>,
+    first_used_frame: u32,
+    frame: u32,
+) -> bool {
+    last_refresh_frame.is_none() && frame.wrapping_sub(first_used_frame) > 1
+}
+
+impl<I: IndexInfo> Plugin for IndexPlugin<I> {
+    fn build(&self, app: &mut App) {
+        let mut added = PluginAdded::<I>::new();
+        added.refreshes_first = self.schedule == First.intern();
+        app.init_resource::<I::Storage>().insert_resource(added);
+        if let Some((fraction, set_rebuild_threshold)) = self.rebuild_threshold {
+            set_rebuild_threshold(app, fraction);
+        }
+        if let Some(register) = self.reflect_lookup {
+            register(
+                &mut app
+                    .world
+                    .get_resource_or_insert_with(ReflectLookups::default),
+            );
+        }
+
+        #[cfg(not(feature = "diagnostics"))]
+        let refresh = refresh_index_system::<I>.into_configs();
+
+        #[cfg(feature = "diagnostics")]
+        let refresh = {
+            crate::diagnostics::register_diagnostics::<I>(app);
+            crate::diagnostics::refresh_index_diagnostics_system::<I>.into_configs()
+        };
+
+        // Apply the commands queued by the systems the refresh runs after, so that it sees
+        // the components they insert.
+        let refresh = if self.after.is_empty() {
+            refresh
+        } else {
+            let refresh = (apply_deferred, refresh).chain();
+            self.after
+                .iter()
+                .fold(refresh, |refresh, &set| refresh.after(set))
+        };
+
+        match self.snapshot {
+            Some(snapshot) => {
+                let snapshot = snapshot(app);
+                app.add_systems(self.schedule, (refresh, snapshot).chain())
+            }
+            None => app.add_systems(self.schedule, refresh),
+        };
+    }
+}
+
+/// System that refreshes an index. Added by [`IndexPlugin`], unless the `diagnostics`
+/// feature is enabled, in which case a system that also records measurements is used instead.
+pub fn refresh_index_system<I: IndexInfo>(
+    mut storage: ResMut<I::Storage>,
+    mut data: StaticSystemParam<<I::Storage as IndexStorage<I>>::RefreshData<'_, '_>>,
+    plugin: Option<ResMut<PluginAdded<I>>>,
+    frame: Option<Res<FrameCount>>,
+) {
+    storage.refresh(&mut data);
+    if let Some(mut plugin) = plugin {
+        plugin.mark_refreshed(frame);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PluginAdded;
+    use crate::prelude::*;
+    use bevy::core::{FrameCount, FrameCountPlugin};
+    use bevy::ecs::schedule::ScheduleLabel;
+    use bevy::ecs::system::SystemState;
+    use bevy::prelude::*;
+    use bevy::time::{TimePlugin, TimeUpdateStrategy};
+    use std::time::Duration;
+
+    #[derive(Component, Clone, Eq, Hash, PartialEq, Debug)]
+    struct Number(usize);
+
+    struct EagerNumber;
+
+    impl IndexInfo for EagerNumber {
+        type Component = Number;
+        type Value = usize;
+        type Storage = HashmapStorage<Self>;
+        type ValueParam = ();
+        const REFRESH: RefreshPolicy = RefreshPolicy::Eager;
+
+        fn value(c: &Number, _: &()) -> usize {
+            c.0
+        }
+    }
+
+    #[derive(Resource, Default)]
+    struct Steps(usize);
+
+    // Each fixed step increments every number, after checking that the previous step's
+    // change was picked up by the plugin's refresh at the end of that step.
+    fn step(mut steps: ResMut<Steps>, mut nums: Query<&mut Number>) {
+        steps.0 += 1;
+        for mut num in &mut nums {
+            num.0 += 1;
+        }
+    }
+
+    // `ReadIndex` never refreshes, so only the plugin's system can update what this sees.
+    fn check(steps: Res<Steps>, idx: ReadIndex<EagerNumber>) {
+        if steps.0 > 0 {
+            assert_eq!(idx.get(&steps.0).len(), 2);
+            assert_eq!(idx.get(&(steps.0 - 1)).len(), 0);
+        }
+    }
+
+    const STEP: Duration = Duration::from_micros(15_625);
+
+    #[test]
+    fn test_fixed_update_refresh() {
+        let mut app = App::new();
+        app.add_plugins((
+            TimePlugin,
+            IndexPlugin::<EagerNumber>::new()
+                .in_schedule(FixedUpdate)
+                .refresh_after(step),
+        ))
+        // Every frame is long enough for exactly 3 fixed steps. A 1/64s step is exact in
+        // floating point, so no time is lost to rounding between frames.
+        .insert_resource(Time::<Fixed>::from_duration(STEP))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(STEP * 3))
+        .init_resource::<Steps>()
+        .add_systems(FixedUpdate, (check, step).chain());
+        app.world.spawn(Number(0));
+        app.world.spawn(Number(0));
+
+        // The first frame has no elapsed time, so it runs no fixed steps.
+        for _ in 0..3 {
+            app.update();
+        }
+        assert_eq!(app.world.resource::<Steps>().0, 6);
+        let mut state = SystemState::<ReadIndex<EagerNumber>>::new(&mut app.world);
+        assert_eq!(state.get(&app.world).get(&6).len(), 2);
+    }
+
+    fn increment(mut nums: Query<&mut Number>) {
+        for mut num in &mut nums {
+            num.0 += 1;
+        }
+    }
+
+    #[test]
+    fn test_eager_refresh() {
+        let mut app = App::new();
+        app.add_plugins(IndexPlugin::<EagerNumber>::new())
+            .init_resource::<Steps>()
+            .add_systems(
+                Update,
+                (
+                    increment,
+                    // Changes from earlier in the frame are not seen until the next frame.
+                    |steps: Res<Steps>, mut idx: Index<EagerNumber>| {
+                        assert_eq!(idx.lookup(&steps.0).len(), 1);
+                        assert_eq!(idx.lookup(&(steps.0 + 1)).len(), 0);
+                    },
+                    |mut steps: ResMut<Steps>| steps.0 += 1,
+                )
+                    .chain(),
+            );
+        app.world.spawn(Number(0));
+        for _ in 0..3 {
+            app.update();
+        }
+    }
+
+    #[test]
+    fn test_refresh_after_commands() {
+        let mut app = App::new();
+        let spawn = |mut commands: Commands| {
+            commands.spawn(Number(0));
+        };
+        app.add_plugins(
+            IndexPlugin::<EagerNumber>::new()
+                .in_schedule(Update)
+                .refresh_after(spawn),
+        )
+        .add_systems(Update, spawn);
+        let mut state = SystemState::<Index<EagerNumber>>::new(&mut app.world);
+        for frame in 1..=3 {
+            app.update();
+            // Eager indexes aren't refreshed here, so this is what the plugin's refresh saw:
+            // each frame's spawn is already visible in the same frame.
+            assert_eq!(state.get_mut(&mut app.world).lookup(&0).len(), frame);
+        }
+    }
+
+    #[derive(ScheduleLabel, Clone, Debug, PartialEq, Eq, Hash)]
+    struct NeverRuns;
+
+    #[test]
+    fn test_refresh_sentinel() {
+        let mut app = App::new();
+        app.add_plugins((
+            FrameCountPlugin,
+            IndexPlugin::<EagerNumber>::new(),
+            IndexPlugin::<ComponentIndex<Number>>::new().in_schedule(NeverRuns),
+        ));
+        app.add_systems(
+            Update,
+            |_a: Index<EagerNumber>, _b: Index<ComponentIndex<Number>>| {},
+        );
+        for _ in 0..3 {
+            app.update();
+        }
+        let frame = |app: &App| app.world.resource::<FrameCount>().0;
+        assert_eq!(
+            app.world
+                .resource::<PluginAdded<EagerNumber>>()
+                .last_refresh_frame,
+            Some(frame(&app) - 1)
+        );
+        assert_eq!(
+            app.world
+                .resource::<PluginAdded<ComponentIndex<Number>>>()
+                .last_refresh_frame,
+            None
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_refresh_missed() {
+        use super::refresh_missed;
+
+        assert!(!refresh_missed(None, 5, 6));
+        assert!(refresh_missed(None, 5, 7));
+        assert!(!refresh_missed(Some(5), 5, 7));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "refreshed it this frame")]
+    fn test_eager_lookup_before_refresh() {
+        let mut app = App::new();
+        app.add_plugins((FrameCountPlugin, IndexPlugin::<EagerNumber>::new()))
+            // Startup runs before the plugin's refresh in `First`.
+            .add_systems(Startup, |mut idx: Index<EagerNumber>| {
+                idx.lookup(&0);
+            });
+        app.world.spawn(Number(0));
+        app.update();
+    }
+
+    #[test]
+    #[should_panic(expected = "IndexPlugin")]
+    fn test_eager_without_plugin() {
+        let mut app = App::new();
+        app.add_systems(Update, |_idx: Index<EagerNumber>| {});
+        app.update();
+    }
+}
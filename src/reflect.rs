@@ -0,0 +1,132 @@
+use crate::index::IndexInfo;
+use crate::storage::{Bucket, HashmapStorage};
+use bevy::prelude::*;
+use bevy::reflect::FromReflect;
+use bevy::utils::HashMap;
+use std::any::{type_name, TypeId};
+
+/// A type-erased lookup in an index, which converts a reflected value to the index's value
+/// type and returns the entities that have it. Returns no entities if the value can't be
+/// converted.
+///
+/// Lookups only read the index's storage, so they see the index as of its last refresh.
+pub type ReflectLookupFn = fn(&World, &dyn Reflect) -> Vec<Entity>;
+
+/// A lookup registered in [`ReflectLookups`] for one index.
+#[derive(Clone, Copy)]
+pub struct ReflectLookup {
+    /// The type name of the index's [`IndexInfo`], to show to users.
+    pub index_name: &'static str,
+    /// The [`TypeId`] of the index's value type, for finding its registration in the
+    /// [`AppTypeRegistry`], e.g. to build an editor for it.
+    pub value_type: TypeId,
+    /// The lookup itself.
+    pub lookup: ReflectLookupFn,
+}
+
+/// [`Resource`] of type-erased lookups for indexes, so that editors and inspectors can let
+/// users type in a value and see the entities with it, without knowing the index types.
+///
+/// Lookups are registered with [`IndexPlugin::with_reflect_lookup`], or with
+/// [`register`][Self::register] for indexes without a plugin. Only indexes using
+/// [`HashmapStorage`] whose value types implement [`FromReflect`] can be registered.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// # use bevy_mod_index::reflect::ReflectLookups;
+/// fn inspect(world: &World) {
+///     for lookup in world.resource::<ReflectLookups>().iter() {
+///         // Build `value` from the user's input with the registration of `lookup.value_type`.
+///         # let value = 0;
+///         let entities = (lookup.lookup)(world, &value);
+///         println!("{}: {:?}", lookup.index_name, entities);
+///     }
+/// }
+/// ```
+///
+/// [`IndexPlugin::with_reflect_lookup`]: crate::plugin::IndexPlugin::with_reflect_lookup
+#[derive(Resource, Default)]
+pub struct ReflectLookups(HashMap<TypeId, ReflectLookup>);
+
+impl ReflectLookups {
+    /// Register the lookup for the index `I`, replacing any earlier registration.
+    pub fn register<I, B>(&mut self)
+    where
+        I: IndexInfo<Storage = HashmapStorage<I, B>>,
+        I::Value: FromReflect,
+        B: Bucket<Entity>,
+    {
+        self.0.insert(
+            TypeId::of::<I>(),
+            ReflectLookup {
+                index_name: type_name::<I>(),
+                value_type: TypeId::of::<I::Value>(),
+                lookup: reflect_lookup::<I, B>,
+            },
+        );
+    }
+
+    /// Get the lookup for the index `I`, if it was registered.
+    pub fn get<I: IndexInfo>(&self) -> Option<&ReflectLookup> {
+        self.0.get(&TypeId::of::<I>())
+    }
+
+    /// Iterate over the lookups of every registered index, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &ReflectLookup> {
+        self.0.values()
+    }
+}
+
+/// The [`ReflectLookupFn`] for the index `I`.
+pub fn reflect_lookup<I, B>(world: &World, value: &dyn Reflect) -> Vec<Entity>
+where
+    I: IndexInfo<Storage = HashmapStorage<I, B>>,
+    I::Value: FromReflect,
+    B: Bucket<Entity>,
+{
+    let (Some(value), Some(storage)) = (
+        I::Value::from_reflect(value),
+        world.get_resource::<HashmapStorage<I, B>>(),
+    ) else {
+        return Vec::new();
+    };
+    storage
+        .map_ref()
+        .get_ref(&value)
+        .map(|bucket| bucket.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::ReflectLookups;
+    use crate::prelude::*;
+    use bevy::prelude::*;
+    use bevy::reflect::DynamicTupleStruct;
+
+    #[derive(Component, Reflect, Clone, PartialEq, Eq, Hash, Debug)]
+    struct Number(usize);
+
+    #[test]
+    fn test_reflect_lookup() {
+        let mut app = App::new();
+        app.add_plugins(IndexPlugin::<ComponentIndex<Number>>::new().with_reflect_lookup());
+        let a = app.world.spawn(Number(1)).id();
+        app.world.spawn(Number(2));
+        app.update();
+
+        let lookups = app.world.resource::<ReflectLookups>();
+        let lookup = lookups.get::<ComponentIndex<Number>>().unwrap();
+        assert_eq!(lookups.iter().count(), 1);
+        assert_eq!(lookup.value_type, std::any::TypeId::of::<Number>());
+        assert_eq!((lookup.lookup)(&app.world, &Number(1)), vec![a]);
+
+        // Values built dynamically, like an inspector would, work too.
+        let mut value = DynamicTupleStruct::default();
+        value.insert(1usize);
+        assert_eq!((lookup.lookup)(&app.world, &value), vec![a]);
+        assert!((lookup.lookup)(&app.world, &Number(3)).is_empty());
+        assert!((lookup.lookup)(&app.world, &"1".to_string()).is_empty());
+    }
+}
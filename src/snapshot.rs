@@ -0,0 +1,129 @@
+use crate::index::IndexInfo;
+use crate::storage::{Bucket, HashmapStorage};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// [`Resource`] holding a frozen copy of an index, for systems that only read it.
+///
+/// Systems that use an [`Index`][crate::index::Index] need mutable access to its storage,
+/// so they can't run in parallel with each other. Systems that take
+/// `Res<IndexSnapshotRes<I>>` only need shared access, so any number of them can run at
+/// once.
+///
+/// The snapshot is rebuilt by [`IndexPlugin`][crate::plugin::IndexPlugin] right after it
+/// refreshes the index, when enabled with
+/// [`with_snapshot`][crate::plugin::IndexPlugin::with_snapshot]. Rebuilding copies every
+/// bucket, so it costs O(n) in the number of indexed entities each time the plugin's
+/// schedule runs. Readers see the index as of the last rebuild: with the default [`First`]
+/// schedule that is the start of the current frame, but readers that run before the
+/// plugin's schedule in a frame see the previous frame's snapshot instead.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// # #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// # struct Cell(i32);
+/// # struct CellIndex;
+/// # impl IndexInfo for CellIndex {
+/// #     type Component = Cell;
+/// #     type Value = Cell;
+/// #     type Storage = HashmapStorage<Self>;
+/// #     type ValueParam = ();
+/// #     fn value(c: &Cell, _: &()) -> Cell { c.clone() }
+/// # }
+/// fn count_origin(snapshot: Res<IndexSnapshotRes<CellIndex>>) {
+///     let count = snapshot.lookup(&Cell(0)).len();
+/// }
+///
+/// App::new()
+///     .add_plugins(IndexPlugin::<CellIndex>::new().with_snapshot())
+///     .add_systems(Update, count_origin);
+/// ```
+#[derive(Resource)]
+pub struct IndexSnapshotRes<I: IndexInfo> {
+    map: Arc<HashMap<I::Value, HashSet<Entity>>>,
+    empty: HashSet<Entity>,
+}
+
+impl<I: IndexInfo> Default for IndexSnapshotRes<I> {
+    fn default() -> Self {
+        Self {
+            map: Default::default(),
+            empty: HashSet::new(),
+        }
+    }
+}
+
+impl<I: IndexInfo> IndexSnapshotRes<I> {
+    /// Get all of the entities that had the given value when the snapshot was taken.
+    pub fn lookup(&self, val: &I::Value) -> &HashSet<Entity> {
+        self.map.get(val).unwrap_or(&self.empty)
+    }
+
+    /// Get the whole snapshot, which can be kept after the resource is rebuilt.
+    pub fn snapshot(&self) -> Arc<HashMap<I::Value, HashSet<Entity>>> {
+        self.map.clone()
+    }
+}
+
+/// System that rebuilds the [`IndexSnapshotRes`] of an index from its storage. Added by
+/// [`IndexPlugin::with_snapshot`][crate::plugin::IndexPlugin::with_snapshot].
+pub fn snapshot_index_system<I, B>(
+    storage: Res<HashmapStorage<I, B>>,
+    mut snapshot: ResMut<IndexSnapshotRes<I>>,
+) where
+    I: IndexInfo<Storage = HashmapStorage<I, B>>,
+    B: Bucket<Entity>,
+{
+    snapshot.map = Arc::new(
+        storage
+            .map_ref()
+            .iter()
+            .map(|(val, bucket)| (val.clone(), bucket.to_hash_set()))
+            .collect(),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use bevy::prelude::*;
+
+    #[derive(Component, Clone, Eq, Hash, PartialEq, Debug)]
+    struct Number(usize);
+
+    fn check(n: usize, count: usize) -> impl Fn(Res<IndexSnapshotRes<ComponentIndex<Number>>>) {
+        move |snapshot: Res<IndexSnapshotRes<ComponentIndex<Number>>>| {
+            assert_eq!(snapshot.lookup(&Number(n)).len(), count);
+        }
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut app = App::new();
+        app.add_plugins(IndexPlugin::<ComponentIndex<Number>>::new().with_snapshot());
+        app.world.spawn(Number(1));
+        app.world.spawn(Number(1));
+        app.world.spawn(Number(2));
+
+        app.add_systems(Update, (check(1, 2), check(2, 1)));
+        app.update();
+
+        let old = app
+            .world
+            .resource::<IndexSnapshotRes<ComponentIndex<Number>>>()
+            .snapshot();
+        app.world.spawn(Number(3));
+        app.update();
+        assert_eq!(
+            app.world
+                .resource::<IndexSnapshotRes<ComponentIndex<Number>>>()
+                .lookup(&Number(3))
+                .len(),
+            1
+        );
+        // Snapshots that were taken out are not changed by later rebuilds.
+        assert!(!old.contains_key(&Number(3)));
+    }
+}
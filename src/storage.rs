@@ -1,10 +1,28 @@
-use crate::index::IndexInfo;
+use crate::index::{indexed_value, Discrepancy, IndexChange, IndexInfo};
 use crate::unique_multimap::UniqueMultiMap;
+use bevy::core::FrameCount;
+use bevy::ecs::change_detection::CHECK_TICK_THRESHOLD;
 use bevy::ecs::component::Tick;
-use bevy::ecs::system::{StaticSystemParam, SystemChangeTick, SystemParam};
+use bevy::ecs::event::ManualEventReader;
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::ecs::removal_detection::RemovedComponentEntity;
+use bevy::ecs::system::{
+    ReadOnlySystemParam, StaticSystemParam, SystemChangeTick, SystemMeta, SystemParam,
+};
+use bevy::ecs::world::unsafe_world_cell::UnsafeWorldCell;
 use bevy::prelude::*;
-use bevy::utils::HashSet;
+use bevy::utils::{hashbrown, AHasher, EntityHashSet, HashMap, HashSet, RandomState};
+use indexmap::IndexSet;
+#[cfg(feature = "history")]
+use std::collections::VecDeque;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
 use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+#[cfg(feature = "channel")]
+use std::sync::{mpsc::Receiver, Mutex};
+use std::sync::{Arc, OnceLock};
 
 /// Defines the internal storage for an index, which is stored as a [`Resource`].
 ///
@@ -12,13 +30,17 @@ use std::marker::PhantomData;
 /// in your [`IndexInfo`] implementations, but you can use this to customize
 /// the storage of your index's data if necessary
 ///
+/// Storage is derived entirely from the indexed components, so it is not reflected and is
+/// not saved in scenes. Components spawned from a scene are indexed like any other new
+/// components by the next refresh, under the entities that the scene loader maps them to.
+///
 /// This crate provides the following storage implementations:
 ///
-/// | Feature | [`HashmapStorage`] | [`NoStorage`] |
-/// |---|---|---|
-/// | Automatic refresh timing | When used; once per-system run | N/A - it always reads the world data directly |
-/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] | Yes |
-/// | Sees updates from earlier in the frame? | Yes | Yes |
+/// | Feature | [`HashmapStorage`] | [`OrderedStorage`] | [`SparseStorage`] | [`NoStorage`] |
+/// |---|---|---|---|---|
+/// | Automatic refresh timing | When used; once per-system run | When used; once per-system run | When used; once per-system run | N/A - it always reads the world data directly |
+/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] | After manually calling [`refresh`][Self::refresh] | After manually calling [`refresh`][Self::refresh] | Yes |
+/// | Sees updates from earlier in the frame? | Yes | Yes | Yes | Yes |
 pub trait IndexStorage<I: IndexInfo>: Resource + Default {
     /// [`SystemParam`] that is fetched alongside this storage [`Resource`] when
     /// an [`Index`][crate::index::Index] is included in a system.
@@ -36,10 +58,298 @@ pub trait IndexStorage<I: IndexInfo>: Resource + Default {
 
     /// Refresh this storage with the latest state from the world.
     fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>);
+
+    /// Called when the deferred changes from a system that uses this index are applied,
+    /// e.g. by [`apply_deferred`], so that the storage can react to component removals and
+    /// despawns from [`Commands`] before the next refresh.
+    ///
+    /// Parameters are applied in order, so this sees the commands of other systems that
+    /// were applied first, and of the same system if its [`Commands`] come before the
+    /// [`Index`][crate::index::Index] in its parameters.
+    ///
+    /// Does nothing by default.
+    fn apply(&mut self, _world: &World) {}
+
+    /// The number of entities currently stored in the index, if the storage keeps track
+    /// of them. Used for diagnostics.
+    ///
+    /// Returns `None` by default.
+    fn entity_count(&self) -> Option<usize> {
+        None
+    }
+
+    /// Make the next [`refresh`][Self::refresh] reconcile the whole storage with the world
+    /// instead of only checking what changed since the last refresh, e.g. after tooling
+    /// edited the storage directly.
+    ///
+    /// Does nothing by default, for storages that don't cache anything between refreshes.
+    /// Storages that do should override this.
+    fn invalidate(&mut self) {}
+}
+
+/// Reads removals of a component straight from the world, independently of the removal
+/// tracking done in a refresh.
+pub(crate) struct RemovalReader<C: Component> {
+    reader: ManualEventReader<RemovedComponentEntity>,
+    phantom: PhantomData<fn() -> C>,
+}
+
+impl<C: Component> Default for RemovalReader<C> {
+    fn default() -> Self {
+        Self {
+            reader: Default::default(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<C: Component> RemovalReader<C> {
+    pub(crate) fn read(&mut self, world: &World) -> Vec<Entity> {
+        let Some(events) = world
+            .component_id::<C>()
+            .and_then(|id| world.removed_components().get(id))
+        else {
+            return Vec::new();
+        };
+        self.reader
+            .read(events)
+            .cloned()
+            .map(Entity::from)
+            .collect()
+    }
 }
 
 // ==================================================================
 
+/// The set of entities sharing a single value in a [`HashmapStorage`].
+///
+/// This is implemented for [`HashSet`], which is the default, and [`IndexSet`], which
+/// remembers the order that entities were added to the bucket.
+pub trait Bucket<V: 'static>: Default + IntoIterator<Item = V> + Send + Sync + 'static {
+    /// Add a value to the bucket.
+    fn insert(&mut self, v: V);
+    /// Remove a value from the bucket.
+    fn remove(&mut self, v: &V);
+    /// The number of values in the bucket.
+    fn len(&self) -> usize;
+    /// Whether the bucket has no values.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Iterate over the values in the bucket.
+    fn iter(&self) -> impl Iterator<Item = &V>;
+    /// Copy the values in the bucket to a [`HashSet`].
+    fn to_hash_set(&self) -> HashSet<V>;
+}
+
+impl<V: Hash + Eq + Clone + Send + Sync + 'static> Bucket<V> for HashSet<V> {
+    fn insert(&mut self, v: V) {
+        HashSet::insert(self, v);
+    }
+    fn remove(&mut self, v: &V) {
+        HashSet::remove(self, v);
+    }
+    fn len(&self) -> usize {
+        HashSet::len(self)
+    }
+    fn iter(&self) -> impl Iterator<Item = &V> {
+        HashSet::iter(self)
+    }
+    fn to_hash_set(&self) -> HashSet<V> {
+        self.clone()
+    }
+}
+
+impl<V: Hash + Eq + Clone + Send + Sync + 'static> Bucket<V> for IndexSet<V> {
+    fn insert(&mut self, v: V) {
+        IndexSet::insert(self, v);
+    }
+    fn remove(&mut self, v: &V) {
+        // Preserve the order of the remaining values.
+        IndexSet::shift_remove(self, v);
+    }
+    fn len(&self) -> usize {
+        IndexSet::len(self)
+    }
+    fn iter(&self) -> impl Iterator<Item = &V> {
+        IndexSet::iter(self)
+    }
+    fn to_hash_set(&self) -> HashSet<V> {
+        self.iter().cloned().collect()
+    }
+}
+
+impl Bucket<Entity> for EntityHashSet<Entity> {
+    fn insert(&mut self, v: Entity) {
+        hashbrown::HashSet::insert(self, v);
+    }
+    fn remove(&mut self, v: &Entity) {
+        hashbrown::HashSet::remove(self, v);
+    }
+    fn len(&self) -> usize {
+        hashbrown::HashSet::len(self)
+    }
+    fn iter(&self) -> impl Iterator<Item = &Entity> {
+        hashbrown::HashSet::iter(self)
+    }
+    fn to_hash_set(&self) -> HashSet<Entity> {
+        self.iter().copied().collect()
+    }
+}
+
+/// [`BuildHasher`] with a fixed `SEED`, so that the iteration order of sets using it only
+/// depends on what was inserted. Used by [`SeededStorage`].
+///
+/// This is meant for reproducible debugging, not security: hashes are predictable, so
+/// they offer no protection against inputs crafted to collide. The order is the same from
+/// run to run of a program, but may differ between platforms.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SeededState<const SEED: u64>;
+
+impl<const SEED: u64> BuildHasher for SeededState<SEED> {
+    type Hasher = AHasher;
+
+    fn build_hasher(&self) -> AHasher {
+        RandomState::with_seeds(
+            SEED,
+            SEED ^ 0x243f_6a88_85a3_08d3,
+            SEED ^ 0x1319_8a2e_0370_7344,
+            SEED ^ 0xa409_3822_299f_31d0,
+        )
+        .build_hasher()
+    }
+}
+
+/// A [`HashSet`](hashbrown::HashSet) whose iteration order is reproducible. See
+/// [`SeededState`].
+pub type SeededHashSet<V, const SEED: u64> = hashbrown::HashSet<V, SeededState<SEED>>;
+
+impl<V: Hash + Eq + Clone + Send + Sync + 'static, const SEED: u64> Bucket<V>
+    for SeededHashSet<V, SEED>
+{
+    fn insert(&mut self, v: V) {
+        hashbrown::HashSet::insert(self, v);
+    }
+    fn remove(&mut self, v: &V) {
+        hashbrown::HashSet::remove(self, v);
+    }
+    fn len(&self) -> usize {
+        hashbrown::HashSet::len(self)
+    }
+    fn iter(&self) -> impl Iterator<Item = &V> {
+        hashbrown::HashSet::iter(self)
+    }
+    fn to_hash_set(&self) -> HashSet<V> {
+        self.iter().cloned().collect()
+    }
+}
+
+/// [`Bucket`] that shares its entities behind an [`Arc`], so that they can be read without
+/// copying them. Used by [`SharedStorage`].
+///
+/// Modifying the bucket while it is shared copies the entities first, so readers holding
+/// an older [`Arc`] keep seeing the entities from when they looked it up.
+pub struct SharedSet<V>(Arc<HashSet<V>>);
+
+impl<V> Default for SharedSet<V> {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+impl<V> SharedSet<V> {
+    /// Get another reference to the shared entities.
+    pub fn share(&self) -> Arc<HashSet<V>> {
+        self.0.clone()
+    }
+}
+
+impl<V: Clone> IntoIterator for SharedSet<V> {
+    type Item = V;
+    type IntoIter = bevy::utils::hashbrown::hash_set::IntoIter<V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Arc::unwrap_or_clone(self.0).into_iter()
+    }
+}
+
+impl<V: Hash + Eq + Clone + Send + Sync + 'static> Bucket<V> for SharedSet<V> {
+    fn insert(&mut self, v: V) {
+        Arc::make_mut(&mut self.0).insert(v);
+    }
+    fn remove(&mut self, v: &V) {
+        if self.0.contains(v) {
+            Arc::make_mut(&mut self.0).remove(v);
+        }
+    }
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+    fn iter(&self) -> impl Iterator<Item = &V> {
+        self.0.iter()
+    }
+    fn to_hash_set(&self) -> HashSet<V> {
+        (*self.0).clone()
+    }
+}
+
+/// [`Bucket`] that keeps a copy of its entities from the last time they were read with
+/// [`cached`][Self::cached], until the bucket next changes. Used by [`CachedStorage`].
+pub struct CachedSet<V> {
+    set: HashSet<V>,
+    cached: OnceLock<Arc<HashSet<V>>>,
+}
+
+impl<V> Default for CachedSet<V> {
+    fn default() -> Self {
+        Self {
+            set: Default::default(),
+            cached: OnceLock::new(),
+        }
+    }
+}
+
+impl<V: Clone> CachedSet<V> {
+    /// Get the entities in the bucket, copying them only if they changed since the last
+    /// call.
+    pub fn cached(&self) -> Arc<HashSet<V>> {
+        self.cached
+            .get_or_init(|| Arc::new(self.set.clone()))
+            .clone()
+    }
+}
+
+impl<V> IntoIterator for CachedSet<V> {
+    type Item = V;
+    type IntoIter = bevy::utils::hashbrown::hash_set::IntoIter<V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.set.into_iter()
+    }
+}
+
+impl<V: Hash + Eq + Clone + Send + Sync + 'static> Bucket<V> for CachedSet<V> {
+    fn insert(&mut self, v: V) {
+        if self.set.insert(v) {
+            self.cached.take();
+        }
+    }
+    fn remove(&mut self, v: &V) {
+        if self.set.remove(v) {
+            self.cached.take();
+        }
+    }
+    fn len(&self) -> usize {
+        self.set.len()
+    }
+    fn iter(&self) -> impl Iterator<Item = &V> {
+        self.set.iter()
+    }
+    fn to_hash_set(&self) -> HashSet<V> {
+        self.set.clone()
+    }
+}
+
 /// [`IndexStorage`] implementation that maintains a mapping from values to components
 /// which have that value.
 ///
@@ -48,60 +358,2191 @@ pub trait IndexStorage<I: IndexInfo>: Resource + Default {
 /// | Automatic refresh timing | When used; once per-system run |
 /// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] |
 /// | Sees updates from earlier in the frame? | Yes |
+///
+/// The entities for each value are kept in a [`Bucket`], which is a [`HashSet`] by default.
+/// See [`InsertionOrderStorage`] for a variant that remembers the order of each bucket.
 #[derive(Resource)]
-pub struct HashmapStorage<I: IndexInfo> {
-    map: UniqueMultiMap<I::Value, Entity>,
-    last_refresh_tick: Tick,
+pub struct HashmapStorage<I: IndexInfo, B: Bucket<Entity> = HashSet<Entity>> {
+    map: UniqueMultiMap<I::Value, Entity, B>,
+    last_refresh_tick: Option<Tick>,
+    empty: B,
+    removals: RemovalReader<I::Component>,
+    value_ids: ValueIds<I::Value>,
+    on_change: Vec<ChangeCallback<I::Value>>,
+    #[cfg(feature = "history")]
+    history: VecDeque<(Tick, IndexChange<I::Value>)>,
+    #[cfg(feature = "channel")]
+    refresh_channel: Option<Mutex<Receiver<Entity>>>,
+    #[cfg(feature = "test-util")]
+    frozen: bool,
+    dirty: bool,
+    rebuild_threshold: Option<f32>,
+    changed_at: HashMap<Entity, Tick>,
+    last_tick_check: Tick,
+    transitions: Transitions,
+    #[cfg(test)]
+    pub(crate) rebuilds: usize,
+}
+
+/// The entities that entered, exited or changed value in an index during a frame, if
+/// [`IndexInfo::TRACK_TRANSITIONS`] is set. Otherwise, every method does nothing.
+#[derive(Default)]
+struct Transitions {
+    enabled: bool,
+    frame: Option<u32>,
+    entered: HashSet<Entity>,
+    exited: HashSet<Entity>,
+    changed: HashSet<Entity>,
+}
+
+impl Transitions {
+    /// Forget the transitions of earlier frames, or of earlier refreshes if frames aren't
+    /// counted.
+    fn begin(&mut self, frame: Option<u32>) {
+        if self.enabled && (frame.is_none() || frame != self.frame) {
+            self.entered.clear();
+            self.exited.clear();
+            self.changed.clear();
+            self.frame = frame;
+        }
+    }
+
+    // Exiting and entering again within a frame cancel out, and vice versa.
+    fn enter(&mut self, entity: Entity) {
+        if self.enabled && !self.exited.remove(&entity) {
+            self.entered.insert(entity);
+        }
+    }
+
+    fn exit(&mut self, entity: Entity) {
+        if !self.enabled {
+            return;
+        }
+        self.changed.remove(&entity);
+        if !self.entered.remove(&entity) {
+            self.exited.insert(entity);
+        }
+    }
+
+    /// Record that `entity` was given a new value, including when it entered.
+    fn change(&mut self, entity: Entity) {
+        if self.enabled {
+            self.changed.insert(entity);
+        }
+    }
+}
+
+/// The default fraction of components that must change at once for a [`HashmapStorage`] to
+/// rebuild its map from scratch instead of updating it entity by entity. See
+/// [`IndexPlugin::rebuild_threshold`][crate::plugin::IndexPlugin::rebuild_threshold].
+///
+/// This is roughly where rebuilding became faster in the `refresh` benchmark, whose values
+/// are cheap to compute.
+pub const DEFAULT_REBUILD_THRESHOLD: f32 = 0.6;
+
+/// Prints the number of distinct values and entities and the tick of the last refresh, and
+/// with `{:#?}`, every value and its entities.
+impl<I: IndexInfo, B: Bucket<Entity>> fmt::Debug for HashmapStorage<I, B>
+where
+    I::Value: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verbose = f.alternate();
+        let mut s = f.debug_struct("HashmapStorage");
+        s.field("values", &self.map.key_count())
+            .field("entities", &self.map.len())
+            .field(
+                "last_refresh_tick",
+                &self.last_refresh_tick.map(|tick| tick.get()),
+            );
+        if verbose {
+            s.field("buckets", &self.map.debug_buckets());
+        }
+        s.finish()
+    }
+}
+
+/// A callback registered with [`Index::on_change`][crate::index::Index::on_change], called
+/// with an entity, its previous value (if it had one), and its new value.
+pub type ChangeCallback<V> = Box<dyn FnMut(Entity, Option<V>, V) + Send + Sync>;
+
+/// Stable ids for values, assigned in the order they are first requested.
+struct ValueIds<V> {
+    ids: HashMap<V, u64>,
+    values: HashMap<u64, V>,
+}
+
+impl<V> Default for ValueIds<V> {
+    fn default() -> Self {
+        Self {
+            ids: Default::default(),
+            values: Default::default(),
+        }
+    }
 }
 
-impl<I: IndexInfo> Default for HashmapStorage<I> {
+/// [`HashmapStorage`] that keeps the entities for each value in the order they were
+/// added to that value's bucket, so they can be read back in order with
+/// [`Index::lookup_ordered`][crate::index::Index::lookup_ordered].
+///
+/// Entities are appended when they are first indexed under a value or when their value
+/// changes. [`IndexSet`] uses somewhat more memory per entity than [`HashSet`], and removing
+/// an entity from a bucket is linear in the size of that bucket.
+pub type InsertionOrderStorage<I> = HashmapStorage<I, IndexSet<Entity>>;
+
+/// [`HashmapStorage`] that keeps each bucket in an [`EntityHashSet`], which uses a hasher
+/// specialized for [`Entity`] that is much cheaper than the default one.
+///
+/// Use [`Index::lookup_entityset`][crate::index::Index::lookup_entityset] to read a bucket
+/// without copying it. [`lookup`][crate::index::Index::lookup] still works, but has to
+/// rehash every entity to copy them into a [`HashSet`].
+pub type EntitySetStorage<I> = HashmapStorage<I, EntityHashSet<Entity>>;
+
+/// [`HashmapStorage`] whose buckets are hashed with a fixed `SEED`, so that
+/// [`Index::lookup_seeded`][crate::index::Index::lookup_seeded] iterates over entities in
+/// the same order every run, given the same sequence of changes to the world.
+///
+/// This is for reproducible debugging output, not a security feature. See [`SeededState`].
+pub type SeededStorage<I, const SEED: u64 = 0> = HashmapStorage<I, SeededHashSet<Entity, SEED>>;
+
+/// [`HashmapStorage`] that keeps each bucket behind an [`Arc`], so that its entities can
+/// be shared with [`Index::lookup_shared`][crate::index::Index::lookup_shared] instead of
+/// being copied into a new [`HashSet`] for every lookup.
+///
+/// This is useful for values that are looked up by many systems every frame. Changing a
+/// bucket while it is still shared from an earlier lookup copies it first.
+pub type SharedStorage<I> = HashmapStorage<I, SharedSet<Entity>>;
+
+/// [`HashmapStorage`] that remembers the result of
+/// [`Index::lookup_cached`][crate::index::Index::lookup_cached] for each bucket, so that
+/// repeated lookups of a bucket that hasn't changed share one [`HashSet`].
+///
+/// This suits a few values that are looked up every frame but rarely change. Each cached
+/// set is kept until its bucket changes, which costs memory for values that are only
+/// looked up once.
+pub type CachedStorage<I> = HashmapStorage<I, CachedSet<Entity>>;
+
+impl<I: IndexInfo, B: Bucket<Entity>> Default for HashmapStorage<I, B> {
     fn default() -> Self {
         Self {
             map: Default::default(),
-            last_refresh_tick: Tick::new(0),
+            last_refresh_tick: None,
+            empty: Default::default(),
+            removals: Default::default(),
+            value_ids: Default::default(),
+            on_change: Vec::new(),
+            #[cfg(feature = "history")]
+            history: VecDeque::new(),
+            #[cfg(feature = "channel")]
+            refresh_channel: None,
+            #[cfg(feature = "test-util")]
+            frozen: false,
+            dirty: false,
+            rebuild_threshold: Some(DEFAULT_REBUILD_THRESHOLD),
+            changed_at: Default::default(),
+            last_tick_check: Tick::new(0),
+            transitions: Transitions {
+                enabled: I::TRACK_TRANSITIONS,
+                ..Default::default()
+            },
+            #[cfg(test)]
+            rebuilds: 0,
         }
     }
 }
 
-impl<I: IndexInfo> IndexStorage<I> for HashmapStorage<I> {
-    type RefreshData<'w, 's> = HashmapStorageRefreshData<'w, 's, I>;
+impl<I: IndexInfo, B: Bucket<Entity>> HashmapStorage<I, B> {
+    /// Build a storage that already contains `entries`, for benchmarking lookups without
+    /// the cost of refreshing.
+    ///
+    /// Insert it as a resource before the index is first used. Refreshes only look at
+    /// components in the world, so entries for entities that don't have the component are
+    /// kept as they are, and a world without any such components refreshes almost for free.
+    #[doc(hidden)]
+    pub fn from_entries(entries: impl IntoIterator<Item = (I::Value, Entity)>) -> Self {
+        let mut storage = Self::default();
+        for (value, entity) in entries {
+            storage.map.insert(&value, &entity);
+        }
+        storage
+    }
 
-    fn lookup<'w, 's>(
+    /// Replace the contents of the index with `mapping`, and stop refreshes and removals from
+    /// changing it until [`clear_test_mapping`][Self::clear_test_mapping] is called.
+    ///
+    /// This is meant for unit-testing systems that use an index: the entities don't need the
+    /// indexed component, so a test can state exactly which entities map to which values.
+    ///
+    /// ```
+    /// # use bevy::prelude::*;
+    /// # use bevy::ecs::system::RunSystemOnce;
+    /// # use bevy_mod_index::prelude::*;
+    /// # #[derive(Component, Clone, PartialEq, Eq, Hash)]
+    /// # struct Team(u32);
+    /// fn team_size(mut idx: Index<ComponentIndex<Team>>) -> usize {
+    ///     idx.lookup(&Team(1)).len()
+    /// }
+    ///
+    /// let mut world = World::new();
+    /// let (a, b) = (world.spawn_empty().id(), world.spawn_empty().id());
+    /// let mut storage = HashmapStorage::<ComponentIndex<Team>>::default();
+    /// storage.set_test_mapping(vec![(Team(1), a), (Team(1), b)]);
+    /// world.insert_resource(storage);
+    ///
+    /// assert_eq!(world.run_system_once(team_size), 2);
+    /// ```
+    #[cfg(feature = "test-util")]
+    pub fn set_test_mapping(&mut self, mapping: Vec<(I::Value, Entity)>) {
+        self.map = Default::default();
+        for (value, entity) in mapping {
+            self.map.insert(&value, &entity);
+        }
+        self.frozen = true;
+    }
+
+    /// Let refreshes update the index again after
+    /// [`set_test_mapping`][Self::set_test_mapping], starting with a full rebuild.
+    #[cfg(feature = "test-util")]
+    pub fn clear_test_mapping(&mut self) {
+        self.map = Default::default();
+        self.last_refresh_tick = None;
+        self.frozen = false;
+    }
+
+    /// Get read-only access to the underlying map, for inspecting the index's contents.
+    ///
+    /// The map is kept up to date by refreshes of the index; to see the latest state of the
+    /// world, inspect it through an [`Index`][crate::index::Index].
+    pub fn map_ref(&self) -> &UniqueMultiMap<I::Value, Entity, B> {
+        &self.map
+    }
+
+    /// Get the bucket of entities with the given value, which may be empty.
+    pub(crate) fn bucket(&self, val: &I::Value) -> &B {
+        self.map.get_ref(val).unwrap_or(&self.empty)
+    }
+
+    /// Get the entities whose value hashes to `hash`, for
+    /// [`Index::lookup_by_hash`][crate::index::Index::lookup_by_hash].
+    pub(crate) fn lookup_by_hash(&mut self, hash: u64) -> HashSet<Entity> {
+        self.map.get_by_hash(hash)
+    }
+
+    /// Get the value that `entity` is currently indexed under.
+    pub(crate) fn value_of(&self, entity: Entity) -> Option<&I::Value> {
+        self.map.key_of(&entity)
+    }
+
+    /// Move `entity` to the bucket for `new_value`, removing it from its current bucket.
+    pub(crate) fn update(&mut self, entity: Entity, new_value: &I::Value) {
+        self.map.insert(new_value, &entity);
+    }
+
+    /// Exchange the values of `a` and `b`. If only one of them is indexed, the other takes
+    /// its value and it is removed.
+    pub(crate) fn swap(&mut self, a: Entity, b: Entity) {
+        let value_a = self.map.key_of(&a).cloned();
+        let value_b = self.map.key_of(&b).cloned();
+        for (entity, value) in [(a, value_b), (b, value_a)] {
+            match value {
+                Some(value) => self.map.insert(&value, &entity),
+                None => self.map.remove(&entity),
+            };
+        }
+    }
+
+    /// Make refreshes only re-index the entities received from `receiver`, or go back to
+    /// full refreshes with `None`.
+    #[cfg(feature = "channel")]
+    pub(crate) fn set_refresh_channel(&mut self, receiver: Option<Receiver<Entity>>) {
+        self.refresh_channel = receiver.map(Mutex::new);
+    }
+
+    /// Refresh only the entities waiting in the refresh channel, if one is set and a full
+    /// refresh has been done. Returns whether it did.
+    #[cfg(feature = "channel")]
+    fn refresh_from_channel<'w, 's>(
         &mut self,
-        val: &I::Value,
-        _data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
-    ) -> HashSet<Entity> {
-        self.map.get(val)
+        data: &mut StaticSystemParam<HashmapStorageRefreshData<'w, 's, I>>,
+    ) -> bool {
+        let (Some(channel), Some(_), false) =
+            (&self.refresh_channel, self.last_refresh_tick, self.dirty)
+        else {
+            return false;
+        };
+        let entities: Vec<Entity> = channel
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .try_iter()
+            .collect();
+        let this_run = data.ticks.this_run();
+        for entity in data.read_removals() {
+            self.unindex(entity, this_run);
+        }
+        self.refresh_entities(&entities, data);
+        true
     }
 
-    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
-        for entity in data.removals.read() {
-            self.map.remove(&entity);
+    /// Register a callback to run whenever a refresh moves an entity to a new value.
+    pub(crate) fn on_change(&mut self, callback: ChangeCallback<I::Value>) {
+        self.on_change.push(callback);
+    }
+
+    /// Index `entity` under `value` during a refresh at `tick`, notifying the change
+    /// callbacks if its value is different from before.
+    pub(crate) fn index_value(&mut self, value: &I::Value, entity: Entity, tick: Tick) {
+        let old = self.map.insert(value, &entity);
+        if old.as_ref() == Some(value) {
+            return;
+        }
+        if old.is_none() {
+            self.transitions.enter(entity);
+        }
+        self.transitions.change(entity);
+        self.mark_changed(entity, tick);
+        for callback in &mut self.on_change {
+            callback(entity, old.clone(), value.clone());
+        }
+        self.record(tick, || IndexChange::Insert {
+            entity,
+            old,
+            new: value.clone(),
+        });
+    }
+
+    /// Remove `entity` from the index during a refresh at `tick`.
+    pub(crate) fn unindex(&mut self, entity: Entity, tick: Tick) {
+        self.forget_change_tick(entity);
+        if let Some(old) = self.map.remove(&entity) {
+            self.transitions.exit(entity);
+            self.record(tick, || IndexChange::Remove { entity, old });
         }
+    }
+
+    /// Remember that `entity` got a new value at `tick`, if change ticks are tracked.
+    fn mark_changed(&mut self, entity: Entity, tick: Tick) {
+        if I::TRACK_CHANGE_TICKS {
+            self.changed_at.insert(entity, tick);
+        }
+    }
+
+    fn forget_change_tick(&mut self, entity: Entity) {
+        if I::TRACK_CHANGE_TICKS {
+            self.changed_at.remove(&entity);
+        }
+    }
+
+    /// Clamp the stored change ticks so that they stay comparable with `this_run`, the same
+    /// way [`World::check_change_ticks`] clamps component ticks, and only as often.
+    fn check_change_ticks(&mut self, this_run: Tick) {
+        if !I::TRACK_CHANGE_TICKS
+            || this_run.get().wrapping_sub(self.last_tick_check.get()) < CHECK_TICK_THRESHOLD
+        {
+            return;
+        }
+        for tick in self.changed_at.values_mut() {
+            if this_run.get().wrapping_sub(tick.get()) > Tick::MAX.get() {
+                tick.set(this_run.get().wrapping_sub(Tick::MAX.get()));
+            }
+        }
+        self.last_tick_check = this_run;
+    }
+
+    /// Add a change to the history, if it is enabled.
+    #[cfg_attr(not(feature = "history"), allow(unused_variables))]
+    fn record(&mut self, tick: Tick, change: impl FnOnce() -> IndexChange<I::Value>) {
+        #[cfg(feature = "history")]
+        if I::HISTORY_LEN > 0 {
+            if self.history.len() == I::HISTORY_LEN {
+                self.history.pop_front();
+            }
+            self.history.push_back((tick, change()));
+        }
+    }
+
+    /// Get the recorded changes, oldest first.
+    #[cfg(feature = "history")]
+    pub(crate) fn recent_changes(&mut self) -> &[(Tick, IndexChange<I::Value>)] {
+        self.history.make_contiguous()
+    }
+
+    /// Refresh this storage, taking the refresh data directly so that other storages can
+    /// wrap this one.
+    pub(crate) fn refresh_from(&mut self, data: &mut HashmapStorageRefreshData<I>) {
+        self.refresh_skipping(data, |_| false);
+    }
+
+    /// Like [`refresh_from`][Self::refresh_from], but leaves out the entities that `skip`
+    /// returns `true` for, and drops them if the full re-check after
+    /// [`invalidate`][IndexStorage::invalidate] finds them in the index.
+    pub(crate) fn refresh_skipping(
+        &mut self,
+        data: &mut HashmapStorageRefreshData<I>,
+        skip: impl Fn(Entity) -> bool,
+    ) {
+        let this_run = data.ticks.this_run();
+        self.transitions.begin(data.frame.get());
+        self.check_change_ticks(this_run);
+        if std::mem::take(&mut self.dirty) {
+            // Re-check every component, and drop entities that don't have one any more.
+            self.last_refresh_tick = None;
+            let stale: Vec<Entity> = self
+                .map
+                .iter_rev()
+                .map(|(entity, _)| *entity)
+                .filter(|entity| !data.components.contains(*entity) || skip(*entity))
+                .collect();
+            for entity in stale {
+                self.unindex(entity, this_run);
+            }
+        }
+        for entity in data.read_removals() {
+            self.unindex(entity, this_run);
+        }
+        let mut rebuild_after = self.rebuild_limit(data);
         for (entity, component) in &data.components {
-            if component.last_changed().is_newer_than(
-                // Subtract 1 so that changes from the system where the index was updated are seen.
-                // The `is_newer_than` implementation assumes we don't care about those changes since
-                // "this" system is the one that made the change, but for indexing, we do care.
-                Tick::new(self.last_refresh_tick.get().wrapping_sub(1)),
-                data.ticks.this_run(),
-            ) {
-                self.map.insert(&I::value(&component), &entity);
+            if changed_since_refresh::<I>(&component, self.last_refresh_tick, this_run)
+                && !skip(entity)
+            {
+                // Once enough components have changed, re-checking the rest at once is
+                // cheaper than moving each of them between buckets.
+                if let Some(limit) = &mut rebuild_after {
+                    if *limit == 0 {
+                        self.rebuild_from(data, this_run, skip);
+                        self.last_refresh_tick = Some(this_run);
+                        return;
+                    }
+                    *limit -= 1;
+                }
+                match indexed_value::<I>(entity, &component, &data.value_param) {
+                    Some(value) => self.index_value(&value, entity, this_run),
+                    None => self.unindex(entity, this_run),
+                };
             }
         }
-        self.last_refresh_tick = data.ticks.this_run();
+        self.last_refresh_tick = Some(this_run);
     }
-}
 
-type ComponentsQuery<'w, 's, T> =
-    Query<'w, 's, (Entity, Ref<'static, <T as IndexInfo>::Component>)>;
+    /// Rebuild the map from scratch when more than `fraction` of the components changed at
+    /// once, or always update it entity by entity with `None`.
+    pub(crate) fn set_rebuild_threshold(&mut self, fraction: Option<f32>) {
+        self.rebuild_threshold = fraction;
+    }
 
-#[doc(hidden)]
-#[derive(SystemParam)]
-pub struct HashmapStorageRefreshData<'w, 's, I: IndexInfo> {
-    components: ComponentsQuery<'w, 's, I>,
-    removals: RemovedComponents<'w, 's, <I as IndexInfo>::Component>,
-    ticks: SystemChangeTick,
+    /// How many changed components a refresh updates entity by entity before rebuilding the
+    /// map instead, or `None` if it never rebuilds. Storages that report changes never
+    /// rebuild, since a rebuild doesn't notify callbacks or record history.
+    fn rebuild_limit(&self, data: &HashmapStorageRefreshData<I>) -> Option<usize> {
+        let fraction = self.rebuild_threshold?;
+        // Before the first refresh, every component counts as changed.
+        self.last_refresh_tick?;
+        #[cfg(feature = "history")]
+        if I::HISTORY_LEN > 0 {
+            return None;
+        }
+        if !self.on_change.is_empty() {
+            return None;
+        }
+        // Counting the components only adds up the sizes of the tables that they are in.
+        Some((fraction * data.components.iter().len() as f32) as usize)
+    }
+
+    /// Replace the map with a new one that has the same entities as an entity by entity
+    /// refresh would leave, without removing any of them from their old buckets.
+    ///
+    /// Only components that changed since the last refresh are re-indexed, following the
+    /// same rules as [`changed_since_refresh`], so changes hidden from the refreshing system
+    /// and entities taken out of the index stay as they were. Entities without a component
+    /// whose removal was missed are kept too.
+    fn rebuild_from(
+        &mut self,
+        data: &HashmapStorageRefreshData<I>,
+        tick: Tick,
+        skip: impl Fn(Entity) -> bool,
+    ) {
+        #[cfg(test)]
+        {
+            self.rebuilds += 1;
+        }
+        let mut map = UniqueMultiMap::default();
+        map.reserve(self.map.len());
+        for (entity, component) in &data.components {
+            let old = self.map.key_of(&entity);
+            if !changed_since_refresh::<I>(&component, self.last_refresh_tick, tick) || skip(entity)
+            {
+                if let Some(old) = old {
+                    map.insert(old, &entity);
+                }
+                continue;
+            }
+            let Some(value) = indexed_value::<I>(entity, &component, &data.value_param) else {
+                continue;
+            };
+            match old {
+                Some(old) if *old == value => {}
+                Some(_) => {
+                    self.mark_changed(entity, tick);
+                    self.transitions.change(entity);
+                }
+                None => {
+                    self.mark_changed(entity, tick);
+                    self.transitions.enter(entity);
+                    self.transitions.change(entity);
+                }
+            }
+            map.insert(&value, &entity);
+        }
+        for (&entity, value) in self.map.iter_rev() {
+            if map.key_of(&entity).is_some() {
+                continue;
+            }
+            if data.components.contains(entity) {
+                self.transitions.exit(entity);
+            } else {
+                map.insert(value, &entity);
+            }
+        }
+        if I::TRACK_CHANGE_TICKS {
+            self.changed_at
+                .retain(|entity, _| map.key_of(entity).is_some());
+        }
+        self.map = map;
+    }
+
+    /// Get the entities that entered the index this frame and are still in it.
+    pub(crate) fn entered(&self) -> Vec<Entity> {
+        self.transitions.entered.iter().copied().collect()
+    }
+
+    /// Get the entities that exited the index this frame and haven't entered it again.
+    pub(crate) fn exited(&self) -> Vec<Entity> {
+        self.transitions.exited.iter().copied().collect()
+    }
+
+    /// Get the entities that were given a new value this frame and are still in the index.
+    pub(crate) fn changed_this_frame(&self) -> &HashSet<Entity> {
+        &self.transitions.changed
+    }
+
+    /// Forget that `entity` was given a new value this frame.
+    pub(crate) fn forget_changed(&mut self, entity: Entity) {
+        self.transitions.changed.remove(&entity);
+    }
+
+    /// Get the entities whose value last changed in a refresh after `tick`, with their
+    /// current values.
+    pub(crate) fn changed_since(&self, tick: Tick, this_run: Tick) -> Vec<(Entity, I::Value)> {
+        self.changed_at
+            .iter()
+            .filter(|(_, changed)| changed.is_newer_than(tick, this_run))
+            .filter_map(|(&entity, _)| Some((entity, self.map.key_of(&entity)?.clone())))
+            .collect()
+    }
+
+    /// Remove the bucket for `val` at `tick`, returning its entities. The entities exit
+    /// the index as if they had been removed by a refresh.
+    pub(crate) fn take(&mut self, val: &I::Value, tick: Tick) -> Option<B> {
+        let bucket = self.map.remove_key(val)?;
+        for &entity in bucket.iter() {
+            self.forget_change_tick(entity);
+            self.transitions.exit(entity);
+            self.record(tick, || IndexChange::Remove {
+                entity,
+                old: val.clone(),
+            });
+        }
+        Some(bucket)
+    }
+
+    /// Get the id for `val`, assigning the next unused id if it doesn't have one yet.
+    pub(crate) fn value_id(&mut self, val: &I::Value) -> u64 {
+        let ids = &mut self.value_ids;
+        if let Some(&id) = ids.ids.get(val) {
+            return id;
+        }
+        let id = ids.ids.len() as u64;
+        ids.ids.insert(val.clone(), id);
+        ids.values.insert(id, val.clone());
+        id
+    }
+
+    /// Get the value that was assigned `id` by [`value_id`][Self::value_id].
+    pub(crate) fn value_for_id(&self, id: u64) -> Option<&I::Value> {
+        self.value_ids.values.get(&id)
+    }
+
+    /// Reserve space for every changed component that isn't indexed yet, so that the next
+    /// refresh doesn't need to grow the map repeatedly.
+    pub(crate) fn reserve_for_refresh<'w, 's>(
+        &mut self,
+        data: &StaticSystemParam<HashmapStorageRefreshData<'w, 's, I>>,
+    ) {
+        let this_run = data.ticks.this_run();
+        let new = data
+            .components
+            .iter()
+            .filter(|(entity, component)| {
+                changed_since_refresh::<I>(component, self.last_refresh_tick, this_run)
+                    && self.map.key_of(entity).is_none()
+            })
+            .count();
+        self.map.reserve(new);
+    }
+
+    /// Refresh only the given entities, leaving the refresh tick alone so that the next
+    /// full refresh still sees changes to every other entity.
+    pub(crate) fn refresh_entities<'w, 's>(
+        &mut self,
+        entities: &[Entity],
+        data: &StaticSystemParam<HashmapStorageRefreshData<'w, 's, I>>,
+    ) {
+        let this_run = data.ticks.this_run();
+        for &entity in entities {
+            match data.components.get(entity) {
+                Ok((_, component)) => {
+                    if changed_since_refresh::<I>(&component, self.last_refresh_tick, this_run) {
+                        match indexed_value::<I>(entity, &component, &data.value_param) {
+                            Some(value) => self.index_value(&value, entity, this_run),
+                            None => self.unindex(entity, this_run),
+                        };
+                    }
+                }
+                Err(_) => self.unindex(entity, this_run),
+            }
+        }
+    }
+
+    /// Remove all mappings, and reset the refresh tick so that the next refresh
+    /// re-indexes every component.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = (I::Value, Entity)> {
+        self.last_refresh_tick = None;
+        self.map.drain()
+    }
+
+    /// Remove every entity for which `is_live` returns false.
+    pub(crate) fn prune(&mut self, is_live: impl Fn(Entity) -> bool) {
+        let dead: Vec<Entity> = self
+            .map
+            .iter_rev()
+            .map(|(&entity, _)| entity)
+            .filter(|&entity| !is_live(entity))
+            .collect();
+        for entity in dead {
+            self.forget_change_tick(entity);
+            self.map.remove(&entity);
+        }
+    }
+
+    /// Get each distinct value along with the number of entities that have it.
+    pub(crate) fn bucket_sizes(&self) -> Vec<(I::Value, usize)> {
+        self.map
+            .iter()
+            .map(|(k, set)| (k.clone(), set.len()))
+            .collect()
+    }
+
+    /// Get the entities with the given value along with their components.
+    pub(crate) fn lookup_with<'a, 'pw, 'ps, 'w, 's>(
+        &'a self,
+        val: &I::Value,
+        data: &'a StaticSystemParam<'pw, 'ps, HashmapStorageRefreshData<'w, 's, I>>,
+    ) -> impl Iterator<Item = (Entity, &'a I::Component)> + use<'a, 'pw, 'ps, 'w, 's, I, B> {
+        self.bucket(val)
+            .iter()
+            .filter_map(|&entity| data.components.get(entity).ok())
+            .map(|(entity, component)| (entity, component.into_inner()))
+    }
+
+    /// Compute the changes that the next refresh would make, without applying them.
+    pub(crate) fn pending_changes<'w, 's>(
+        &self,
+        data: &StaticSystemParam<HashmapStorageRefreshData<'w, 's, I>>,
+    ) -> Vec<IndexChange<I::Value>> {
+        let mut changes = Vec::new();
+        // Rather than reading removal events, which would consume them, look for
+        // entities that no longer have the component.
+        for (&entity, old) in self.map.iter_rev() {
+            if !data.components.contains(entity) {
+                changes.push(IndexChange::Remove {
+                    entity,
+                    old: old.clone(),
+                });
+            }
+        }
+        for (entity, component) in &data.components {
+            if !changed_since_refresh::<I>(
+                &component,
+                self.last_refresh_tick,
+                data.ticks.this_run(),
+            ) {
+                continue;
+            }
+            let old = self.map.key_of(&entity);
+            match indexed_value::<I>(entity, &component, &data.value_param) {
+                Some(new) if old != Some(&new) => changes.push(IndexChange::Insert {
+                    entity,
+                    old: old.cloned(),
+                    new,
+                }),
+                None if old.is_some() => changes.push(IndexChange::Remove {
+                    entity,
+                    old: old.unwrap().clone(),
+                }),
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    /// Compare the stored mappings against the current state of the world.
+    pub(crate) fn discrepancies<'w, 's>(
+        &self,
+        data: &StaticSystemParam<HashmapStorageRefreshData<'w, 's, I>>,
+    ) -> Vec<Discrepancy<I::Value>> {
+        let mut discrepancies = Vec::new();
+        for (entity, component) in &data.components {
+            let expected = indexed_value::<I>(entity, &component, &data.value_param);
+            let actual = self.map.key_of(&entity);
+            if expected.as_ref() != actual {
+                discrepancies.push(Discrepancy {
+                    entity,
+                    expected,
+                    actual: actual.cloned(),
+                });
+            }
+        }
+        for (&entity, actual) in self.map.iter_rev() {
+            if !data.components.contains(entity) {
+                discrepancies.push(Discrepancy {
+                    entity,
+                    expected: None,
+                    actual: Some(actual.clone()),
+                });
+            }
+        }
+        discrepancies
+    }
+
+    /// Get all of the entities whose value satisfies `pred`.
+    pub(crate) fn lookup_where(&self, pred: impl Fn(&I::Value) -> bool) -> HashSet<Entity> {
+        self.map
+            .iter()
+            .filter(|(k, _)| pred(k))
+            .flat_map(|(_, set)| set.iter().copied())
+            .collect()
+    }
+}
+
+impl<I: IndexInfo, B: Bucket<Entity>> IndexStorage<I> for HashmapStorage<I, B> {
+    type RefreshData<'w, 's> = HashmapStorageRefreshData<'w, 's, I>;
+
+    fn lookup<'w, 's>(
+        &mut self,
+        val: &I::Value,
+        _data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
+    ) -> HashSet<Entity> {
+        self.map.get(val)
+    }
+
+    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
+        #[cfg(feature = "test-util")]
+        if self.frozen {
+            return;
+        }
+        #[cfg(feature = "channel")]
+        if self.refresh_from_channel(data) {
+            return;
+        }
+        self.refresh_from(data);
+    }
+
+    fn apply(&mut self, world: &World) {
+        let tick = world.read_change_tick();
+        #[cfg(feature = "test-util")]
+        if self.frozen {
+            return;
+        }
+        if let Some(frame) = world.get_resource::<FrameCount>() {
+            if I::TRACK_TRANSITIONS {
+                self.transitions.begin(Some(frame.0));
+            }
+        }
+        for entity in self.removals.read(world) {
+            self.unindex(entity, tick);
+        }
+    }
+
+    fn entity_count(&self) -> Option<usize> {
+        Some(self.map.len())
+    }
+
+    fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+}
+
+pub(crate) fn changed_since_refresh<I: IndexInfo>(
+    component: &Ref<I::Component>,
+    last_refresh_tick: Option<Tick>,
+    this_run: Tick,
+) -> bool {
+    tick_since_refresh::<I>(component.last_changed(), last_refresh_tick, this_run)
+}
+
+/// Like [`changed_since_refresh`], but for a change tick from any component.
+fn tick_since_refresh<I: IndexInfo>(
+    last_changed: Tick,
+    last_refresh_tick: Option<Tick>,
+    this_run: Tick,
+) -> bool {
+    // Skip changes made by the system that is currently refreshing; they will be seen by
+    // the next refresh from any other system since they happen at `last_refresh_tick`.
+    if !I::SEE_OWN_CHANGES && last_changed == this_run {
+        return false;
+    }
+    // Before the first refresh, everything counts as changed, however old it is.
+    let Some(last_refresh_tick) = last_refresh_tick else {
+        return true;
+    };
+    // Changes made at `last_refresh_tick` come from the system that last refreshed the
+    // index, after it did so. `is_newer_than` assumes we don't care about those changes
+    // since "this" system is the one that made them, but for indexing, we do care.
+    last_changed == last_refresh_tick || last_changed.is_newer_than(last_refresh_tick, this_run)
+}
+
+type ComponentsQuery<'w, 's, T> =
+    Query<'w, 's, (Entity, Ref<'static, <T as IndexInfo>::Component>)>;
+
+#[doc(hidden)]
+#[derive(SystemParam)]
+pub struct HashmapStorageRefreshData<'w, 's, I: IndexInfo> {
+    pub(crate) components: ComponentsQuery<'w, 's, I>,
+    pub(crate) value_param: StaticSystemParam<'w, 's, <I as IndexInfo>::ValueParam>,
+    pub(crate) removals: RemovedComponents<'w, 's, <I as IndexInfo>::Component>,
+    pub(crate) ticks: SystemChangeTick,
+    pub(crate) frame: TransitionFrame<'w, I>,
+}
+
+/// The current [`FrameCount`] for indexes with [`IndexInfo::TRACK_TRANSITIONS`], which is
+/// not fetched at all for other indexes, so that their systems don't read it.
+#[doc(hidden)]
+pub struct TransitionFrame<'w, I: IndexInfo> {
+    frame: Option<Res<'w, FrameCount>>,
+    phantom: PhantomData<fn() -> I>,
+}
+
+impl<'w, I: IndexInfo> TransitionFrame<'w, I> {
+    /// The current frame, if transitions are tracked and frames are counted.
+    pub(crate) fn get(&self) -> Option<u32> {
+        self.frame.as_ref().map(|frame| frame.0)
+    }
+}
+
+type FrameParam<'w> = Option<Res<'w, FrameCount>>;
+
+// SAFETY: Access is declared and fetched by delegating to `Option<Res<FrameCount>>`, or not at
+// all when transitions aren't tracked.
+unsafe impl<'w, I: IndexInfo> SystemParam for TransitionFrame<'w, I> {
+    type State = Option<<FrameParam<'static> as SystemParam>::State>;
+    type Item<'w2, 's2> = TransitionFrame<'w2, I>;
+
+    fn init_state(world: &mut World, system_meta: &mut SystemMeta) -> Self::State {
+        I::TRACK_TRANSITIONS.then(|| <FrameParam as SystemParam>::init_state(world, system_meta))
+    }
+
+    unsafe fn get_param<'w2, 's2>(
+        state: &'s2 mut Self::State,
+        system_meta: &SystemMeta,
+        world: UnsafeWorldCell<'w2>,
+        change_tick: Tick,
+    ) -> Self::Item<'w2, 's2> {
+        TransitionFrame {
+            frame: state.as_mut().and_then(|state| {
+                <FrameParam as SystemParam>::get_param(state, system_meta, world, change_tick)
+            }),
+            phantom: PhantomData,
+        }
+    }
+}
+
+// SAFETY: Only reads the `FrameCount` resource.
+unsafe impl<'w, I: IndexInfo> ReadOnlySystemParam for TransitionFrame<'w, I> {}
+
+impl<'w, 's, I: IndexInfo> HashmapStorageRefreshData<'w, 's, I> {
+    /// Read the entities whose component was removed since this system last read them.
+    ///
+    /// Each system has its own reader, so a removal may be read after another system has
+    /// already indexed the component being added back. Entities that have the component
+    /// again are skipped: they were either indexed since, or they are changed and will be
+    /// re-indexed by this refresh.
+    pub(crate) fn read_removals(&mut self) -> Vec<Entity> {
+        let components = &self.components;
+        self.removals
+            .read()
+            .filter(|&entity| !components.contains(entity))
+            .collect()
+    }
+}
+
+//======================================================================
+
+/// [`IndexStorage`] implementation that works like [`HashmapStorage`], but only keeps the
+/// `N` most recently looked up buckets.
+///
+/// Whenever a refresh leaves more than `N` distinct values in the index, the buckets that
+/// were least recently looked up (or created) are dropped. This bounds the memory used by
+/// indexes over values that are only relevant for a short time, like transient ids, at
+/// the cost of completeness: looking up a dropped value finds nothing. The storage
+/// remembers which entities it dropped, and the next refresh after such a lookup
+/// re-populates the value's bucket from those that still have it, so it can be found again
+/// from then on. Entities whose component changes are re-indexed as usual.
+///
+/// | Feature | `LruStorage` |
+/// |---|---|
+/// | Automatic refresh timing | When used; once per-system run |
+/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] |
+/// | Sees updates from earlier in the frame? | Yes |
+#[derive(Resource)]
+pub struct LruStorage<I: IndexInfo, const N: usize> {
+    inner: HashmapStorage<I>,
+    last_used: HashMap<I::Value, u64>,
+    clock: u64,
+    evicted: HashSet<Entity>,
+    wanted: HashSet<I::Value>,
+}
+
+impl<I: IndexInfo, const N: usize> Default for LruStorage<I, N> {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            last_used: Default::default(),
+            clock: 0,
+            evicted: Default::default(),
+            wanted: Default::default(),
+        }
+    }
+}
+
+impl<I: IndexInfo, const N: usize> LruStorage<I, N> {
+    fn touch(&mut self, val: &I::Value) {
+        self.clock += 1;
+        match self.last_used.get_mut(val) {
+            Some(t) => *t = self.clock,
+            None => {
+                self.last_used.insert(val.clone(), self.clock);
+            }
+        }
+    }
+
+    /// Put the dropped entities that still have a value that was looked up since back into
+    /// the index, and forget the ones that were removed or re-indexed since.
+    fn restore(&mut self, data: &HashmapStorageRefreshData<I>) {
+        let this_run = data.ticks.this_run();
+        let wanted = std::mem::take(&mut self.wanted);
+        if wanted.is_empty() {
+            let inner = &self.inner;
+            self.evicted.retain(|&entity| {
+                data.components.contains(entity) && inner.value_of(entity).is_none()
+            });
+            return;
+        }
+        let evicted: Vec<Entity> = self.evicted.drain().collect();
+        for entity in evicted {
+            let Ok((_, component)) = data.components.get(entity) else {
+                continue;
+            };
+            if self.inner.value_of(entity).is_some() {
+                continue;
+            }
+            match indexed_value::<I>(entity, &component, &data.value_param) {
+                Some(value) if wanted.contains(&value) => {
+                    self.inner.index_value(&value, entity, this_run)
+                }
+                Some(_) => {
+                    self.evicted.insert(entity);
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn evict(&mut self, tick: Tick) {
+        // Forget about values that were looked up but don't have any entities.
+        let map = &self.inner.map;
+        self.last_used.retain(|val, _| map.get_ref(val).is_some());
+
+        let excess = self.inner.map.key_count().saturating_sub(N);
+        if excess == 0 {
+            return;
+        }
+        let mut by_age: Vec<(u64, I::Value)> = self
+            .last_used
+            .iter()
+            .map(|(val, t)| (*t, val.clone()))
+            .collect();
+        by_age.sort_unstable_by_key(|(t, _)| *t);
+        for (_, val) in by_age.into_iter().take(excess) {
+            let entities: Vec<Entity> = self.inner.bucket(&val).iter().copied().collect();
+            for entity in entities {
+                self.inner.unindex(entity, tick);
+                self.evicted.insert(entity);
+            }
+            self.last_used.remove(&val);
+        }
+    }
+}
+
+impl<I: IndexInfo, const N: usize> IndexStorage<I> for LruStorage<I, N> {
+    type RefreshData<'w, 's> = HashmapStorageRefreshData<'w, 's, I>;
+
+    fn lookup<'w, 's>(
+        &mut self,
+        val: &I::Value,
+        data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
+    ) -> HashSet<Entity> {
+        if self.inner.map.get_ref(val).is_some() {
+            self.touch(val);
+        } else if !self.evicted.is_empty() {
+            self.wanted.insert(val.clone());
+        }
+        self.inner.lookup(val, data)
+    }
+
+    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
+        self.inner.refresh(data);
+        if !self.evicted.is_empty() {
+            self.restore(data);
+        }
+        // Buckets created by this refresh count as used now.
+        let new_values: Vec<I::Value> = self
+            .inner
+            .map
+            .iter()
+            .filter(|(val, _)| !self.last_used.contains_key(*val))
+            .map(|(val, _)| val.clone())
+            .collect();
+        for val in &new_values {
+            self.touch(val);
+        }
+        self.evict(data.ticks.this_run());
+    }
+
+    fn apply(&mut self, world: &World) {
+        self.inner.apply(world);
+    }
+
+    fn entity_count(&self) -> Option<usize> {
+        self.inner.entity_count()
+    }
+
+    fn invalidate(&mut self) {
+        // The full re-check indexes evicted entities again, and the next refresh evicts
+        // whatever is over the limit.
+        self.inner.invalidate();
+        self.evicted.clear();
+        self.wanted.clear();
+    }
+}
+
+//======================================================================
+
+/// [`IndexStorage`] implementation that works like [`HashmapStorage`], but also re-indexes
+/// an entity when the `D` component changes on any entity that it references, as given
+/// by [`IndexInfo::dependency`] or [`IndexInfo::dependencies`].
+///
+/// This allows indexing by a value computed from another entity, e.g. a `Targeting(Entity)`
+/// component by the `Team` of its target, read through a [`Query`] in the index's
+/// [`ValueParam`][IndexInfo::ValueParam]:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// #[derive(Component, Clone, Copy, PartialEq, Eq, Hash)]
+/// struct Team(u32);
+/// #[derive(Component)]
+/// struct Targeting(Entity);
+///
+/// struct TargetTeam;
+/// impl IndexInfo for TargetTeam {
+///     type Component = Targeting;
+///     type Value = Team;
+///     type Storage = DependentStorage<Self, Team>;
+///     type ValueParam = Query<'static, 'static, &'static Team>;
+///
+///     fn value(_: &Targeting, _: &Query<&Team>) -> Team {
+///         unreachable!()
+///     }
+///     fn try_value(c: &Targeting, teams: &Query<&Team>) -> Option<Team> {
+///         teams.get(c.0).ok().copied()
+///     }
+///     fn dependency(c: &Targeting) -> Option<Entity> {
+///         Some(c.0)
+///     }
+/// }
+/// ```
+///
+/// Values can also aggregate several entities, e.g. a parent can be indexed by the total
+/// `Weight` of its [`Children`] by indexing the `Children` component with a
+/// `Query<&Weight>` as the value param, and returning the children from
+/// [`dependencies`][IndexInfo::dependencies]. The parent is re-indexed when its set of
+/// children changes, since that changes its `Children` component, and when any child's
+/// `Weight` changes. Each re-index costs O(children) to recompute the aggregate.
+///
+/// On top of the cost of a [`HashmapStorage`], each refresh checks every `D` component for
+/// changes, and the storage keeps two extra maps between referencing and referenced entities.
+///
+/// | Feature | `DependentStorage` |
+/// |---|---|
+/// | Automatic refresh timing | When used; once per-system run |
+/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] |
+/// | Sees updates from earlier in the frame? | Yes |
+#[derive(Resource)]
+pub struct DependentStorage<I: IndexInfo, D: Component> {
+    inner: HashmapStorage<I>,
+    depends_on: HashMap<Entity, Vec<Entity>>,
+    dependents: HashMap<Entity, HashSet<Entity>>,
+    last_refresh_tick: Option<Tick>,
+    phantom: PhantomData<fn() -> D>,
+}
+
+impl<I: IndexInfo, D: Component> Default for DependentStorage<I, D> {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            depends_on: Default::default(),
+            dependents: Default::default(),
+            last_refresh_tick: None,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<I: IndexInfo, D: Component> DependentStorage<I, D> {
+    fn set_dependencies(&mut self, entity: Entity, dependencies: Vec<Entity>) {
+        for old in self.depends_on.remove(&entity).into_iter().flatten() {
+            if let Some(set) = self.dependents.get_mut(&old) {
+                set.remove(&entity);
+                if set.is_empty() {
+                    self.dependents.remove(&old);
+                }
+            }
+        }
+        if dependencies.is_empty() {
+            return;
+        }
+        for &dependency in &dependencies {
+            self.dependents
+                .entry(dependency)
+                .or_default()
+                .insert(entity);
+        }
+        self.depends_on.insert(entity, dependencies);
+    }
+}
+
+impl<I: IndexInfo, D: Component> IndexStorage<I> for DependentStorage<I, D> {
+    type RefreshData<'w, 's> = DependentStorageRefreshData<'w, 's, I, D>;
+
+    fn lookup<'w, 's>(
+        &mut self,
+        val: &I::Value,
+        _data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
+    ) -> HashSet<Entity> {
+        self.inner.map.get(val)
+    }
+
+    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
+        let data = &mut **data;
+        let this_run = data.inner.ticks.this_run();
+
+        for entity in data.inner.read_removals() {
+            self.inner.unindex(entity, this_run);
+            self.set_dependencies(entity, Vec::new());
+        }
+        for (entity, component) in &data.inner.components {
+            if changed_since_refresh::<I>(&component, self.last_refresh_tick, this_run) {
+                self.set_dependencies(entity, I::dependencies(&component).collect());
+            }
+        }
+
+        // Entities that reference a changed entity need to be re-indexed even if their own
+        // component didn't change.
+        let mut stale = HashSet::new();
+        for (dependency, component) in &data.dependencies {
+            if tick_since_refresh::<I>(component.last_changed(), self.last_refresh_tick, this_run) {
+                stale.extend(self.dependents.get(&dependency).into_iter().flatten());
+            }
+        }
+        for dependency in data.removed_dependencies.read() {
+            stale.extend(self.dependents.get(&dependency).into_iter().flatten());
+        }
+
+        self.inner.refresh_from(&mut data.inner);
+        for entity in stale {
+            if let Ok((_, component)) = data.inner.components.get(entity) {
+                match indexed_value::<I>(entity, &component, &data.inner.value_param) {
+                    Some(value) => self.inner.index_value(&value, entity, this_run),
+                    None => self.inner.unindex(entity, this_run),
+                };
+            }
+        }
+        self.last_refresh_tick = Some(this_run);
+    }
+
+    fn apply(&mut self, world: &World) {
+        self.inner.apply(world);
+    }
+
+    fn entity_count(&self) -> Option<usize> {
+        self.inner.entity_count()
+    }
+
+    fn invalidate(&mut self) {
+        self.inner.invalidate();
+        self.depends_on.clear();
+        self.dependents.clear();
+        self.last_refresh_tick = None;
+    }
+}
+
+#[doc(hidden)]
+#[derive(SystemParam)]
+pub struct DependentStorageRefreshData<'w, 's, I: IndexInfo, D: Component> {
+    inner: HashmapStorageRefreshData<'w, 's, I>,
+    dependencies: Query<'w, 's, (Entity, Ref<'static, D>)>,
+    removed_dependencies: RemovedComponents<'w, 's, D>,
+}
+
+//======================================================================
+
+/// [`IndexStorage`] implementation that works like [`HashmapStorage`], but also keeps track
+/// of which entity in each group has the largest `M` component, where the group is the
+/// entity's value in the index.
+///
+/// Use [`Index::group_max`][crate::index::Index::group_max] to look up the largest measure
+/// in a group. Entities without an `M` component are still indexed, but never count as
+/// the maximum of their group. Ties are broken by the larger [`Entity`].
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// struct Team(u32);
+///
+/// #[derive(Component, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// struct Score(u32);
+///
+/// struct TeamIndex;
+///
+/// impl IndexInfo for TeamIndex {
+///     type Component = Team;
+///     type Value = Team;
+///     type Storage = GroupMaxStorage<Self, Score>;
+///     type ValueParam = ();
+///
+///     fn value(c: &Team, _: &()) -> Team {
+///         c.clone()
+///     }
+/// }
+///
+/// fn best_player(mut idx: Index<TeamIndex>) {
+///     if let Some((score, player)) = idx.group_max(&Team(1)) {
+///         // ...
+///     }
+/// }
+/// ```
+///
+/// On top of the cost of a [`HashmapStorage`], each refresh checks every `M` component for
+/// changes, and each change to an entity's group or measure costs O(log n) in the size of
+/// its group.
+///
+/// | Feature | `GroupMaxStorage` |
+/// |---|---|
+/// | Automatic refresh timing | When used; once per-system run |
+/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] |
+/// | Sees updates from earlier in the frame? | Yes |
+#[derive(Resource)]
+pub struct GroupMaxStorage<I: IndexInfo, M: Component + Ord + Clone> {
+    inner: HashmapStorage<I>,
+    groups: HashMap<I::Value, BTreeSet<(M, Entity)>>,
+    placed: HashMap<Entity, (I::Value, M)>,
+    last_refresh_tick: Option<Tick>,
+    removals: RemovalReader<I::Component>,
+    measure_removals: RemovalReader<M>,
+}
+
+impl<I: IndexInfo, M: Component + Ord + Clone> Default for GroupMaxStorage<I, M> {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            groups: Default::default(),
+            placed: Default::default(),
+            last_refresh_tick: None,
+            removals: Default::default(),
+            measure_removals: Default::default(),
+        }
+    }
+}
+
+impl<I: IndexInfo, M: Component + Ord + Clone> GroupMaxStorage<I, M> {
+    /// Get the largest measure in `group`, and the entity that has it.
+    pub(crate) fn group_max(&self, group: &I::Value) -> Option<(&M, Entity)> {
+        let (measure, entity) = self.groups.get(group)?.last()?;
+        Some((measure, *entity))
+    }
+
+    fn place(&mut self, entity: Entity, measure: Option<&M>) {
+        self.unplace(entity);
+        let (Some(group), Some(measure)) = (self.inner.value_of(entity), measure) else {
+            return;
+        };
+        self.groups
+            .entry(group.clone())
+            .or_default()
+            .insert((measure.clone(), entity));
+        self.placed.insert(entity, (group.clone(), measure.clone()));
+    }
+
+    fn unplace(&mut self, entity: Entity) {
+        let Some((group, measure)) = self.placed.remove(&entity) else {
+            return;
+        };
+        let set = self
+            .groups
+            .get_mut(&group)
+            .expect("unplace: Cached group was not present in group map!");
+        set.remove(&(measure, entity));
+        if set.is_empty() {
+            self.groups.remove(&group);
+        }
+    }
+}
+
+impl<I: IndexInfo, M: Component + Ord + Clone> IndexStorage<I> for GroupMaxStorage<I, M> {
+    type RefreshData<'w, 's> = GroupMaxStorageRefreshData<'w, 's, I, M>;
+
+    fn lookup<'w, 's>(
+        &mut self,
+        val: &I::Value,
+        _data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
+    ) -> HashSet<Entity> {
+        self.inner.map.get(val)
+    }
+
+    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
+        let data = &mut **data;
+        let this_run = data.inner.ticks.this_run();
+
+        let mut stale = HashSet::new();
+        for entity in data.inner.read_removals() {
+            self.inner.unindex(entity, this_run);
+            stale.insert(entity);
+        }
+        stale.extend(data.removed_measures.read());
+        for (entity, component) in &data.inner.components {
+            if changed_since_refresh::<I>(&component, self.last_refresh_tick, this_run) {
+                stale.insert(entity);
+            }
+        }
+        for (entity, measure) in &data.measures {
+            if tick_since_refresh::<I>(measure.last_changed(), self.last_refresh_tick, this_run) {
+                stale.insert(entity);
+            }
+        }
+
+        self.inner.refresh_from(&mut data.inner);
+        for entity in stale {
+            let measure = data.measures.get(entity).ok().map(|(_, m)| m);
+            self.place(entity, measure.as_deref());
+        }
+        self.last_refresh_tick = Some(this_run);
+    }
+
+    fn apply(&mut self, world: &World) {
+        self.inner.apply(world);
+        for entity in self.removals.read(world) {
+            self.unplace(entity);
+        }
+        for entity in self.measure_removals.read(world) {
+            self.unplace(entity);
+        }
+    }
+
+    fn entity_count(&self) -> Option<usize> {
+        self.inner.entity_count()
+    }
+
+    fn invalidate(&mut self) {
+        self.inner.invalidate();
+        self.groups.clear();
+        self.placed.clear();
+        self.last_refresh_tick = None;
+    }
+}
+
+#[doc(hidden)]
+#[derive(SystemParam)]
+pub struct GroupMaxStorageRefreshData<'w, 's, I: IndexInfo, M: Component + Ord + Clone> {
+    inner: HashmapStorageRefreshData<'w, 's, I>,
+    measures: Query<'w, 's, (Entity, Ref<'static, M>)>,
+    removed_measures: RemovedComponents<'w, 's, M>,
+}
+
+//======================================================================
+
+/// A component with a numeric amount that [`AggregateStorage`] sums up per group.
+pub trait Measure: Component {
+    /// The amount that this component contributes to its group.
+    fn measure(&self) -> f64;
+}
+
+/// [`IndexStorage`] implementation that works like [`HashmapStorage`], but treats each
+/// value as a group and keeps a running sum and count of the [`Measure`] component `M` of
+/// the entities in it, which [`Index::group_sum`][crate::index::Index::group_sum] and
+/// [`Index::group_avg`][crate::index::Index::group_avg] return.
+///
+/// Entities without an `M` component can still be looked up, but don't count towards their
+/// group. When an entity's measure or group changes, its old amount is subtracted and its
+/// new amount added, so refreshing costs O(1) per change instead of re-summing the group.
+/// Sums are kept as [`f64`], so measures with fractional amounts can drift slightly after
+/// many changes; whole amounts are exact up to 2<sup>53</sup>.
+///
+/// | Feature | `AggregateStorage` |
+/// |---|---|
+/// | Automatic refresh timing | When used; once per-system run |
+/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] |
+/// | Sees updates from earlier in the frame? | Yes |
+#[derive(Resource)]
+pub struct AggregateStorage<I: IndexInfo, M: Measure> {
+    inner: HashmapStorage<I>,
+    totals: HashMap<I::Value, (f64, usize)>,
+    placed: HashMap<Entity, (I::Value, f64)>,
+    last_refresh_tick: Option<Tick>,
+    removals: RemovalReader<I::Component>,
+    measure_removals: RemovalReader<M>,
+}
+
+impl<I: IndexInfo, M: Measure> Default for AggregateStorage<I, M> {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            totals: Default::default(),
+            placed: Default::default(),
+            last_refresh_tick: None,
+            removals: Default::default(),
+            measure_removals: Default::default(),
+        }
+    }
+}
+
+impl<I: IndexInfo, M: Measure> AggregateStorage<I, M> {
+    /// Get the sum of the measures in `group`, and how many entities they are from.
+    pub(crate) fn group_total(&self, group: &I::Value) -> (f64, usize) {
+        self.totals.get(group).copied().unwrap_or((0.0, 0))
+    }
+
+    fn place(&mut self, entity: Entity, measure: Option<&M>) {
+        self.unplace(entity);
+        let (Some(group), Some(measure)) = (self.inner.value_of(entity), measure) else {
+            return;
+        };
+        let amount = measure.measure();
+        let (sum, count) = self.totals.entry(group.clone()).or_default();
+        *sum += amount;
+        *count += 1;
+        self.placed.insert(entity, (group.clone(), amount));
+    }
+
+    fn unplace(&mut self, entity: Entity) {
+        let Some((group, amount)) = self.placed.remove(&entity) else {
+            return;
+        };
+        let (sum, count) = self
+            .totals
+            .get_mut(&group)
+            .expect("unplace: Cached group was not present in totals map!");
+        *sum -= amount;
+        *count -= 1;
+        if *count == 0 {
+            self.totals.remove(&group);
+        }
+    }
+}
+
+impl<I: IndexInfo, M: Measure> IndexStorage<I> for AggregateStorage<I, M> {
+    type RefreshData<'w, 's> = AggregateStorageRefreshData<'w, 's, I, M>;
+
+    fn lookup<'w, 's>(
+        &mut self,
+        val: &I::Value,
+        _data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
+    ) -> HashSet<Entity> {
+        self.inner.map.get(val)
+    }
+
+    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
+        let data = &mut **data;
+        let this_run = data.inner.ticks.this_run();
+
+        let mut stale = HashSet::new();
+        for entity in data.inner.read_removals() {
+            self.inner.unindex(entity, this_run);
+            stale.insert(entity);
+        }
+        stale.extend(data.removed_measures.read());
+        for (entity, component) in &data.inner.components {
+            if changed_since_refresh::<I>(&component, self.last_refresh_tick, this_run) {
+                stale.insert(entity);
+            }
+        }
+        for (entity, measure) in &data.measures {
+            if tick_since_refresh::<I>(measure.last_changed(), self.last_refresh_tick, this_run) {
+                stale.insert(entity);
+            }
+        }
+
+        self.inner.refresh_from(&mut data.inner);
+        for entity in stale {
+            let measure = data.measures.get(entity).ok().map(|(_, m)| m);
+            self.place(entity, measure.as_deref());
+        }
+        self.last_refresh_tick = Some(this_run);
+    }
+
+    fn apply(&mut self, world: &World) {
+        self.inner.apply(world);
+        for entity in self.removals.read(world) {
+            self.unplace(entity);
+        }
+        for entity in self.measure_removals.read(world) {
+            self.unplace(entity);
+        }
+    }
+
+    fn entity_count(&self) -> Option<usize> {
+        self.inner.entity_count()
+    }
+
+    fn invalidate(&mut self) {
+        self.inner.invalidate();
+        self.totals.clear();
+        self.placed.clear();
+        self.last_refresh_tick = None;
+    }
+}
+
+#[doc(hidden)]
+#[derive(SystemParam)]
+pub struct AggregateStorageRefreshData<'w, 's, I: IndexInfo, M: Measure> {
+    inner: HashmapStorageRefreshData<'w, 's, I>,
+    measures: Query<'w, 's, (Entity, Ref<'static, M>)>,
+    removed_measures: RemovedComponents<'w, 's, M>,
+}
+
+//======================================================================
+
+/// [`IndexStorage`] implementation that works like [`HashmapStorage`], but leaves out
+/// entities that have the marker component `D`, e.g. entities that are disabled or hidden.
+///
+/// Adding `D` to an entity removes it from the index at the next refresh, and removing `D`
+/// indexes it again with its current value.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// #[derive(Component)]
+/// struct Disabled;
+///
+/// #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// struct Team(u32);
+///
+/// struct ActiveTeam;
+/// impl IndexInfo for ActiveTeam {
+///     type Component = Team;
+///     type Value = Team;
+///     type Storage = SkipDisabledStorage<Self, Disabled>;
+///     type ValueParam = ();
+///
+///     fn value(c: &Team, _: &()) -> Team {
+///         c.clone()
+///     }
+/// }
+/// ```
+///
+/// On top of the cost of a [`HashmapStorage`], each refresh checks each changed component
+/// for `D`, and visits the entities that `D` was added to or removed from.
+///
+/// | Feature | `SkipDisabledStorage` |
+/// |---|---|
+/// | Automatic refresh timing | When used; once per-system run |
+/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] |
+/// | Sees updates from earlier in the frame? | Yes |
+#[derive(Resource)]
+pub struct SkipDisabledStorage<I: IndexInfo, D: Component> {
+    pub(crate) inner: HashmapStorage<I>,
+    phantom: PhantomData<fn() -> D>,
+}
+
+impl<I: IndexInfo, D: Component> Default for SkipDisabledStorage<I, D> {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<I: IndexInfo, D: Component> IndexStorage<I> for SkipDisabledStorage<I, D> {
+    type RefreshData<'w, 's> = SkipDisabledStorageRefreshData<'w, 's, I, D>;
+
+    fn lookup<'w, 's>(
+        &mut self,
+        val: &I::Value,
+        _data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
+    ) -> HashSet<Entity> {
+        self.inner.map.get(val)
+    }
+
+    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
+        let data = &mut **data;
+        let this_run = data.inner.ticks.this_run();
+
+        let disabled = &data.disabled;
+        self.inner
+            .refresh_skipping(&mut data.inner, |entity| disabled.contains(entity));
+        // Entities that were disabled earlier have already been dropped, and the refresh
+        // above doesn't index them again.
+        for entity in &data.newly_disabled {
+            self.inner.unindex(entity, this_run);
+        }
+        for entity in data.enabled.read() {
+            if data.disabled.contains(entity) {
+                continue;
+            }
+            if let Ok((_, component)) = data.inner.components.get(entity) {
+                match indexed_value::<I>(entity, &component, &data.inner.value_param) {
+                    Some(value) => self.inner.index_value(&value, entity, this_run),
+                    None => self.inner.unindex(entity, this_run),
+                };
+            }
+        }
+    }
+
+    fn apply(&mut self, world: &World) {
+        self.inner.apply(world);
+    }
+
+    fn entity_count(&self) -> Option<usize> {
+        self.inner.entity_count()
+    }
+
+    fn invalidate(&mut self) {
+        self.inner.invalidate();
+    }
+}
+
+#[doc(hidden)]
+#[derive(SystemParam)]
+pub struct SkipDisabledStorageRefreshData<'w, 's, I: IndexInfo, D: Component> {
+    inner: HashmapStorageRefreshData<'w, 's, I>,
+    disabled: Query<'w, 's, (), (With<D>, With<<I as IndexInfo>::Component>)>,
+    newly_disabled: Query<'w, 's, Entity, (Added<D>, With<<I as IndexInfo>::Component>)>,
+    enabled: RemovedComponents<'w, 's, D>,
+}
+
+//======================================================================
+
+/// [`IndexStorage`] implementation that works like [`HashmapStorage`], but only indexes
+/// entities that match the query filter `F`, e.g. `With<Player>`.
+///
+/// Refreshes only visit the archetypes that match `F`, so an index over a few of many
+/// archetypes with the component doesn't pay for walking the rest. Entities that stop
+/// matching `F` are dropped at the next refresh, and entities that start matching it are
+/// indexed with their current value.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// #[derive(Component)]
+/// struct Player;
+///
+/// #[derive(Component, Clone, PartialEq, Eq, Hash)]
+/// struct Team(u32);
+///
+/// struct PlayerTeam;
+/// impl IndexInfo for PlayerTeam {
+///     type Component = Team;
+///     type Value = Team;
+///     type Storage = FilteredStorage<Self, With<Player>>;
+///     type ValueParam = ();
+///
+///     fn value(c: &Team, _: &()) -> Team {
+///         c.clone()
+///     }
+/// }
+/// ```
+///
+/// Unlike a [`HashmapStorage`], each refresh looks up every unchanged matching entity to
+/// find the ones that just started matching, and never rebuilds the map at once.
+///
+/// | Feature | `FilteredStorage` |
+/// |---|---|
+/// | Automatic refresh timing | When used; once per-system run |
+/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] |
+/// | Sees updates from earlier in the frame? | Yes |
+#[derive(Resource)]
+pub struct FilteredStorage<I: IndexInfo, F: ReadOnlyWorldQuery + 'static> {
+    inner: HashmapStorage<I>,
+    phantom: PhantomData<fn() -> F>,
+}
+
+impl<I: IndexInfo, F: ReadOnlyWorldQuery + 'static> Default for FilteredStorage<I, F> {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<I: IndexInfo, F: ReadOnlyWorldQuery + 'static> IndexStorage<I> for FilteredStorage<I, F> {
+    type RefreshData<'w, 's> = FilteredStorageRefreshData<'w, 's, I, F>;
+
+    fn lookup<'w, 's>(
+        &mut self,
+        val: &I::Value,
+        _data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
+    ) -> HashSet<Entity> {
+        self.inner.map.get(val)
+    }
+
+    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
+        let data = &mut **data;
+        let inner = &mut self.inner;
+        let this_run = data.inner.ticks.this_run();
+        inner.transitions.begin(data.inner.frame.get());
+        inner.check_change_ticks(this_run);
+        if std::mem::take(&mut inner.dirty) {
+            inner.last_refresh_tick = None;
+        }
+        for entity in data.inner.read_removals() {
+            inner.unindex(entity, this_run);
+        }
+
+        let mut indexed = 0;
+        for (entity, component) in &data.matching {
+            let changed = changed_since_refresh::<I>(&component, inner.last_refresh_tick, this_run);
+            // Unchanged entities that aren't indexed may have only just started matching.
+            if changed || inner.value_of(entity).is_none() {
+                match indexed_value::<I>(entity, &component, &data.inner.value_param) {
+                    Some(value) => inner.index_value(&value, entity, this_run),
+                    None => inner.unindex(entity, this_run),
+                };
+            }
+            if inner.value_of(entity).is_some() {
+                indexed += 1;
+            }
+        }
+        // Anything else in the index has stopped matching since it was indexed.
+        if inner.map.len() > indexed {
+            let stale: Vec<Entity> = inner
+                .map
+                .iter_rev()
+                .map(|(entity, _)| *entity)
+                .filter(|entity| !data.matching.contains(*entity))
+                .collect();
+            for entity in stale {
+                inner.unindex(entity, this_run);
+            }
+        }
+        inner.last_refresh_tick = Some(this_run);
+    }
+
+    fn apply(&mut self, world: &World) {
+        self.inner.apply(world);
+    }
+
+    fn entity_count(&self) -> Option<usize> {
+        self.inner.entity_count()
+    }
+
+    fn invalidate(&mut self) {
+        self.inner.invalidate();
+    }
+}
+
+#[doc(hidden)]
+#[derive(SystemParam)]
+pub struct FilteredStorageRefreshData<'w, 's, I: IndexInfo, F: ReadOnlyWorldQuery + 'static> {
+    inner: HashmapStorageRefreshData<'w, 's, I>,
+    matching: Query<'w, 's, (Entity, Ref<'static, <I as IndexInfo>::Component>), F>,
+}
+
+//======================================================================
+
+/// [`IndexStorage`] implementation that keeps values in sorted order, allowing lookups
+/// of all entities with values in a range.
+///
+/// To use floats as ordered values, wrap them in [`TotalF32`][crate::values::TotalF32].
+///
+/// | Feature | `OrderedStorage` |
+/// |---|---|
+/// | Automatic refresh timing | When used; once per-system run |
+/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] |
+/// | Sees updates from earlier in the frame? | Yes |
+#[derive(Resource)]
+pub struct OrderedStorage<I: IndexInfo> {
+    map: BTreeMap<I::Value, HashSet<Entity>>,
+    rev_map: HashMap<Entity, I::Value>,
+    last_refresh_tick: Option<Tick>,
+    removals: RemovalReader<I::Component>,
+    dirty: bool,
+}
+
+impl<I: IndexInfo> Default for OrderedStorage<I> {
+    fn default() -> Self {
+        Self {
+            map: Default::default(),
+            rev_map: Default::default(),
+            last_refresh_tick: None,
+            removals: Default::default(),
+            dirty: false,
+        }
+    }
+}
+
+impl<I: IndexInfo> OrderedStorage<I>
+where
+    I::Value: Ord,
+{
+    fn insert(&mut self, entity: Entity, value: I::Value) {
+        if self.rev_map.get(&entity) == Some(&value) {
+            return;
+        }
+        self.remove(entity);
+        self.map.entry(value.clone()).or_default().insert(entity);
+        self.rev_map.insert(entity, value);
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some(old) = self.rev_map.remove(&entity) {
+            let set = self
+                .map
+                .get_mut(&old)
+                .expect("remove: Cached key from rev_map was not present in forward map!");
+            set.remove(&entity);
+            if set.is_empty() {
+                self.map.remove(&old);
+            }
+        }
+    }
+
+    /// Iterate over the non-empty buckets with values in `range`, in order.
+    ///
+    /// Ranges that end before they start are empty, instead of panicking like
+    /// [`BTreeMap::range`].
+    pub(crate) fn range(
+        &self,
+        range: impl RangeBounds<I::Value>,
+    ) -> impl Iterator<Item = (&I::Value, &HashSet<Entity>)> {
+        let reversed = match (range.start_bound(), range.end_bound()) {
+            (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+            (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end))
+            | (Bound::Included(start), Bound::Excluded(end)) => start > end,
+            _ => false,
+        };
+        (!reversed)
+            .then(|| self.map.range(range))
+            .into_iter()
+            .flatten()
+    }
+}
+
+impl<I: IndexInfo> IndexStorage<I> for OrderedStorage<I>
+where
+    I::Value: Ord,
+{
+    type RefreshData<'w, 's> = HashmapStorageRefreshData<'w, 's, I>;
+
+    fn lookup<'w, 's>(
+        &mut self,
+        val: &I::Value,
+        _data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
+    ) -> HashSet<Entity> {
+        self.map.get(val).cloned().unwrap_or_default()
+    }
+
+    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
+        if std::mem::take(&mut self.dirty) {
+            // Re-check every component, and drop entities that don't have one any more.
+            self.last_refresh_tick = None;
+            let stale: Vec<Entity> = self
+                .rev_map
+                .keys()
+                .copied()
+                .filter(|entity| !data.components.contains(*entity))
+                .collect();
+            for entity in stale {
+                self.remove(entity);
+            }
+        }
+        for entity in data.read_removals() {
+            self.remove(entity);
+        }
+        for (entity, component) in &data.components {
+            if changed_since_refresh::<I>(&component, self.last_refresh_tick, data.ticks.this_run())
+            {
+                match indexed_value::<I>(entity, &component, &data.value_param) {
+                    Some(value) => self.insert(entity, value),
+                    None => self.remove(entity),
+                }
+            }
+        }
+        self.last_refresh_tick = Some(data.ticks.this_run());
+    }
+
+    fn apply(&mut self, world: &World) {
+        for entity in self.removals.read(world) {
+            self.remove(entity);
+        }
+    }
+
+    fn entity_count(&self) -> Option<usize> {
+        Some(self.rev_map.len())
+    }
+
+    fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+}
+
+//======================================================================
+
+/// [`IndexStorage`] implementation for components that are present on very few entities.
+///
+/// Unlike [`HashmapStorage`], this does not keep a reverse mapping from entities to values,
+/// and it shrinks its allocation to fit once removals leave it more than half empty. In exchange, changing
+/// or removing an entity's value requires checking every distinct value in the index,
+/// so this should only be used when the number of distinct values is small.
+///
+/// | Feature | `SparseStorage` |
+/// |---|---|
+/// | Automatic refresh timing | When used; once per-system run |
+/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] |
+/// | Sees updates from earlier in the frame? | Yes |
+#[derive(Resource)]
+pub struct SparseStorage<I: IndexInfo> {
+    map: HashMap<I::Value, HashSet<Entity>>,
+    last_refresh_tick: Option<Tick>,
+    removals: RemovalReader<I::Component>,
+    dirty: bool,
+}
+
+impl<I: IndexInfo> Default for SparseStorage<I> {
+    fn default() -> Self {
+        Self {
+            map: Default::default(),
+            last_refresh_tick: None,
+            removals: Default::default(),
+            dirty: false,
+        }
+    }
+}
+
+impl<I: IndexInfo> SparseStorage<I> {
+    // Returns whether the entity was present.
+    fn remove_entity(&mut self, entity: Entity) -> bool {
+        let mut removed = false;
+        self.map.retain(|_, set| {
+            removed |= set.remove(&entity);
+            !set.is_empty()
+        });
+        removed
+    }
+
+    // Shrinking reallocates, so only shrink the map and buckets that are mostly unused.
+    fn shrink_if_oversized(&mut self) {
+        if self.map.capacity() > 2 * self.map.len() {
+            self.map.shrink_to_fit();
+        }
+        for set in self.map.values_mut() {
+            if set.capacity() > 2 * set.len() {
+                set.shrink_to_fit();
+            }
+        }
+    }
+}
+
+impl<I: IndexInfo> IndexStorage<I> for SparseStorage<I> {
+    type RefreshData<'w, 's> = HashmapStorageRefreshData<'w, 's, I>;
+
+    fn lookup<'w, 's>(
+        &mut self,
+        val: &I::Value,
+        _data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
+    ) -> HashSet<Entity> {
+        self.map.get(val).cloned().unwrap_or_default()
+    }
+
+    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
+        let mut removed = false;
+        if std::mem::take(&mut self.dirty) {
+            // Re-check every component, and drop entities that don't have one any more.
+            self.last_refresh_tick = None;
+            let components = &data.components;
+            self.map.retain(|_, set| {
+                let len = set.len();
+                set.retain(|entity| components.contains(*entity));
+                removed |= set.len() != len;
+                !set.is_empty()
+            });
+        }
+        for entity in data.read_removals() {
+            removed |= self.remove_entity(entity);
+        }
+        for (entity, component) in &data.components {
+            if changed_since_refresh::<I>(&component, self.last_refresh_tick, data.ticks.this_run())
+            {
+                removed |= self.remove_entity(entity);
+                if let Some(value) = indexed_value::<I>(entity, &component, &data.value_param) {
+                    self.map.entry(value).or_default().insert(entity);
+                }
+            }
+        }
+        if removed {
+            self.shrink_if_oversized();
+        }
+        self.last_refresh_tick = Some(data.ticks.this_run());
+    }
+
+    fn apply(&mut self, world: &World) {
+        let mut removed = false;
+        for entity in self.removals.read(world) {
+            removed |= self.remove_entity(entity);
+        }
+        if removed {
+            self.shrink_if_oversized();
+        }
+    }
+
+    fn entity_count(&self) -> Option<usize> {
+        Some(self.map.values().map(HashSet::len).sum())
+    }
+
+    fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+}
+
+//======================================================================
+
+/// [`IndexStorage`] implementation that works like [`HashmapStorage`], but interns values
+/// so that each distinct value is only stored once.
+///
+/// [`HashmapStorage`] keeps a copy of an entity's value for every entity, so that it can
+/// find the entity's bucket when it changes or is removed. For large values shared by many
+/// entities, like `String`s or `Vec`s, these copies can take up far more memory than the
+/// entities themselves. This storage instead keeps each value in an [`Arc`] that is shared
+/// by its bucket and every entity in it, at the cost of a reference count update whenever
+/// an entity moves.
+///
+/// | Feature | `InternedStorage` |
+/// |---|---|
+/// | Automatic refresh timing | When used; once per-system run |
+/// | Sees updates from earlier in the system? | After manually calling [`refresh`][Self::refresh] |
+/// | Sees updates from earlier in the frame? | Yes |
+#[derive(Resource)]
+pub struct InternedStorage<I: IndexInfo> {
+    map: UniqueMultiMap<Arc<I::Value>, Entity>,
+    last_refresh_tick: Option<Tick>,
+    removals: RemovalReader<I::Component>,
+    dirty: bool,
+}
+
+impl<I: IndexInfo> Default for InternedStorage<I> {
+    fn default() -> Self {
+        Self {
+            map: Default::default(),
+            last_refresh_tick: None,
+            removals: Default::default(),
+            dirty: false,
+        }
+    }
+}
+
+impl<I: IndexInfo> InternedStorage<I> {
+    /// Index `entity` under `value`, reusing the interned copy of the value if there is one.
+    fn insert(&mut self, value: I::Value, entity: Entity) {
+        let value = match self.map.get_key_value(&value) {
+            Some((interned, _)) => interned.clone(),
+            None => Arc::new(value),
+        };
+        self.map.insert(&value, &entity);
+    }
+
+    /// Get the interned value that `entity` is currently indexed under.
+    pub(crate) fn value_of(&self, entity: Entity) -> Option<&Arc<I::Value>> {
+        self.map.key_of(&entity)
+    }
+}
+
+impl<I: IndexInfo> IndexStorage<I> for InternedStorage<I> {
+    type RefreshData<'w, 's> = HashmapStorageRefreshData<'w, 's, I>;
+
+    fn lookup<'w, 's>(
+        &mut self,
+        val: &I::Value,
+        _data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
+    ) -> HashSet<Entity> {
+        self.map
+            .get_key_value(val)
+            .map(|(_, set)| set.clone())
+            .unwrap_or_default()
+    }
+
+    fn refresh<'w, 's>(&mut self, data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>) {
+        if std::mem::take(&mut self.dirty) {
+            // Re-check every component, and drop entities that don't have one any more.
+            self.last_refresh_tick = None;
+            let stale: Vec<Entity> = self
+                .map
+                .iter_rev()
+                .map(|(entity, _)| *entity)
+                .filter(|entity| !data.components.contains(*entity))
+                .collect();
+            for entity in stale {
+                self.map.remove(&entity);
+            }
+        }
+        for entity in data.read_removals() {
+            self.map.remove(&entity);
+        }
+        for (entity, component) in &data.components {
+            if changed_since_refresh::<I>(&component, self.last_refresh_tick, data.ticks.this_run())
+            {
+                match indexed_value::<I>(entity, &component, &data.value_param) {
+                    Some(value) => self.insert(value, entity),
+                    None => {
+                        self.map.remove(&entity);
+                    }
+                };
+            }
+        }
+        self.last_refresh_tick = Some(data.ticks.this_run());
+    }
+
+    fn apply(&mut self, world: &World) {
+        for entity in self.removals.read(world) {
+            self.map.remove(&entity);
+        }
+    }
+
+    fn entity_count(&self) -> Option<usize> {
+        Some(self.map.len())
+    }
+
+    fn invalidate(&mut self) {
+        self.dirty = true;
+    }
 }
 
 //======================================================================
@@ -129,15 +2570,22 @@ impl<I: IndexInfo> Default for NoStorage<I> {
 }
 
 impl<I: IndexInfo> IndexStorage<I> for NoStorage<I> {
-    type RefreshData<'w, 's> = Query<'w, 's, (Entity, &'static I::Component)>;
+    type RefreshData<'w, 's> = (
+        Query<'w, 's, (Entity, &'static I::Component)>,
+        StaticSystemParam<'w, 's, I::ValueParam>,
+    );
 
     fn lookup<'w, 's>(
         &mut self,
         val: &I::Value,
         data: &mut StaticSystemParam<Self::RefreshData<'w, 's>>,
     ) -> HashSet<Entity> {
-        data.iter()
-            .filter_map(|(e, c)| if I::value(c) == *val { Some(e) } else { None })
+        let (components, param) = &**data;
+        components
+            .iter()
+            .filter_map(|(e, c)| {
+                (indexed_value::<I>(e, c, param).as_ref() == Some(val)).then_some(e)
+            })
             .collect()
     }
 
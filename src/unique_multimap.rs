@@ -1,39 +1,145 @@
-use bevy::utils::{HashMap, HashSet};
-use std::hash::Hash;
+use crate::storage::Bucket;
+use bevy::utils::{FixedState, HashMap, HashSet};
+use smallvec::SmallVec;
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
 
 /// Map where a key can have multiple values, but a value can only exist for one key at a time.
 /// Re-inserting a value is a no-op if it already exists under the same key, otherwise the value is
 /// removed from under it's present key and added under the new key.
-
-pub struct UniqueMultiMap<K, V> {
-    map: HashMap<K, HashSet<V>>,
+///
+/// Keys never have empty sets of values: when the last value is removed or moved away from a
+/// key, the key is removed too, so iterating never reports keys without values.
+pub struct UniqueMultiMap<K, V, B = HashSet<V>> {
+    map: HashMap<K, B>,
     rev_map: HashMap<V, K>,
+    // Keys by their hash with `FixedState`, built by the first `get_by_hash` and kept up to
+    // date from then on.
+    by_hash: Option<HashMap<u64, SmallVec<[K; 1]>>>,
 }
 
-impl<K, V> Default for UniqueMultiMap<K, V> {
+impl<K, V, B> Default for UniqueMultiMap<K, V, B> {
     fn default() -> Self {
         Self {
             map: Default::default(),
             rev_map: Default::default(),
+            by_hash: None,
         }
     }
 }
 
-impl<K, V> UniqueMultiMap<K, V>
+impl<K, V: 'static, B> UniqueMultiMap<K, V, B>
 where
     K: Hash + Eq + Clone,
     V: Hash + Eq + Clone,
+    B: Bucket<V>,
 {
+    /// Copy the values stored under `k` to a [`HashSet`], which is empty if there are none.
     pub fn get(&self, k: &K) -> HashSet<V> {
         self.map
             .get(k)
-            .map(|set| set.clone())
-            .unwrap_or_else(|| HashSet::new())
+            .map(|set| set.to_hash_set())
+            .unwrap_or_default()
+    }
+
+    /// Get the values stored under `k`, if there are any.
+    pub fn get_ref(&self, k: &K) -> Option<&B> {
+        self.map.get(k)
+    }
+
+    /// Get the stored key equal to `k` and its values, looking it up by anything the key can
+    /// be borrowed as.
+    pub fn get_key_value<Q>(&self, k: &Q) -> Option<(&K, &B)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get_key_value(k)
+    }
+
+    /// Copy the values stored under every key whose hash with [`FixedState`] is `hash`.
+    ///
+    /// The first call groups every key by its hash, and later insertions and removals keep
+    /// the groups up to date, so that each call only hashes the keys that share `hash`.
+    pub(crate) fn get_by_hash(&mut self, hash: u64) -> HashSet<V> {
+        let map = &self.map;
+        let by_hash = self.by_hash.get_or_insert_with(|| {
+            let mut by_hash = HashMap::<u64, SmallVec<[K; 1]>>::default();
+            for k in map.keys() {
+                by_hash
+                    .entry(FixedState.hash_one(k))
+                    .or_default()
+                    .push(k.clone());
+            }
+            by_hash
+        });
+        by_hash
+            .get(&hash)
+            .into_iter()
+            .flatten()
+            .flat_map(|k| map[k].iter().cloned())
+            .collect()
+    }
+
+    /// Get the key that `v` is currently stored under.
+    pub fn key_of(&self, v: &V) -> Option<&K> {
+        self.rev_map.get(v)
+    }
+
+    /// Iterate over all values and the key each is stored under.
+    pub fn iter_rev(&self) -> impl Iterator<Item = (&V, &K)> {
+        self.rev_map.iter()
+    }
+
+    /// Iterate over all keys and their (non-empty) sets of values.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &B)> {
+        self.map.iter()
+    }
+
+    /// Remove a key and all of its values.
+    pub(crate) fn remove_key(&mut self, k: &K) -> Option<B> {
+        let set = self.map.remove(k)?;
+        for v in set.iter() {
+            self.rev_map.remove(v);
+        }
+        self.unhash_key(k);
+        Some(set)
+    }
+
+    /// The number of values across all keys.
+    pub fn len(&self) -> usize {
+        self.rev_map.len()
+    }
+
+    /// Whether there are no values under any key.
+    pub fn is_empty(&self) -> bool {
+        self.rev_map.is_empty()
+    }
+
+    /// Reserve space for at least `additional` more values.
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.rev_map.reserve(additional);
+    }
+
+    /// The number of distinct keys.
+    pub fn key_count(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Remove every entry, returning them as (key, value) pairs.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = (K, V)> {
+        self.rev_map.clear();
+        self.by_hash = None;
+        std::mem::take(&mut self.map)
+            .into_iter()
+            .flat_map(|(k, set)| set.into_iter().map(move |v| (k.clone(), v)))
     }
 
     /// Returns value's old key
     // Todo: rely a little less on clone
-    pub fn insert(&mut self, new_k: &K, v: &V) -> Option<K> {
+    pub(crate) fn insert(&mut self, new_k: &K, v: &V) -> Option<K> {
         let maybe_old_k = self.rev_map.insert(v.clone(), new_k.clone());
 
         if let Some(old_k) = &maybe_old_k {
@@ -46,59 +152,165 @@ where
             self.purge_from_forward(old_k, v, "insert");
         }
         // insert new value
-        self.map.get_mut_or_insert_default(new_k).insert(v.clone());
+        match self.map.get_mut(new_k) {
+            Some(set) => set.insert(v.clone()),
+            None => {
+                let mut set = B::default();
+                set.insert(v.clone());
+                self.map.insert(new_k.clone(), set);
+                if let Some(by_hash) = &mut self.by_hash {
+                    by_hash
+                        .entry(FixedState.hash_one(new_k))
+                        .or_default()
+                        .push(new_k.clone());
+                }
+            }
+        }
 
         maybe_old_k
     }
 
     /// Returns value's old key
-    pub fn remove(&mut self, v: &V) -> Option<K> {
+    pub(crate) fn remove(&mut self, v: &V) -> Option<K> {
         let maybe_old_k = self.rev_map.remove(v);
 
         if let Some(old_k) = &maybe_old_k {
             self.purge_from_forward(old_k, v, "remove");
         }
 
-        return maybe_old_k;
+        maybe_old_k
     }
 
     // Removes v from k's set, removing the set completely if it would be empty
     // Panics if k is not in the forward map.
     fn purge_from_forward(&mut self, k: &K, v: &V, fn_name: &str) {
-        let old_set = self.map.get_mut(k).expect(&format!(
-            "{}: Cached key from rev_map was not present in forward map!",
-            fn_name,
-        ));
+        let old_set = self.map.get_mut(k).unwrap_or_else(|| {
+            panic!(
+                "{}: Cached key from rev_map was not present in forward map!",
+                fn_name,
+            )
+        });
         match old_set.len() {
             1 => {
                 self.map.remove(k);
+                self.unhash_key(k);
             }
             _ => {
                 old_set.remove(v);
             }
         }
     }
+
+    // Drop a removed key from the hash groups, if they have been built.
+    fn unhash_key(&mut self, k: &K) {
+        let Some(by_hash) = &mut self.by_hash else {
+            return;
+        };
+        let hash = FixedState.hash_one(k);
+        if let Some(keys) = by_hash.get_mut(&hash) {
+            keys.retain(|key| key != k);
+            if keys.is_empty() {
+                by_hash.remove(&hash);
+            }
+        }
+    }
 }
 
-trait HashMapExt<K, V> {
-    fn get_or_insert_default(&mut self, k: &K) -> &V;
-    fn get_mut_or_insert_default(&mut self, k: &K) -> &mut V;
+impl<K, V: 'static, B> UniqueMultiMap<K, V, B>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+    B: Bucket<V>,
+{
+    /// Format every key and its values, in no particular order.
+    pub(crate) fn debug_buckets(&self) -> impl fmt::Debug + '_ {
+        DebugBuckets(&self.map, PhantomData)
+    }
 }
 
-impl<K: Eq + Hash + Clone, V: Default> HashMapExt<K, V> for HashMap<K, V> {
-    fn get_or_insert_default(&mut self, k: &K) -> &V {
-        if !self.contains_key(k) {
-            self.insert(k.clone(), V::default());
+/// Prints the number of keys and values, and with `{:#?}`, every key and its values.
+impl<K, V: 'static, B> fmt::Debug for UniqueMultiMap<K, V, B>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+    B: Bucket<V>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verbose = f.alternate();
+        let mut s = f.debug_struct("UniqueMultiMap");
+        s.field("keys", &self.map.len())
+            .field("values", &self.rev_map.len());
+        if verbose {
+            s.field("map", &self.debug_buckets());
         }
-        // We just inserted a value if one wasn't there, so unwrap is ok
-        self.get(k).unwrap()
+        s.finish()
     }
+}
+
+struct DebugBuckets<'a, K, V, B>(&'a HashMap<K, B>, PhantomData<fn() -> V>);
 
-    fn get_mut_or_insert_default(&mut self, k: &K) -> &mut V {
-        if !self.contains_key(k) {
-            self.insert(k.clone(), V::default());
+impl<K: fmt::Debug, V: fmt::Debug + 'static, B: Bucket<V>> fmt::Debug
+    for DebugBuckets<'_, K, V, B>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        for (k, set) in self.0 {
+            map.key(k);
+            map.value(&DebugBucket(set, PhantomData));
         }
-        // We just inserted a value if one wasn't there, so unwrap is ok
-        self.get_mut(k).unwrap()
+        map.finish()
+    }
+}
+
+struct DebugBucket<'a, V, B>(&'a B, PhantomData<fn() -> V>);
+
+impl<V: fmt::Debug + 'static, B: Bucket<V>> fmt::Debug for DebugBucket<'_, V, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UniqueMultiMap;
+
+    #[test]
+    fn test_empty_keys_are_removed() {
+        let mut map = UniqueMultiMap::<u32, u32>::default();
+        map.insert(&1, &10);
+        map.insert(&1, &11);
+        map.insert(&2, &20);
+
+        // Moving the last value away from a key removes it.
+        map.insert(&3, &20);
+        assert!(map.get_ref(&2).is_none());
+
+        map.remove(&10);
+        assert_eq!(map.key_count(), 2);
+        map.remove(&11);
+        assert!(map.get_ref(&1).is_none());
+
+        let keys: Vec<_> = map.iter().map(|(k, _)| *k).collect();
+        assert_eq!(keys, vec![3]);
+        assert!(map.iter().all(|(_, set)| !set.is_empty()));
+    }
+
+    #[test]
+    fn test_debug() {
+        let mut map = UniqueMultiMap::<u32, u32>::default();
+        map.insert(&1, &10);
+        map.insert(&1, &11);
+        map.insert(&2, &20);
+        assert_eq!(
+            format!("{:?}", map),
+            "UniqueMultiMap { keys: 2, values: 3 }"
+        );
+
+        map.remove(&10);
+        map.remove(&11);
+        assert_eq!(
+            format!("{:#?}", map),
+            "UniqueMultiMap {\n    keys: 1,\n    values: 1,\n    map: {\n        2: {\n            20,\n        },\n    },\n}"
+        );
     }
 }
@@ -0,0 +1,220 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+
+/// An [`f32`] that implements [`Eq`], [`Ord`], and [`Hash`] so that it can be used as an
+/// index value.
+///
+/// Values are compared using [`f32::total_cmp`], so `-0.0` and `0.0` are different values,
+/// and `NaN`s are equal to themselves.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TotalF32(pub f32);
+
+impl PartialEq for TotalF32 {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TotalF32 {}
+
+impl PartialOrd for TotalF32 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TotalF32 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for TotalF32 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state)
+    }
+}
+
+impl From<f32> for TotalF32 {
+    fn from(f: f32) -> Self {
+        Self(f)
+    }
+}
+
+/// Defines how a [`KeyWrapper`] compares and hashes the value it wraps.
+///
+/// Values that are equal must hash the same.
+pub trait KeyEquivalence<V: ?Sized>: 'static {
+    /// Whether `a` and `b` should be indexed under the same key.
+    fn eq(a: &V, b: &V) -> bool;
+
+    /// Hash `v` consistently with [`eq`][Self::eq].
+    fn hash<H: Hasher>(v: &V, state: &mut H);
+}
+
+/// A value that the index compares and hashes with `E` instead of its own [`Eq`] and
+/// [`Hash`], e.g. to look up strings without regard to case.
+///
+/// This keeps custom equality inside the index, without changing how the value or
+/// component compare anywhere else. Wrap the value in [`IndexInfo::value`], and wrap the
+/// values you look up in the same way:
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_index::prelude::*;
+/// struct NameIgnoringCase;
+///
+/// impl IndexInfo for NameIgnoringCase {
+///     type Component = Name;
+///     type Value = KeyWrapper<String, CaseInsensitive>;
+///     type Storage = HashmapStorage<Self>;
+///     type ValueParam = ();
+///
+///     fn value(name: &Name, _: &()) -> Self::Value {
+///         KeyWrapper::new(name.to_string())
+///     }
+/// }
+///
+/// fn find_bob(mut idx: Index<NameIgnoringCase>) {
+///     // Finds "Bob", "BOB", "bob", etc.
+///     let bobs = idx.lookup(&KeyWrapper::new("bob".to_string()));
+/// }
+/// ```
+///
+/// [`IndexInfo::value`]: crate::index::IndexInfo::value
+pub struct KeyWrapper<V, E> {
+    value: V,
+    equivalence: PhantomData<fn() -> E>,
+}
+
+impl<V, E> KeyWrapper<V, E> {
+    /// Wrap `value`.
+    pub fn new(value: V) -> Self {
+        Self {
+            value,
+            equivalence: PhantomData,
+        }
+    }
+
+    /// Get the wrapped value, which may differ from other values that are equal to it under
+    /// `E`, e.g. by case.
+    pub fn get(&self) -> &V {
+        &self.value
+    }
+
+    /// Unwrap the value.
+    pub fn into_inner(self) -> V {
+        self.value
+    }
+}
+
+impl<V: Clone, E> Clone for KeyWrapper<V, E> {
+    fn clone(&self) -> Self {
+        Self::new(self.value.clone())
+    }
+}
+
+impl<V: fmt::Debug, E> fmt::Debug for KeyWrapper<V, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<V, E: KeyEquivalence<V>> PartialEq for KeyWrapper<V, E> {
+    fn eq(&self, other: &Self) -> bool {
+        E::eq(&self.value, &other.value)
+    }
+}
+
+impl<V, E: KeyEquivalence<V>> Eq for KeyWrapper<V, E> {}
+
+impl<V, E: KeyEquivalence<V>> Hash for KeyWrapper<V, E> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        E::hash(&self.value, state)
+    }
+}
+
+impl<V, E> From<V> for KeyWrapper<V, E> {
+    fn from(value: V) -> Self {
+        Self::new(value)
+    }
+}
+
+/// [`KeyEquivalence`] for strings that ignores case, using Unicode lowercase mappings.
+pub struct CaseInsensitive;
+
+impl<V: AsRef<str> + ?Sized> KeyEquivalence<V> for CaseInsensitive {
+    fn eq(a: &V, b: &V) -> bool {
+        lowercase(a.as_ref()).eq(lowercase(b.as_ref()))
+    }
+
+    fn hash<H: Hasher>(v: &V, state: &mut H) {
+        for c in lowercase(v.as_ref()) {
+            c.hash(state);
+        }
+    }
+}
+
+fn lowercase(s: &str) -> impl Iterator<Item = char> + '_ {
+    s.chars().flat_map(char::to_lowercase)
+}
+
+/// A type with a smallest and largest value, so that a range can cover every value of it.
+pub trait Bounded: Ord + Clone {
+    /// The smallest value.
+    const MIN: Self;
+    /// The largest value.
+    const MAX: Self;
+}
+
+macro_rules! impl_bounded {
+    ($($t:ty),*) => {
+        $(impl Bounded for $t {
+            const MIN: Self = <$t>::MIN;
+            const MAX: Self = <$t>::MAX;
+        })*
+    };
+}
+
+impl_bounded!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+impl Bounded for char {
+    const MIN: Self = '\0';
+    const MAX: Self = char::MAX;
+}
+
+impl Bounded for bool {
+    const MIN: Self = false;
+    const MAX: Self = true;
+}
+
+/// A tuple value that can be matched by its leading fields `P`, by covering every value of
+/// the remaining fields. Used by
+/// [`Index::lookup_prefix`][crate::index::Index::lookup_prefix].
+///
+/// A 3-tuple can be matched by its first field, or by a tuple of its first two fields.
+pub trait TuplePrefix<P>: Ord + Sized {
+    /// The range of values that start with `prefix`.
+    fn prefix_range(prefix: &P) -> RangeInclusive<Self>;
+}
+
+impl<A: Ord + Clone, B: Bounded> TuplePrefix<A> for (A, B) {
+    fn prefix_range(a: &A) -> RangeInclusive<Self> {
+        (a.clone(), B::MIN)..=(a.clone(), B::MAX)
+    }
+}
+
+impl<A: Ord + Clone, B: Bounded, C: Bounded> TuplePrefix<A> for (A, B, C) {
+    fn prefix_range(a: &A) -> RangeInclusive<Self> {
+        (a.clone(), B::MIN, C::MIN)..=(a.clone(), B::MAX, C::MAX)
+    }
+}
+
+impl<A: Ord + Clone, B: Ord + Clone, C: Bounded> TuplePrefix<(A, B)> for (A, B, C) {
+    fn prefix_range((a, b): &(A, B)) -> RangeInclusive<Self> {
+        (a.clone(), b.clone(), C::MIN)..=(a.clone(), b.clone(), C::MAX)
+    }
+}